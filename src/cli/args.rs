@@ -1,10 +1,15 @@
 use clap::{crate_authors, crate_version, App, AppSettings, Arg, ValueHint};
 use clap_generate::generate;
 use clap_generate::generators::{Bash, Elvish, Fish, PowerShell, Zsh};
-use music_organizer::FileOpType;
-use std::path::PathBuf;
+use music_organizer::{
+    ApePolicy, Articles, CheckRegistry, CollisionPolicy, DateKind, Extensions, FileOpType,
+    Filesystem, Mode, Normalization, OverwritePolicy, Resolutions, Template,
+};
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
+use crate::config::Config;
+
 const BIN_NAME: &str = "music-organizer";
 
 const BASH: &str = "bash";
@@ -13,6 +18,25 @@ const FISH: &str = "fish";
 const PWRSH: &str = "powershell";
 const ZSH: &str = "zsh";
 
+const APE_SYNC: &str = "sync";
+const APE_STRIP: &str = "strip";
+
+const DATE_RELEASE: &str = "release";
+const DATE_ORIGINAL: &str = "original";
+
+const COLLISION_SKIP: &str = "skip";
+const COLLISION_SUFFIX: &str = "suffix";
+const COLLISION_KEEP_BEST: &str = "keep-best";
+
+const OVERWRITE_NEVER: &str = "never";
+const OVERWRITE_IDENTICAL: &str = "identical";
+const OVERWRITE_NEWER: &str = "newer";
+const OVERWRITE_ALWAYS: &str = "always";
+
+const NORMALIZATION_NONE: &str = "none";
+
+const DISC_DIR_NAME: &str = "CD{disc}{discsubtitle? \" - {discsubtitle}\"}";
+
 pub struct Args {
     pub music_dir: PathBuf,
     pub output_dir: PathBuf,
@@ -21,10 +45,37 @@ pub struct Args {
     pub assume_yes: bool,
     pub dry_run: bool,
     pub no_check: bool,
+    pub checks: CheckRegistry,
     pub no_cleanup: bool,
+    pub ape_policy: ApePolicy,
+    pub mode: Mode,
+    pub extensions: Extensions,
+    pub year_prefix: Option<DateKind>,
+    pub sort_artist_dirs: bool,
+    pub articles: Option<Articles>,
+    pub compilation_dir: Option<String>,
+    pub template: Option<Template>,
+    pub disc_dirs: Option<Template>,
+    pub filesystem: Filesystem,
+    pub normalization: Option<Normalization>,
+    pub transliterate: bool,
+    pub infer_totals: bool,
+    /// The answers of the resolutions file and the record file.
+    pub resolutions: Resolutions,
+    /// The file interactive answers are added to.
+    pub record: Option<PathBuf>,
+    /// The answers that were already recorded.
+    pub recorded: Resolutions,
+    pub collisions: CollisionPolicy,
+    pub overwrite: OverwritePolicy,
+    pub replay_gain: bool,
+    pub replay_gain_all: bool,
 }
 
 pub fn parse_args() -> Args {
+    let mut checks = CheckRegistry::default();
+    let check_names: Vec<String> = checks.names().map(|n| n.to_owned()).collect();
+
     let mut app = App::new("music organizer")
         .setting(AppSettings::ColoredHelp)
         .setting(AppSettings::ColorAuto)
@@ -37,7 +88,7 @@ pub fn parse_args() -> Args {
                 .long("music-dir")
                 .about("The directory which will be searched for music files")
                 .takes_value(true)
-                .required_unless_present("generate-completion")
+                .required_unless_present_any(["generate-completion", "list-checks"])
                 .value_hint(ValueHint::DirPath),
         )
         .arg(
@@ -62,6 +113,24 @@ pub fn parse_args() -> Args {
                 .about("Don't check for inconsistencies")
                 .takes_value(false),
         )
+        .arg(
+            Arg::new("checks")
+                .long("checks")
+                .value_name("names")
+                .about("Only run these checks, separated by commas")
+                .takes_value(true)
+                .multiple(true)
+                .use_delimiter(true)
+                .possible_values(&check_names.iter().map(|n| n.as_str()).collect::<Vec<_>>())
+                .conflicts_with("nocheck"),
+        )
+        .arg(
+            Arg::new("list-checks")
+                .long("list-checks")
+                .about("Lists the available checks")
+                .conflicts_with("music-dir")
+                .takes_value(false),
+        )
         .arg(
             Arg::new("nocleanup")
                 .long("nocleanup")
@@ -83,6 +152,217 @@ pub fn parse_args() -> Args {
                 .takes_value(false)
                 .conflicts_with("assume-yes"),
         )
+        .arg(
+            Arg::new("audiobooks")
+                .short('a')
+                .long("audiobooks")
+                .about("Organize m4b/m4a audiobooks as Author/Series/NN - Title instead of music")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("audio-ext")
+                .long("audio-ext")
+                .value_name("ext")
+                .about("Additional audio file extensions, separated by commas")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_delimiter(true),
+        )
+        .arg(
+            Arg::new("image-ext")
+                .long("image-ext")
+                .value_name("ext")
+                .about("Additional image file extensions like webp, gif or bmp")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_delimiter(true),
+        )
+        .arg(
+            Arg::new("sidecar-ext")
+                .long("sidecar-ext")
+                .value_name("ext")
+                .about("Additional extensions of files that are moved along with the songs")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_delimiter(true),
+        )
+        .arg(
+            Arg::new("year-prefix")
+                .long("year-prefix")
+                .value_name("date")
+                .about("Prefix release directories with the year of the release or original date")
+                .takes_value(true)
+                .possible_values(&[DATE_RELEASE, DATE_ORIGINAL]),
+        )
+        .arg(
+            Arg::new("sort-artist-dirs")
+                .long("sort-artist-dirs")
+                .about("Name artist directories by their sort name, like 'Beatles, The'")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("move-articles")
+                .long("move-articles")
+                .value_name("lang")
+                .about("Move leading articles of these languages if an artist has no sort name")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_delimiter(true)
+                .possible_values(&Articles::builtin_languages().collect::<Vec<_>>())
+                .requires("sort-artist-dirs"),
+        )
+        .arg(
+            Arg::new("article")
+                .long("article")
+                .value_name("lang=article")
+                .about("Additional articles that are moved, for example 'en=Ye'")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_delimiter(true)
+                .requires("sort-artist-dirs"),
+        )
+        .arg(
+            Arg::new("compilation-dir")
+                .long("compilation-dir")
+                .value_name("dir")
+                .about(
+                    "Put compilations into this directory, like 'Various Artists', instead of \
+                     the directories of their release artists",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("template")
+                .short('t')
+                .long("template")
+                .value_name("template")
+                .about(
+                    "The path of songs relative to the output dir, like \
+                    '{artistdir}/{year? \"{year} - \"}{album}/{track:02} - {title}'",
+                )
+                .takes_value(true)
+                .conflicts_with("year-prefix"),
+        )
+        .arg(
+            Arg::new("disc-dirs")
+                .long("disc-dirs")
+                .about("Put the songs of multi-disc releases into a directory per disc")
+                .takes_value(false)
+                .conflicts_with("template"),
+        )
+        .arg(
+            Arg::new("disc-dir-name")
+                .long("disc-dir-name")
+                .value_name("template")
+                .about("The name of disc directories, defaults to 'CD{disc}'")
+                .takes_value(true)
+                .requires("disc-dirs"),
+        )
+        .arg(
+            Arg::new("filesystem")
+                .long("filesystem")
+                .value_name("fs")
+                .about("Sanitize names for the filesystem of the output dir, defaults to ntfs")
+                .takes_value(true)
+                .possible_values(&Filesystem::names().collect::<Vec<_>>()),
+        )
+        .arg(
+            Arg::new("normalize")
+                .long("normalize")
+                .value_name("form")
+                .about("The Unicode normalization form of names, defaults to nfc")
+                .takes_value(true)
+                .possible_values(
+                    &Normalization::names().chain([NORMALIZATION_NONE]).collect::<Vec<_>>(),
+                ),
+        )
+        .arg(
+            Arg::new("transliterate")
+                .long("transliterate")
+                .about("Convert names to ASCII, for example 'Björk' to 'Bjork'")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("collisions")
+                .long("collisions")
+                .value_name("policy")
+                .about("What to do with songs that would end up at the same path, defaults to skip")
+                .takes_value(true)
+                .possible_values(&[COLLISION_SKIP, COLLISION_SUFFIX, COLLISION_KEEP_BEST]),
+        )
+        .arg(
+            Arg::new("overwrite")
+                .long("overwrite")
+                .value_name("policy")
+                .about(
+                    "When to overwrite files that already exist at a new path, identical only \
+                    overwrites files with the same content, newer ones older than the source. \
+                    Defaults to never",
+                )
+                .takes_value(true)
+                .possible_values(&[
+                    OVERWRITE_NEVER,
+                    OVERWRITE_IDENTICAL,
+                    OVERWRITE_NEWER,
+                    OVERWRITE_ALWAYS,
+                ]),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("file")
+                .about("A TOML config file, defaults to ~/.config/music-organizer/config.toml")
+                .takes_value(true)
+                .value_hint(ValueHint::FilePath),
+        )
+        .arg(
+            Arg::new("resolutions")
+                .long("resolutions")
+                .value_name("file")
+                .about("A TOML file with answers to checks that are applied before asking")
+                .takes_value(true)
+                .value_hint(ValueHint::FilePath),
+        )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .value_name("file")
+                .about(
+                    "Add the answers to checks to a TOML file, which is also read like a \
+                     resolutions file",
+                )
+                .takes_value(true)
+                .value_hint(ValueHint::FilePath),
+        )
+        .arg(
+            Arg::new("infer-totals")
+                .long("infer-totals")
+                .about(
+                    "Fill in missing total tracks and discs from the highest numbers of a release",
+                )
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("replaygain")
+                .long("replaygain")
+                .about("Measure the loudness of releases that are missing ReplayGain tags")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("replaygain-all")
+                .long("replaygain-all")
+                .about("Measure the loudness of all releases and update their ReplayGain tags")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("ape-tags")
+                .long("ape-tags")
+                .value_name("policy")
+                .about("What to do with APEv2 tags found next to the ID3 tag of MP3 files")
+                .takes_value(true)
+                .possible_values(&[APE_SYNC, APE_STRIP])
+                .default_value(APE_SYNC),
+        )
         .arg(
             Arg::new("verbosity")
                 .short('v')
@@ -120,6 +400,13 @@ pub fn parse_args() -> Args {
         exit(0);
     }
 
+    if matches.is_present("list-checks") {
+        for (c, _) in checks.checks() {
+            println!("{:<18}{}", c.name(), c.description());
+        }
+        exit(0);
+    }
+
     let music_dir = {
         let dir = PathBuf::from(matches.value_of("music-dir").unwrap());
         match PathBuf::from(&dir).canonicalize() {
@@ -144,6 +431,133 @@ pub fn parse_args() -> Args {
         None => music_dir.clone(),
     };
 
+    let mut extensions = Extensions::default();
+    for e in matches.values_of("audio-ext").into_iter().flatten() {
+        Extensions::add(&mut extensions.audio, e);
+    }
+    for e in matches.values_of("image-ext").into_iter().flatten() {
+        Extensions::add(&mut extensions.image, e);
+    }
+    for e in matches.values_of("sidecar-ext").into_iter().flatten() {
+        Extensions::add(&mut extensions.sidecar, e);
+    }
+
+    let mut articles =
+        matches.values_of("move-articles").map(|l| Articles::builtin(&l.collect::<Vec<_>>()));
+    for a in matches.values_of("article").into_iter().flatten() {
+        let (language, article) = match a.split_once('=') {
+            Some((l, a)) => (l, a),
+            None => {
+                println!("Not a valid article, expected lang=article: {}", a);
+                exit(1)
+            }
+        };
+        articles.get_or_insert_with(|| Articles::builtin(&[])).add(language, article);
+    }
+
+    let config = match matches.value_of("config") {
+        Some(p) => match Config::read(p.as_ref()) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("Could not read config file {}:\n{}", p, e);
+                exit(1)
+            }
+        },
+        None => match Config::default_path().filter(|p| p.exists()) {
+            Some(p) => Config::read(&p).unwrap_or_else(|e| {
+                println!("Could not read config file {}:\n{}", p.display(), e);
+                exit(1)
+            }),
+            None => Config::default(),
+        },
+    };
+
+    let check_names: Option<Vec<&str>> = match matches.values_of("checks") {
+        Some(v) => Some(v.collect()),
+        None => config.checks.as_ref().map(|c| c.iter().map(|n| n.as_str()).collect()),
+    };
+    if let Some(names) = check_names {
+        if let Err(n) = checks.enable_only(&names) {
+            println!("Unknown check: {}", n);
+            exit(1)
+        }
+    }
+
+    let resolutions_file = matches
+        .value_of("resolutions")
+        .map(PathBuf::from)
+        .or(config.resolutions.map(PathBuf::from));
+    let read_resolutions = |p: &Path| {
+        Resolutions::read(p).unwrap_or_else(|e| {
+            println!("Could not read resolutions file {}:\n{}", p.display(), e);
+            exit(1)
+        })
+    };
+    let record = matches.value_of("record").map(PathBuf::from);
+    let recorded = match &record {
+        Some(p) if p.exists() => read_resolutions(p),
+        _ => Resolutions::default(),
+    };
+    let mut resolutions = match &resolutions_file {
+        Some(p) => read_resolutions(p),
+        None => Resolutions::default(),
+    };
+    resolutions.merge(recorded.clone());
+
+    let template = matches.value_of("template").map(|t| t.to_owned()).or(config.template);
+    let template = template.map(|t| parse_template(&t));
+
+    let disc_dirs = match matches.is_present("disc-dirs") || config.disc_dirs {
+        true => {
+            let name = matches.value_of("disc-dir-name").map(|n| n.to_owned());
+            let name = name.or(config.disc_dir_name).unwrap_or_else(|| DISC_DIR_NAME.to_owned());
+            Some(parse_template(&name))
+        }
+        false => None,
+    };
+
+    let filesystem = matches.value_of("filesystem").map(|f| f.to_owned()).or(config.filesystem);
+    let filesystem = match filesystem {
+        Some(f) => Filesystem::from_name(&f).unwrap_or_else(|| {
+            println!("Not a valid filesystem: {}", f);
+            exit(1)
+        }),
+        None => Filesystem::default(),
+    };
+
+    let normalization = matches.value_of("normalize").map(|n| n.to_owned()).or(config.normalize);
+    let normalization = match normalization.as_deref() {
+        Some(NORMALIZATION_NONE) => None,
+        Some(n) => Some(Normalization::from_name(n).unwrap_or_else(|| {
+            println!("Not a valid normalization form: {}", n);
+            exit(1)
+        })),
+        None => Some(Normalization::default()),
+    };
+
+    let collisions = matches.value_of("collisions").map(|c| c.to_owned()).or(config.collisions);
+    let collisions = match collisions.as_deref() {
+        Some(COLLISION_SKIP) | None => CollisionPolicy::Skip,
+        Some(COLLISION_SUFFIX) => CollisionPolicy::Suffix,
+        Some(COLLISION_KEEP_BEST) => CollisionPolicy::KeepBest,
+        Some(c) => {
+            println!("Not a valid collision policy: {}", c);
+            exit(1)
+        }
+    };
+
+    let overwrite = matches.value_of("overwrite").map(|o| o.to_owned()).or(config.overwrite);
+    let overwrite = match overwrite.as_deref() {
+        Some(OVERWRITE_NEVER) | None => OverwritePolicy::Never,
+        Some(OVERWRITE_IDENTICAL) => OverwritePolicy::IfIdentical,
+        Some(OVERWRITE_NEWER) => OverwritePolicy::IfNewer,
+        Some(OVERWRITE_ALWAYS) => OverwritePolicy::Always,
+        Some(o) => {
+            println!("Not a valid overwrite policy: {}", o);
+            exit(1)
+        }
+    };
+
     Args {
         music_dir,
         output_dir,
@@ -154,7 +568,50 @@ pub fn parse_args() -> Args {
         },
         assume_yes: matches.is_present("assume-yes"),
         no_check: matches.is_present("nocheck"),
+        checks,
         no_cleanup: matches.is_present("nocleanup"),
         dry_run: matches.is_present("dryrun"),
+        ape_policy: match matches.value_of("ape-tags") {
+            Some(APE_STRIP) => ApePolicy::Strip,
+            _ => ApePolicy::Sync,
+        },
+        mode: match matches.is_present("audiobooks") {
+            true => Mode::Audiobook,
+            false => Mode::Music,
+        },
+        extensions,
+        year_prefix: match matches.value_of("year-prefix") {
+            Some(DATE_RELEASE) => Some(DateKind::Release),
+            Some(DATE_ORIGINAL) => Some(DateKind::Original),
+            _ => None,
+        },
+        sort_artist_dirs: matches.is_present("sort-artist-dirs"),
+        articles,
+        compilation_dir: matches.value_of("compilation-dir").map(|d| d.to_owned()),
+        template,
+        disc_dirs,
+        filesystem,
+        normalization,
+        transliterate: matches.is_present("transliterate") || config.transliterate,
+        infer_totals: matches.is_present("infer-totals") || config.infer_totals,
+        resolutions,
+        record,
+        recorded,
+        collisions,
+        overwrite,
+        replay_gain: matches.is_present("replaygain") || matches.is_present("replaygain-all"),
+        replay_gain_all: matches.is_present("replaygain-all"),
+    }
+}
+
+fn parse_template(template: &str) -> Template {
+    match Template::parse(template) {
+        Ok(t) => t,
+        Err(e) => {
+            println!("Not a valid template: {}", e.message);
+            println!("{}", template);
+            println!("{:>1$}", "^", e.column);
+            exit(1)
+        }
     }
 }
@@ -0,0 +1,37 @@
+use serde::Deserialize;
+use std::error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Options that are read from a TOML file, values passed on the command line take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub template: Option<String>,
+    pub disc_dirs: bool,
+    pub disc_dir_name: Option<String>,
+    pub filesystem: Option<String>,
+    pub normalize: Option<String>,
+    pub transliterate: bool,
+    pub collisions: Option<String>,
+    pub overwrite: Option<String>,
+    pub checks: Option<Vec<String>>,
+    pub infer_totals: bool,
+    pub resolutions: Option<String>,
+}
+
+impl Config {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn error::Error>> {
+        let content = fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// `$XDG_CONFIG_HOME/music-organizer/config.toml`, which defaults to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = match std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+            Some(d) => PathBuf::from(d),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("music-organizer").join("config.toml"))
+    }
+}
@@ -1,14 +1,18 @@
 use colored::Colorize;
 use music_organizer::{
-    Changes, Checks, Cleanup, FileOpType, MusicIndex, Song, SongOperation, TagUpdate, Value,
+    ApePolicy, ChangeOptions, Changes, Checks, Cleanup, CollisionPolicy, Conflict, Date, Field,
+    FileOpType, Finding, Format, Mode, MusicIndex, Proposal, ReplayGain, Rule, Severity, Song,
+    SongOperation, TagUpdate, Value,
 };
 use std::io::Write;
 use std::path::Path;
 use std::process::exit;
+use std::str::FromStr;
 
 use crate::args::Args;
 
 mod args;
+mod config;
 
 const VERBOSE: usize = 2;
 
@@ -23,7 +27,28 @@ fn main() {
         assume_yes,
         dry_run,
         no_check,
+        checks: registry,
         no_cleanup,
+        ape_policy,
+        mode,
+        extensions,
+        year_prefix,
+        sort_artist_dirs,
+        articles,
+        compilation_dir,
+        template,
+        disc_dirs,
+        filesystem,
+        normalization,
+        transliterate,
+        infer_totals,
+        resolutions,
+        record,
+        recorded,
+        collisions,
+        overwrite,
+        replay_gain,
+        replay_gain_all,
     } = args::parse_args();
 
     let (op_type_sim_pres, op_type_pres_prog, op_type_sim_past) = match op_type {
@@ -36,11 +61,25 @@ fn main() {
     println!("# Indexing");
     println!("============================================================");
     let mut index = MusicIndex::from(music_dir.clone());
+    index.mode = mode;
+    index.extensions = extensions;
 
     let mut i = 1;
-    index.read(&mut |p| {
+    index.read(&mut |p, rule| {
+        let rule = match rule {
+            Rule::Audio(e) => format!("audio .{}", e),
+            Rule::Image(e) => format!("image .{}", e),
+            Rule::Sidecar(e) => format!("sidecar .{}", e),
+            Rule::Content(f) => format!("audio {}", f.name()),
+            Rule::Unmatched => "ignored".to_string(),
+        };
         print_verbose(
-            &format!("{} {}", (i + 1).to_string().blue(), strip_dir(&p, &music_dir).green()),
+            &format!(
+                "{} {} {}",
+                (i + 1).to_string().blue(),
+                strip_dir(&p, &music_dir).green(),
+                rule.yellow()
+            ),
             verbosity >= 2,
         );
         i += 1;
@@ -48,22 +87,102 @@ fn main() {
     reset_print_verbose();
     println!();
 
-    let checks = Checks::from(&index);
+    if mode == Mode::Audiobook && verbosity >= VERBOSE {
+        for s in index.songs.iter() {
+            println!("{} {} chapters", s.release.green(), s.chapters.len().to_string().blue());
+        }
+        println!();
+    }
+
+    let mut checks = Checks::from(&index);
+    let mut recorded = recorded;
+    checks.resolutions = resolutions;
     if !no_check {
         println!("============================================================");
         println!("# Checking");
         println!("============================================================");
 
-        //changes.check_inconsitent_release_artists(inconsitent_artists_dialog);
-        //changes.check_inconsitent_albums(inconsitent_albums_dialog);
-        //changes.check_inconsitent_total_tracks(inconsitent_total_tracks_dialog);
-        //changes.check_inconsitent_total_discs(inconsitent_total_discs_dialog);
+        checks.check_extensions(|s, f| wrong_extension_dialog(&music_dir, s, f, assume_yes));
+        checks.update();
+        checks.run(&registry, |_, finding| {
+            let proposal = finding_dialog(finding, assume_yes);
+            if !assume_yes {
+                recorded.record(finding, proposal.as_ref());
+            }
+            proposal
+        });
+        if infer_totals || checks.resolutions.infer_totals {
+            checks.infer_totals();
+        }
+        println!();
+
+        if let Some(path) = &record {
+            match recorded.write(path) {
+                Ok(()) => println!("recorded answers to {}\n", path.display()),
+                Err(e) => {
+                    println!("{}\n", format!("could not write {}:\n{}", path.display(), e).red())
+                }
+            }
+        }
+    }
+
+    if replay_gain {
+        println!("============================================================");
+        println!("# Analyzing loudness");
+        println!("============================================================");
+        if no_check {
+            checks.update();
+        }
+
+        let mut i = 1;
+        checks.analyze_loudness(replay_gain_all, |s, r| {
+            let result = match r {
+                Ok(l) => match l.replay_gain() {
+                    Some(g) => g.gain_str().green(),
+                    None => "silent".yellow(),
+                },
+                Err(e) => e.to_string().red(),
+            };
+            print_verbose(
+                &format!(
+                    "{} {} {}",
+                    i.to_string().blue(),
+                    strip_dir(&s.path, &music_dir).green(),
+                    result
+                ),
+                verbosity >= 2 || r.is_err(),
+            );
+            i += 1;
+        });
+        reset_print_verbose();
         println!();
     }
 
-    let changes = Changes::generate(checks, &output_dir);
+    if ape_policy == ApePolicy::Strip {
+        checks.strip_ape_tags();
+    }
+
+    let options = ChangeOptions {
+        year_prefix,
+        sort_artist_dirs,
+        articles,
+        compilation_dir,
+        template,
+        disc_dirs,
+        filesystem,
+        normalization,
+        transliterate,
+        collisions,
+        overwrite,
+        op_type,
+    };
+    let changes = Changes::generate(checks, &output_dir, &options);
 
-    if changes.dir_creations.is_empty() && changes.song_operations.is_empty() {
+    if changes.dir_creations.is_empty()
+        && changes.song_operations.is_empty()
+        && changes.conflicts.is_empty()
+        && changes.existing_files.is_empty()
+    {
         println!("{}", "nothing to do".green());
     } else {
         println!("============================================================");
@@ -99,6 +218,40 @@ fn main() {
                 }
                 println!();
             }
+            if !changes.conflicts.is_empty() {
+                println!("conflicts:");
+                for (i, c) in changes.conflicts.iter().enumerate() {
+                    println!(
+                        "{} {}",
+                        (i + 1).to_string().blue(),
+                        format_conflict(&music_dir, &output_dir, c)
+                    );
+                }
+                println!();
+            }
+            if !changes.existing_files.is_empty() {
+                println!("existing files:");
+                for (i, e) in changes.existing_files.iter().enumerate() {
+                    let path = strip_dir(&e.path, &output_dir).yellow();
+                    let source = strip_dir(e.source, &music_dir);
+                    match e.overwrite {
+                        true => println!(
+                            "{} {} {} with {}",
+                            (i + 1).to_string().blue(),
+                            "overwrite".red(),
+                            path,
+                            source
+                        ),
+                        false => println!(
+                            "{} skip {}, {} already exists",
+                            (i + 1).to_string().blue(),
+                            source,
+                            path
+                        ),
+                    }
+                }
+                println!();
+            }
             if !changes.file_operations.is_empty() {
                 println!("others:");
                 for (i, f) in changes.file_operations.iter().enumerate() {
@@ -311,271 +464,6 @@ fn main() {
     println!("{}", "done".green());
 }
 
-//fn inconsitent_artists_dialog(
-//    index: &MusicIndex,
-//    a: &ReleaseArtists,
-//    b: &ReleaseArtists,
-//) -> Option<String> {
-//    fn print(index: &MusicIndex, artist: &ReleaseArtists) {
-//        println!("{}:", artist.name.as_str().yellow());
-//        for (i, al) in artist.releases.iter().enumerate() {
-//            if i == 10 {
-//                println!("   {}", "...".green());
-//                break;
-//            }
-//            println!("   {}:", al.name);
-//            for (j, s) in al.songs.iter().map(|&si| &index.songs[si]).enumerate() {
-//                if i >= 4 || j == 3 {
-//                    println!("      {}", "...".green());
-//                    break;
-//                } else {
-//                    println!(
-//                        "      {:02} - {} - {}",
-//                        s.track_number.unwrap_or(0),
-//                        s.artist.opt_str(),
-//                        s.title.opt_str()
-//                    );
-//                }
-//            }
-//        }
-//    }
-//    println!("These two artists are named similarly:");
-//    print(index, a);
-//    println!();
-//    print(index, b);
-//    println!();
-//
-//    let index = input_options_loop(
-//        "",
-//        &[
-//            "don't do anything",
-//            "rename first to second",
-//            "rename second to first",
-//            "enter new name",
-//        ],
-//    );
-//
-//    match index {
-//        0 => return None,
-//        1 => {
-//            println!("renaming first to second");
-//            return Some(a.name.clone());
-//        }
-//        2 => {
-//            println!("renaming second to first");
-//            return Some(b.name.clone());
-//        }
-//        3 => loop {
-//            let new_name = input_loop("enter new name:", |_| true);
-//            let msg = format!("new name: '{}'", new_name);
-//
-//            let i = input_options_loop(&msg, &["ok", "reenter name", "dismiss"]);
-//
-//            match i {
-//                0 => return Some(new_name),
-//                1 => continue,
-//                _ => return None,
-//            }
-//        },
-//        _ => unreachable!(),
-//    }
-//}
-//
-//fn inconsitent_albums_dialog(
-//    index: &MusicIndex,
-//    artist: &ReleaseArtists,
-//    a: &Release,
-//    b: &Release,
-//) -> Option<String> {
-//    fn print(index: &MusicIndex, album: &Release) {
-//        println!("   {}:", album.name.as_str().yellow());
-//        for s in album.songs.iter().map(|&si| &index.songs[si]) {
-//            println!(
-//                "      {:02} - {} - {}",
-//                s.track_number.unwrap_or(0),
-//                s.artist.opt_str(),
-//                s.title.opt_str()
-//            );
-//        }
-//    }
-//    println!("These two albums are named similarly:");
-//    println!("{}:", artist.name);
-//    print(index, a);
-//    println!();
-//    print(index, b);
-//    println!();
-//
-//    let index = input_options_loop(
-//        "",
-//        &[
-//            "don't do anything",
-//            "rename first to second",
-//            "rename second to first",
-//            "enter new name",
-//        ],
-//    );
-//
-//    match index {
-//        0 => return None,
-//        1 => {
-//            println!("renaming first to second");
-//            return Some(a.name.clone());
-//        }
-//        2 => {
-//            println!("renaming second to first");
-//            return Some(b.name.clone());
-//        }
-//        3 => loop {
-//            let new_name = input_loop("enter new name:", |_| true);
-//            let msg = format!("new name: '{}'", new_name);
-//
-//            let i = input_options_loop(&msg, &["ok", "reenter name", "dismiss"]);
-//
-//            match i {
-//                0 => return Some(new_name),
-//                1 => continue,
-//                2 => return None,
-//                _ => unreachable!(),
-//            }
-//        },
-//        _ => unreachable!(),
-//    }
-//}
-//
-//fn inconsitent_total_tracks_dialog(
-//    artist: &ReleaseArtists,
-//    album: &Release,
-//    total_tracks: Vec<(Vec<&Song>, Option<u16>)>,
-//) -> Option<u16> {
-//    let msg = format!(
-//        "{} - {} this album has different total tracks values:",
-//        artist.name.as_str().yellow(),
-//        album.name.as_str().yellow(),
-//    );
-//    let mut options = vec!["don't do anything", "remove the value", "enter a new value"];
-//
-//    let values: Vec<String> = total_tracks
-//        .iter()
-//        .map(|(songs, tt)| {
-//            let mut tt_str = match tt {
-//                Some(n) => format!("{:02}:   ", n).yellow().to_string(),
-//                None => "none: ".yellow().to_string(),
-//            };
-//            let mut iter = songs.iter();
-//
-//            let s = iter.next().unwrap();
-//            tt_str.push_str(&format!(
-//                "{}|{:02} - {} - {}",
-//                &s.disc_number.unwrap_or(0),
-//                &s.track_number.unwrap_or(0),
-//                &s.artist.opt_str(),
-//                &s.title.opt_str()
-//            ));
-//
-//            for s in iter {
-//                tt_str.push_str(&format!(
-//                    "\n      {}|{:02} - {} - {}",
-//                    &s.disc_number.unwrap_or(0),
-//                    &s.track_number.unwrap_or(0),
-//                    &s.artist.opt_str(),
-//                    &s.title.opt_str()
-//                ));
-//            }
-//
-//            tt_str
-//        })
-//        .collect();
-//
-//    options.extend(values.iter().map(|s| s.as_str()));
-//
-//    let i = input_options_loop(&msg, &options);
-//
-//    match i {
-//        0 => return None,
-//        1 => return Some(0),
-//        2 => loop {
-//            let new_value = input_loop_parse::<u16>("enter a new value:");
-//            let msg = format!("new value: '{}'", new_value);
-//
-//            let i = input_options_loop(&msg, &["ok", "reenter value", "dismiss"]);
-//
-//            match i {
-//                0 => return Some(new_value),
-//                1 => continue,
-//                _ => return None,
-//            }
-//        },
-//        _ => return total_tracks[i - 3].1,
-//    }
-//}
-//
-//fn inconsitent_total_discs_dialog(
-//    artist: &ReleaseArtists,
-//    album: &Release,
-//    total_discs: Vec<(Vec<&Song>, Option<u16>)>,
-//) -> Option<u16> {
-//    let msg = format!(
-//        "{} - {} this album has different total discs values:",
-//        artist.name.as_str().yellow(),
-//        album.name.as_str().yellow(),
-//    );
-//    let mut options = vec!["don't do anything", "remove the value", "enter a new value"];
-//
-//    let values: Vec<String> = total_discs
-//        .iter()
-//        .map(|(songs, tt)| {
-//            let mut tt_str = match tt {
-//                Some(n) => format!("{}:    ", n.to_string().yellow()),
-//                None => "none: ".yellow().to_string(),
-//            };
-//            let mut iter = songs.iter();
-//
-//            let s = iter.next().unwrap();
-//            tt_str.push_str(&format!(
-//                "{}|{:02} - {} - {}",
-//                &s.disc_number.unwrap_or(0),
-//                &s.track_number.unwrap_or(0),
-//                &s.artist.opt_str(),
-//                &s.title.opt_str(),
-//            ));
-//
-//            for s in iter {
-//                tt_str.push_str(&format!(
-//                    "\n      {}|{:02} - {} - {}",
-//                    &s.disc_number.unwrap_or(0),
-//                    &s.track_number.unwrap_or(0),
-//                    &s.artist.opt_str(),
-//                    &s.title.opt_str(),
-//                ));
-//            }
-//
-//            tt_str
-//        })
-//        .collect();
-//
-//    options.extend(values.iter().map(|s| s.as_str()));
-//
-//    let i = input_options_loop(&msg, &options);
-//
-//    match i {
-//        0 => None,
-//        1 => Some(0),
-//        2 => loop {
-//            let new_value = input_loop_parse::<u16>("enter a new value:");
-//            let msg = format!("new value: '{}'", new_value);
-//
-//            let i = input_options_loop(&msg, &["ok", "reenter value", "dismiss"]);
-//
-//            match i {
-//                0 => return Some(new_value),
-//                1 => continue,
-//                _ => return None,
-//            }
-//        },
-//        _ => return total_discs[i - 3].1,
-//    }
-//}
-
 fn format_song_op(
     music_dir: &Path,
     output_dir: &Path,
@@ -642,69 +530,122 @@ fn format_file_op(
     }
 }
 
-fn format_tag_update(s: &Song, u: &TagUpdate, _verbosity: usize) -> String {
-    let mut string = String::new();
-
-    if let Some(s) = format_string_vec("release artists", &s.release_artists, &u.release_artists) {
-        string.push_str(&s);
-    }
-    if let Some(s) = format_string_vec("artists", &s.artists, &u.artists) {
-        string.push_str(&s);
-    }
-    if let Some(s) = format_string("release", &s.release, &u.release) {
-        string.push_str(&s);
-    }
-    if let Some(s) = format_string("title", &s.title, &u.title) {
-        string.push_str(&s);
-    }
-    if let Some(s) = format_u16("track number", s.track_number, u.track_number) {
-        string.push_str(&s);
-    }
-    if let Some(s) = format_u16("total tracks", s.total_tracks, u.total_tracks) {
-        string.push_str(&s);
-    }
-    if let Some(s) = format_u16("disc number", s.disc_number, u.track_number) {
-        string.push_str(&s);
+fn format_conflict(music_dir: &Path, output_dir: &Path, conflict: &Conflict) -> String {
+    let mut s = format!(
+        "{} songs would end up at {}",
+        conflict.songs.len(),
+        strip_dir(&conflict.path, output_dir).yellow()
+    );
+    for (i, song) in conflict.songs.iter().enumerate() {
+        let resolution = match (i, conflict.policy) {
+            (0, _) => "keep".green(),
+            (_, CollisionPolicy::Suffix) => "suffix".yellow(),
+            (_, CollisionPolicy::KeepBest) if song.path == conflict.path => "replace".red(),
+            (_, CollisionPolicy::Skip) | (_, CollisionPolicy::KeepBest) => "skip".red(),
+        };
+        s.push_str(&format!("\n    {} {}", resolution, strip_dir(&song.path, music_dir)));
+        if conflict.policy == CollisionPolicy::KeepBest {
+            s.push_str(&format!(" ({})", song.properties));
+        }
     }
-    if let Some(s) = format_u16("total discs", s.total_discs, u.total_discs) {
-        string.push_str(&s);
+    s
+}
+
+fn format_tag_update(s: &Song, u: &TagUpdate, _verbosity: usize) -> String {
+    let lines = [
+        format_string_vec("release artists", &s.release_artists, &u.release_artists),
+        format_string_vec("artists", &s.artists, &u.artists),
+        format_string("release", &s.release, &u.release),
+        format_string("title", &s.title, &u.title),
+        format_opt_string(
+            "release artists sort",
+            s.release_artists_sort.as_deref(),
+            &u.release_artists_sort,
+        ),
+        format_opt_string("artists sort", s.artists_sort.as_deref(), &u.artists_sort),
+        format_opt_string("release sort", s.release_sort.as_deref(), &u.release_sort),
+        format_opt_string("title sort", s.title_sort.as_deref(), &u.title_sort),
+        format_u16("track number", s.track_number, u.track_number),
+        format_u16("total tracks", s.total_tracks, u.total_tracks),
+        format_u16("disc number", s.disc_number, u.disc_number),
+        format_u16("total discs", s.total_discs, u.total_discs),
+        format_opt_string("disc subtitle", s.disc_subtitle.as_deref(), &u.disc_subtitle),
+        format_date("release date", s.release_date, u.release_date),
+        format_date("original date", s.original_date, u.original_date),
+        format_gain("track gain", s.track_gain, u.track_gain),
+        format_gain("release gain", s.release_gain, u.release_gain),
+        format_opt_string("recording id", s.mb_recording_id.as_deref(), &u.mb_recording_id),
+        format_opt_string("release id", s.mb_release_id.as_deref(), &u.mb_release_id),
+        format_opt_string(
+            "release group id",
+            s.mb_release_group_id.as_deref(),
+            &u.mb_release_group_id,
+        ),
+        format_string_vec("artist ids", &s.mb_artist_ids, &u.mb_artist_ids),
+        format_string_vec("release artist ids", &s.mb_release_artist_ids, &u.mb_release_artist_ids),
+        format_string_vec("genres", &s.genres, &u.genres),
+        format_opt_string("composer", s.composer.as_deref(), &u.composer),
+        format_opt_string("conductor", s.conductor.as_deref(), &u.conductor),
+        format_opt_string("comment", s.comment.as_deref(), &u.comment),
+        format_opt_string("grouping", s.grouping.as_deref(), &u.grouping),
+        format_flag("compilation", s.compilation, u.compilation),
+        format_opt_string("narrator", s.narrator.as_deref(), &u.narrator),
+    ];
+
+    let mut lines: Vec<String> = lines.iter().flatten().cloned().collect();
+    if s.has_ape_tag && u.ape_policy == ApePolicy::Strip {
+        lines.push(format!("remove {}", "APEv2 tag".red()));
     }
 
-    string
+    lines.join("\n")
 }
 
 fn format_u16(name: &str, old: Option<u16>, new: Value<u16>) -> Option<String> {
-    match (old, new) {
-        (Some(old), Value::Update(new)) => Some(format!(
-            "change {}: {} to {}",
-            name,
-            old.to_string().yellow(),
-            new.to_string().green()
-        )),
-        (None, Value::Update(new)) => Some(format!("add {}: {}", name, new.to_string().green())),
-        (Some(old), Value::Remove) => Some(format!("remove {}: {}", name, old.to_string().red())),
-        _ => None,
-    }
+    format_shown(name, old.as_ref(), &new, u16::to_string)
+}
+
+fn format_date(name: &str, old: Option<Date>, new: Value<Date>) -> Option<String> {
+    format_shown(name, old.as_ref(), &new, Date::to_string)
+}
+
+fn format_gain(name: &str, old: Option<ReplayGain>, new: Value<ReplayGain>) -> Option<String> {
+    format_shown(name, old.as_ref(), &new, ReplayGain::gain_str)
 }
 
 fn format_string(name: &str, old: &str, new: &Value<String>) -> Option<String> {
-    match new {
-        Value::Update(new) => Some(format!("change {}: {} to {}", name, old.yellow(), new.green())),
-        Value::Remove => Some(format!("remove {}: {}", name, old.red())),
-        Value::Unchanged => None,
-    }
+    format_opt_string(name, Some(old), new)
 }
 
 fn format_string_vec(name: &str, old: &Vec<String>, new: &Value<Vec<String>>) -> Option<String> {
-    match new {
-        Value::Update(new) => Some(format!(
-            "change {}: {} to {}",
-            name,
-            old.join(", ").yellow(),
-            new.join(", ").green()
-        )),
-        Value::Remove => Some(format!("remove {}: {}", name, old.join(", ").red())),
-        Value::Unchanged => None,
+    format_shown(name, Some(old), new, |v| v.join(", "))
+}
+
+/// Formats the change of a value that is shown as `show` returns it.
+fn format_shown<T>(
+    name: &str,
+    old: Option<&T>,
+    new: &Value<T>,
+    show: impl Fn(&T) -> String,
+) -> Option<String> {
+    format_opt_string(name, old.map(&show).as_deref(), &new.map_ref(show))
+}
+
+fn format_opt_string(name: &str, old: Option<&str>, new: &Value<String>) -> Option<String> {
+    match (old, new) {
+        (Some(old), Value::Update(new)) => {
+            Some(format!("change {}: {} to {}", name, old.yellow(), new.green()))
+        }
+        (None, Value::Update(new)) => Some(format!("add {}: {}", name, new.green())),
+        (Some(old), Value::Remove) => Some(format!("remove {}: {}", name, old.red())),
+        _ => None,
+    }
+}
+
+fn format_flag(name: &str, old: bool, new: Value<bool>) -> Option<String> {
+    match (old, new) {
+        (false, Value::Update(true)) => Some(format!("flag as {}", name.green())),
+        (true, Value::Update(false) | Value::Remove) => Some(format!("unflag {}", name.red())),
+        _ => None,
     }
 }
 
@@ -735,58 +676,162 @@ fn reset_print_verbose() {
     }
 }
 
-//fn input_loop(str: &str, predicate: fn(&str) -> bool) -> String {
-//    loop {
-//        println!("{}", str);
-//        let mut input = String::new();
-//
-//        match std::io::stdin().read_line(&mut input) {
-//            Ok(_) => {
-//                input.pop();
-//                if predicate(&input) {
-//                    return input;
-//                }
-//            }
-//            Err(e) => println!("error:\n {}", e),
-//        }
-//    }
-//}
-//
-//fn input_loop_parse<T: FromStr + Default>(str: &str) -> T {
-//    input_loop(str, |v| v.parse::<T>().is_ok()).parse::<T>().unwrap_or_else(|_| unreachable!())
-//    // Can't use unwrap because FromStr::Err does not neccesarily implement Debug
-//}
-//
-//fn input_options_loop(str: &str, options: &[&str]) -> usize {
-//    loop {
-//        if !str.is_empty() {
-//            println!("{}", str);
-//        }
-//        let mut input = String::with_capacity(2);
+fn input_loop(str: &str, predicate: fn(&str) -> bool) -> String {
+    loop {
+        println!("{}", str);
+        let mut input = String::new();
+
+        match std::io::stdin().read_line(&mut input) {
+            Ok(_) => {
+                input.pop();
+                if predicate(&input) {
+                    return input;
+                }
+            }
+            Err(e) => println!("error:\n {}", e),
+        }
+    }
+}
 //
-//        for (i, s) in options.iter().enumerate() {
-//            if options.len() < 10 {
-//                println!("[{}] {}", i, s.replace("\n", "\n    "));
-//            } else {
-//                println!("[{:02}] {}", i, s.replace("\n", "\n     "));
-//            }
-//        }
+fn input_loop_parse<T: FromStr + Default>(str: &str) -> T {
+    input_loop(str, |v| v.parse::<T>().is_ok()).parse::<T>().unwrap_or_else(|_| unreachable!())
+    // Can't use unwrap because FromStr::Err does not neccesarily implement Debug
+}
 //
-//        match std::io::stdin().read_line(&mut input) {
-//            Ok(_) => match usize::from_str(input.trim_matches('\n')) {
-//                Ok(i) => {
-//                    if i < options.len() {
-//                        return i;
-//                    } else {
-//                        println!("invalid input")
-//                    }
-//                }
-//                Err(_) => println!("invalid input"),
-//            },
-//            Err(e) => println!("error:\n {}", e),
-//        }
-//    }
-//}
+fn input_options_loop(str: &str, options: &[&str]) -> usize {
+    loop {
+        if !str.is_empty() {
+            println!("{}", str);
+        }
+        let mut input = String::with_capacity(2);
+
+        for (i, s) in options.iter().enumerate() {
+            if options.len() < 10 {
+                println!("[{}] {}", i, s.replace("\n", "\n    "));
+            } else {
+                println!("[{:02}] {}", i, s.replace("\n", "\n     "));
+            }
+        }
+
+        match std::io::stdin().read_line(&mut input) {
+            Ok(_) => match usize::from_str(input.trim_matches('\n')) {
+                Ok(i) => {
+                    if i < options.len() {
+                        return i;
+                    } else {
+                        println!("invalid input")
+                    }
+                }
+                Err(_) => println!("invalid input"),
+            },
+            Err(e) => println!("error:\n {}", e),
+        }
+    }
+}
+
+fn wrong_extension_dialog(music_dir: &Path, song: &Song, format: Format, assume_yes: bool) -> bool {
+    println!("{} is a {} file", strip_dir(&song.path, music_dir).yellow(), format.name().green());
+    if assume_yes {
+        return true;
+    }
+
+    input_confirmation_loop(&format!("change the extension to .{}", format.extensions()[0]))
+}
+
+fn finding_dialog<'a>(finding: &Finding<'a>, assume_yes: bool) -> Option<Proposal<'a>> {
+    let severity = match finding.severity {
+        Severity::Info => finding.severity.name().blue(),
+        Severity::Warning => finding.severity.name().yellow(),
+        Severity::Error => finding.severity.name().red(),
+    };
+    println!("{} {}", severity, finding.message);
+    for d in finding.details.iter() {
+        println!("   {}", d);
+    }
+    println!();
+    if assume_yes {
+        // Only findings that can be resolved in one way are resolved, heuristics are left alone
+        return match (finding.severity, finding.field, &finding.proposals[..]) {
+            (Severity::Info, _, _) => None,
+            (_, None, [p]) => Some(p.clone()),
+            _ => None,
+        };
+    }
+    if finding.proposals.is_empty() && finding.field.is_none() {
+        return None;
+    }
+
+    let (enter, remove) = match finding.field {
+        Some(Field::ReleaseArtists) => (Some("enter new names"), false),
+        Some(Field::Release) => (Some("enter a new name"), false),
+        Some(Field::Date(_)) => (Some("enter a date"), true),
+        Some(Field::TotalTracks | Field::TotalDiscs) => (Some("enter a value"), true),
+        None => (None, false),
+    };
+    let mut options = vec!["don't do anything"];
+    options.extend(finding.proposals.iter().map(|p| p.description.as_str()));
+    options.extend(enter);
+    if remove {
+        options.push("remove all");
+    }
+
+    // Entering and removing values is only offered for findings about a field
+    let proposals = finding.proposals.len();
+    match input_options_loop("", &options) {
+        0 => None,
+        i if i <= proposals => Some(finding.proposals[i - 1].clone()),
+        i if i == proposals + 1 => {
+            let (description, update) = enter_value(finding.field.unwrap());
+            Some(Proposal::new(description, &finding.songs, update))
+        }
+        _ => {
+            let mut update = TagUpdate::default();
+            match finding.field.unwrap() {
+                Field::Date(kind) => *update.date_mut(kind) = Value::Remove,
+                Field::TotalTracks => update.total_tracks = Value::Remove,
+                Field::TotalDiscs => update.total_discs = Value::Remove,
+                Field::ReleaseArtists | Field::Release => unreachable!(),
+            }
+            Some(Proposal::new("remove all".to_owned(), &finding.songs, update))
+        }
+    }
+}
+
+fn enter_value(field: Field) -> (String, TagUpdate) {
+    let mut update = TagUpdate::default();
+    let description = match field {
+        Field::ReleaseArtists => {
+            let names = input_loop("enter the new names, separated by ';':", |n| {
+                n.split(';').all(|n| !n.trim().is_empty())
+            });
+            let names: Vec<String> = names.split(';').map(|n| n.trim().to_owned()).collect();
+            let description = format!("rename all to {}", names.join(", "));
+            update.release_artists = Value::Update(names);
+            description
+        }
+        Field::Release => {
+            let name = input_loop("enter the new name:", |n| !n.trim().is_empty());
+            let description = format!("merge into {}", name.trim());
+            update.release = Value::Update(name.trim().to_owned());
+            description
+        }
+        Field::Date(kind) => {
+            let date = input_loop("enter a date (YYYY[-MM[-DD]]):", |d| Date::parse(d).is_some());
+            let date = Date::parse(&date).unwrap();
+            *update.date_mut(kind) = Value::Update(date);
+            format!("set all to {}", date)
+        }
+        Field::TotalTracks | Field::TotalDiscs => {
+            let total: u16 = input_loop_parse("enter a value:");
+            match field {
+                Field::TotalTracks => update.total_tracks = Value::Update(total),
+                _ => update.total_discs = Value::Update(total),
+            }
+            format!("set all to {}", total)
+        }
+    };
+    (description, update)
+}
 
 fn input_confirmation_loop(str: &str) -> bool {
     loop {
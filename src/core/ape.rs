@@ -0,0 +1,247 @@
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const PREAMBLE: &[u8; 8] = b"APETAGEX";
+const VERSION: u32 = 2000;
+const HEADER_LEN: u64 = 32;
+const ID3V1_LEN: u64 = 128;
+
+const HAS_HEADER: u32 = 1 << 31;
+const IS_HEADER: u32 = 1 << 29;
+const ITEM_TYPE_MASK: u32 = 0b110;
+const ITEM_TYPE_UTF8: u32 = 0;
+
+/// An APEv2 tag as it is used by Monkey's Audio, WavPack and Musepack files, sometimes also
+/// found at the end of MP3 files.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tag {
+    pub items: Vec<Item>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+    pub key: String,
+    pub flags: u32,
+    pub value: Vec<u8>,
+}
+
+/// The location of a tag inside a file.
+struct Location {
+    /// The start of the tag including the header if present.
+    start: u64,
+    /// The end of the tag including the footer.
+    end: u64,
+    item_count: u32,
+    items_start: u64,
+}
+
+impl Tag {
+    pub fn read_from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let loc = locate(&mut file)?.ok_or_else(not_found)?;
+
+        let mut data = vec![0; (loc.end - HEADER_LEN - loc.items_start) as usize];
+        file.seek(SeekFrom::Start(loc.items_start))?;
+        file.read_exact(&mut data)?;
+
+        // Every item needs at least its header and the key terminator
+        let mut items = Vec::with_capacity((loc.item_count as usize).min(data.len() / 9));
+        let mut pos = 0;
+        for _ in 0..loc.item_count {
+            let header = data.get(pos..pos + 8).ok_or_else(|| invalid_data("truncated item"))?;
+            let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+            let flags = u32::from_le_bytes(header[4..8].try_into().unwrap());
+            pos += 8;
+
+            let key_len = data[pos..]
+                .iter()
+                .position(|b| *b == 0)
+                .ok_or_else(|| invalid_data("truncated item"))?;
+            let key = String::from_utf8_lossy(&data[pos..pos + key_len]).into_owned();
+            pos += key_len + 1;
+
+            let value = data.get(pos..pos + len).ok_or_else(|| invalid_data("truncated item"))?;
+            pos += len;
+
+            items.push(Item { key, flags, value: value.to_vec() });
+        }
+
+        Ok(Self { items })
+    }
+
+    /// Replaces an existing tag or appends a new one, an ID3v1 tag at the end of the file is
+    /// preserved.
+    pub fn write_to_path(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let (start, trailer) = remove(&mut file)?;
+
+        if !self.items.is_empty() {
+            file.seek(SeekFrom::Start(start))?;
+            file.write_all(&self.to_bytes())?;
+        }
+        file.write_all(&trailer)?;
+
+        Ok(())
+    }
+
+    pub fn remove_from_path(path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let (start, trailer) = remove(&mut file)?;
+
+        file.seek(SeekFrom::Start(start))?;
+        file.write_all(&trailer)
+    }
+
+    pub fn values<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.items
+            .iter()
+            .filter(move |i| i.key.eq_ignore_ascii_case(key) && i.is_text())
+            .flat_map(|i| std::str::from_utf8(&i.value).ok())
+            .flat_map(|v| v.split('\0'))
+    }
+
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.items
+            .iter()
+            .find(|i| i.key.eq_ignore_ascii_case(key) && i.is_text())
+            .and_then(|i| std::str::from_utf8(&i.value).ok())
+            .and_then(|v| v.split('\0').next())
+    }
+
+    pub fn has_cover(&self) -> bool {
+        self.items.iter().any(|i| i.key.to_ascii_lowercase().starts_with("cover art"))
+    }
+
+    /// Multiple values are stored in a single item separated by null bytes.
+    pub fn set_values(&mut self, key: &str, values: &[String]) {
+        let value = values.join("\0").into_bytes();
+        match self.items.iter_mut().find(|i| i.key.eq_ignore_ascii_case(key)) {
+            Some(i) => {
+                i.flags = ITEM_TYPE_UTF8;
+                i.value = value;
+            }
+            None => self.items.push(Item { key: key.to_owned(), flags: ITEM_TYPE_UTF8, value }),
+        }
+    }
+
+    pub fn set_value(&mut self, key: &str, value: impl Into<String>) {
+        self.set_values(key, &[value.into()]);
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.items.retain(|i| !i.key.eq_ignore_ascii_case(key));
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut items = Vec::new();
+        for i in self.items.iter() {
+            items.extend_from_slice(&(i.value.len() as u32).to_le_bytes());
+            items.extend_from_slice(&i.flags.to_le_bytes());
+            items.extend_from_slice(i.key.as_bytes());
+            items.push(0);
+            items.extend_from_slice(&i.value);
+        }
+
+        let size = items.len() as u32 + HEADER_LEN as u32;
+        let count = self.items.len() as u32;
+
+        let mut data = Vec::with_capacity(items.len() + 2 * HEADER_LEN as usize);
+        write_header(&mut data, size, count, HAS_HEADER | IS_HEADER);
+        data.extend_from_slice(&items);
+        write_header(&mut data, size, count, HAS_HEADER);
+
+        data
+    }
+}
+
+impl Item {
+    fn is_text(&self) -> bool {
+        self.flags & ITEM_TYPE_MASK == ITEM_TYPE_UTF8
+    }
+}
+
+fn write_header(data: &mut Vec<u8>, size: u32, count: u32, flags: u32) {
+    data.extend_from_slice(PREAMBLE);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&size.to_le_bytes());
+    data.extend_from_slice(&count.to_le_bytes());
+    data.extend_from_slice(&flags.to_le_bytes());
+    data.extend_from_slice(&[0; 8]);
+}
+
+/// Removes the tag and truncates the file, returns the new end of the file and the ID3v1 tag
+/// that has to be written back.
+fn remove(file: &mut File) -> io::Result<(u64, Vec<u8>)> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let (start, end) = match locate(file)? {
+        Some(l) => (l.start, l.end),
+        None if has_id3v1(file)? => (len - ID3V1_LEN, len - ID3V1_LEN),
+        None => (len, len),
+    };
+
+    let mut trailer = vec![0; (len - end) as usize];
+    file.seek(SeekFrom::Start(end))?;
+    file.read_exact(&mut trailer)?;
+    file.set_len(start)?;
+
+    Ok((start, trailer))
+}
+
+fn locate(file: &mut File) -> io::Result<Option<Location>> {
+    let len = file.seek(SeekFrom::End(0))?;
+
+    let mut end = len;
+    if has_id3v1(file)? {
+        end -= ID3V1_LEN;
+    }
+    if end < HEADER_LEN {
+        return Ok(None);
+    }
+
+    let mut footer = [0; HEADER_LEN as usize];
+    file.seek(SeekFrom::Start(end - HEADER_LEN))?;
+    file.read_exact(&mut footer)?;
+    if &footer[0..8] != PREAMBLE {
+        return Ok(None);
+    }
+
+    let size = u32::from_le_bytes(footer[12..16].try_into().unwrap()) as u64;
+    let item_count = u32::from_le_bytes(footer[16..20].try_into().unwrap());
+    let flags = u32::from_le_bytes(footer[20..24].try_into().unwrap());
+
+    if size < HEADER_LEN {
+        return Err(invalid_data("invalid tag size"));
+    }
+    let items_start = end.checked_sub(size).ok_or_else(|| invalid_data("invalid tag size"))?;
+    let start = match flags & HAS_HEADER != 0 {
+        true => {
+            items_start.checked_sub(HEADER_LEN).ok_or_else(|| invalid_data("invalid tag size"))?
+        }
+        false => items_start,
+    };
+
+    Ok(Some(Location { start, end, item_count, items_start }))
+}
+
+fn has_id3v1(file: &mut File) -> io::Result<bool> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if len < ID3V1_LEN {
+        return Ok(false);
+    }
+
+    let mut marker = [0; 3];
+    file.seek(SeekFrom::Start(len - ID3V1_LEN))?;
+    file.read_exact(&mut marker)?;
+
+    Ok(&marker == b"TAG")
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no APEv2 tag found")
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
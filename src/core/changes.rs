@@ -1,9 +1,13 @@
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{error, io};
 
-use crate::fs::{valid_os_str, valid_os_str_dots};
-use crate::{Checks, DirCreation, FileOpType, FileOperation, MusicIndex, Song, SongOperation};
+use crate::sanitize::transliterate;
+use crate::{
+    Articles, Checks, Context, DateKind, DirCreation, FileOpType, FileOperation, Filesystem, Mode,
+    MusicIndex, Normalization, OverwritePolicy, Song, SongOperation, Template,
+};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Changes<'a> {
@@ -11,24 +15,99 @@ pub struct Changes<'a> {
     pub dir_creations: Vec<DirCreation>,
     pub song_operations: Vec<SongOperation<'a>>,
     pub file_operations: Vec<FileOperation<'a>>,
+    pub conflicts: Vec<Conflict<'a>>,
+    pub existing_files: Vec<ExistingFile<'a>>,
+}
+
+/// Identifies a release while generating paths.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum ReleaseKey<'s> {
+    /// The MusicBrainz release ID.
+    Id(&'s str),
+    /// The release artists and the release name.
+    Name(&'s [String], &'s str),
+}
+
+/// Songs that would end up at the same path, which would overwrite each other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conflict<'a> {
+    pub path: PathBuf,
+    /// The songs in the order they were resolved, the first one keeps the path.
+    pub songs: Vec<&'a Song>,
+    pub policy: CollisionPolicy,
+}
+
+/// A file that already exists at the new path of a song or file, which isn't part of the
+/// changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExistingFile<'a> {
+    pub source: &'a Path,
+    pub path: PathBuf,
+    /// Whether the policy allows overwriting it, otherwise the source isn't moved.
+    pub overwrite: bool,
+}
+
+/// How songs that would end up at the same path are resolved.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CollisionPolicy {
+    /// Leave all but the first song where they are.
+    #[default]
+    Skip,
+    /// Append a number like ` (2)` to the file names of the other songs.
+    Suffix,
+    /// Leave all but the song with the highest quality where they are. A song that is already
+    /// at the path is only replaced if the overwrite policy allows it, otherwise it is kept.
+    KeepBest,
+}
+
+/// Options for the generated paths.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChangeOptions {
+    /// Prefixes release directories with the year of the release, like `1997 - Album`. The
+    /// original date falls back to the release date.
+    pub year_prefix: Option<DateKind>,
+    /// Names artist directories by the sort name of the release artists, like `Beatles, The`.
+    pub sort_artist_dirs: bool,
+    /// Derives missing sort names by moving leading articles to the end.
+    pub articles: Option<Articles>,
+    /// Puts compilations into this directory instead of the one of their release artists, like
+    /// `Various Artists/Album`.
+    pub compilation_dir: Option<String>,
+    /// Lays out songs by a template instead of `Artist/Release/NN - Artists - Title`.
+    pub template: Option<Template>,
+    /// Puts the songs of multi-disc releases into a directory per disc named by this template,
+    /// like `Release/CD1/NN - Artists - Title`, instead of prefixing the disc number.
+    pub disc_dirs: Option<Template>,
+    /// The filesystem of the output directory, which decides how names are sanitized.
+    pub filesystem: Filesystem,
+    /// Converts names to a Unicode normalization form.
+    pub normalization: Option<Normalization>,
+    /// Converts names to ASCII for players that can't display other characters.
+    pub transliterate: bool,
+    pub collisions: CollisionPolicy,
+    pub overwrite: OverwritePolicy,
+    /// Whether files are moved or copied, only moved files free their path for others.
+    pub op_type: FileOpType,
 }
 
 impl<'a> Changes<'a> {
-    pub fn generate(checks: Checks<'a>, output_dir: &Path) -> Self {
+    pub fn generate(checks: Checks<'a>, output_dir: &Path, options: &ChangeOptions) -> Self {
         let mut new = Changes {
             index: checks.index,
             dir_creations: Vec::new(),
-            song_operations: checks.updates,
+            song_operations: Vec::new(),
             file_operations: Vec::new(),
+            conflicts: Vec::new(),
+            existing_files: Vec::new(),
         };
-        new.generate_diff(output_dir);
+        new.generate_diff(checks.updates, output_dir, options);
         new
     }
 }
 
 impl<'a> Changes<'a> {
-    fn new_song_path(&self, song: &'a Song) -> &Path {
-        if let Some(o) = self.song_operations.iter().find(|o| o.song == song) {
+    fn new_song_path<'s>(&'s self, song: &'s Song) -> &'s Path {
+        if let Some(o) = self.song_operations.iter().find(|o| o.song.path == song.path) {
             if let Some(p) = &o.new_path {
                 return p;
             }
@@ -41,7 +120,8 @@ impl<'a> Changes<'a> {
         match self.song_operations.iter_mut().find(|f| f.song == song) {
             Some(fo) => f(fo),
             None => {
-                let mut fo = SongOperation { song, tag_update: None, new_path: None };
+                let mut fo =
+                    SongOperation { song, tag_update: None, new_path: None, overwrite: false };
 
                 f(&mut fo);
 
@@ -59,77 +139,447 @@ impl<'a> Changes<'a> {
         }
     }
 
-    fn generate_diff(&mut self, output_dir: &Path) {
-        self.dir_creations.clear();
-        self.song_operations.clear();
+    /// The extension of the song, which might have been fixed by the checks.
+    fn song_extension(&self, song: &Song) -> Option<OsString> {
+        self.new_song_path(song).extension().map(|e| e.to_owned())
+    }
 
-        if !output_dir.exists() {
-            self.dir_creations.push(DirCreation { path: output_dir.to_owned() })
+    /// The metadata of the song after the tag update of the checks is applied.
+    fn updated_song(&self, song: &'a Song) -> Song {
+        let mut updated = song.clone();
+        let update = self.song_operations.iter().find(|o| o.song.path == song.path);
+        if let Some(u) = update.and_then(|o| o.tag_update.as_ref()) {
+            u.apply_to(&mut updated);
         }
+        updated
+    }
 
-        for song in self.index.songs.iter() {
-            let release_artists = valid_os_str_dots(&song.release_artists_str());
-            let release = valid_os_str_dots(&song.release);
+    /// Releases with a MusicBrainz ID are identified by it, so pressings with the same title
+    /// stay separate. Compilations that are moved to the compilation directory are only grouped
+    /// by their release artists if they are tagged, otherwise they are the track artists, which
+    /// might differ for every song.
+    fn release_key<'s>(song: &'s Song, options: &ChangeOptions) -> ReleaseKey<'s> {
+        let artists: &[String] =
+            match Self::is_compilation_routed(song, options) && !song.has_release_artists {
+                true => &[],
+                false => &song.release_artists,
+            };
+        match &song.mb_release_id {
+            Some(id) => ReleaseKey::Id(id),
+            None => ReleaseKey::Name(artists, &song.release),
+        }
+    }
 
-            let artists = valid_os_str(&song.artists_str());
-            let title = valid_os_str(&song.title);
-            let extension = song.path.extension().unwrap();
-            let disc = song.disc_number.unwrap_or(0);
-            let total_discs = song.total_discs.unwrap_or(0);
-            let track = song.track_number.unwrap_or(0);
+    /// The release artists that the directory of a release is named after, none for
+    /// compilations in the compilation directory.
+    fn release_dir_artists<'s>(song: &'s Song, options: &ChangeOptions) -> &'s [String] {
+        match Self::is_compilation_routed(song, options) {
+            true => &[],
+            false => &song.release_artists,
+        }
+    }
 
-            let mut path = output_dir.join(&release_artists);
-            self.dir_creation(&path);
+    fn is_compilation_routed(song: &Song, options: &ChangeOptions) -> bool {
+        options.compilation_dir.is_some() && song.compilation
+    }
+
+    /// The earliest year of every release, so all of its songs end up in the same directory.
+    fn release_years<'s>(
+        songs: &'s [Song],
+        kind: DateKind,
+        options: &ChangeOptions,
+    ) -> HashMap<ReleaseKey<'s>, i32> {
+        let mut years = HashMap::new();
+        for s in songs.iter() {
+            if let Some(d) = s.date(kind).or(s.release_date) {
+                let key = Self::release_key(s, options);
+                let year = years.entry(key).or_insert(d.year);
+                *year = d.year.min(*year);
+            }
+        }
+        years
+    }
+
+    /// The directory name of every release. Different releases that would share a directory
+    /// are told apart by the start of their MusicBrainz ID, or by their release artists in the
+    /// compilation directory.
+    fn release_dir_names<'s>(
+        songs: &'s [Song],
+        years: &HashMap<ReleaseKey<'s>, i32>,
+        options: &ChangeOptions,
+    ) -> HashMap<ReleaseKey<'s>, String> {
+        let mut dirs: HashMap<(&[String], String), Vec<ReleaseKey>> = HashMap::new();
+        for s in songs.iter() {
+            let key = Self::release_key(s, options);
+            let name = match years.get(&key) {
+                Some(y) => format!("{:04} - {}", y, s.release),
+                None => s.release.clone(),
+            };
+            let keys = dirs.entry((Self::release_dir_artists(s, options), name)).or_default();
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+
+        let mut names = HashMap::new();
+        for ((dir_artists, name), keys) in dirs {
+            let shared = keys.len() > 1;
+            for key in keys {
+                let name = match (shared, key) {
+                    (true, ReleaseKey::Id(id)) => {
+                        format!("{} [{}]", name, id.chars().take(8).collect::<String>())
+                    }
+                    (true, ReleaseKey::Name(a, _)) if dir_artists.is_empty() && !a.is_empty() => {
+                        format!("{} [{}]", name, a.join(", "))
+                    }
+                    _ => name.clone(),
+                };
+                names.insert(key, name);
+            }
+        }
+        names
+    }
+
+    /// The sort names of all release artists, taken from the first song that has one.
+    fn release_artists_sort(songs: &[Song]) -> HashMap<&[String], &str> {
+        let mut names = HashMap::new();
+        for s in songs.iter() {
+            if let Some(n) = &s.release_artists_sort {
+                names.entry(s.release_artists.as_slice()).or_insert(n.as_str());
+            }
+        }
+        names
+    }
 
-            path.push(&release);
+    fn artist_dir_name(
+        song: &Song,
+        sort_names: &HashMap<&[String], &str>,
+        options: &ChangeOptions,
+    ) -> String {
+        if !options.sort_artist_dirs {
+            return song.release_artists_str();
+        }
+
+        match (sort_names.get(song.release_artists.as_slice()), &options.articles) {
+            (Some(n), _) => n.to_string(),
+            (None, Some(a)) => song
+                .release_artists
+                .iter()
+                .map(|n| a.move_leading(n).unwrap_or_else(|| n.clone()))
+                .collect::<Vec<_>>()
+                .join(", "),
+            (None, None) => song.release_artists_str(),
+        }
+    }
+
+    fn song_path(
+        &mut self,
+        song: &Song,
+        output_dir: &Path,
+        artist_dir: &str,
+        release_dir: &str,
+        year: Option<i32>,
+        options: &ChangeOptions,
+    ) -> PathBuf {
+        let disc = song.disc_number.unwrap_or(0);
+        let total_discs = song.total_discs.unwrap_or(0);
+        let track = song.track_number.unwrap_or(0);
+
+        let mut dirs = vec![artist_dir.to_owned(), release_dir.to_owned()];
+
+        let mut file_name = String::new();
+        match (total_discs > 1, &options.disc_dirs) {
+            (true, Some(t)) => {
+                let context =
+                    Context { artist_dir: artist_dir.to_owned(), year, ..Default::default() };
+                dirs.extend(t.render(song, &context));
+            }
+            (true, None) => file_name.push_str(&format!("{} ", disc)),
+            (false, _) => (),
+        }
+        file_name.push_str(&format!(
+            "{:02} - {} - {}",
+            track,
+            song.artists_str().trim(),
+            song.title.trim()
+        ));
+
+        self.sanitized_path(song, output_dir, &dirs, &file_name, options)
+    }
+
+    fn template_path(
+        &mut self,
+        song: &Song,
+        output_dir: &Path,
+        template: &Template,
+        context: &Context,
+        options: &ChangeOptions,
+    ) -> PathBuf {
+        let mut dirs = template.render(song, context);
+        let file_name = match dirs.pop().filter(|n| !n.trim().is_empty()) {
+            Some(n) => n,
+            None => song.path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+        };
+
+        self.sanitized_path(song, output_dir, &dirs, &file_name, options)
+    }
+
+    /// Audiobooks are laid out as `Author/[Series/][NN - ]Title/` where `NN` is the part of the
+    /// series.
+    fn audiobook_path(
+        &mut self,
+        song: &Song,
+        output_dir: &Path,
+        options: &ChangeOptions,
+    ) -> PathBuf {
+        let mut dirs = vec![song.release_artists_str()];
+
+        let mut book_dir = String::new();
+        if let Some(series) = &song.series {
+            dirs.push(series.clone());
+
+            if let Some(part) = song.series_part {
+                book_dir.push_str(&format!("{:02} - ", part));
+            }
+        }
+        book_dir.push_str(&song.release);
+        dirs.push(book_dir);
+
+        // Books that are split into multiple files keep their track numbers
+        let mut file_name = String::new();
+        if let Some(track) = song.track_number.filter(|_| song.total_tracks != Some(1)) {
+            file_name.push_str(&format!("{:02} - ", track));
+        }
+        file_name.push_str(song.title.trim());
+
+        self.sanitized_path(song, output_dir, &dirs, &file_name, options)
+    }
+
+    /// Sanitizes the directories and file name for the target filesystem and creates the
+    /// directories.
+    fn sanitized_path(
+        &mut self,
+        song: &Song,
+        output_dir: &Path,
+        dirs: &[String],
+        file_name: &str,
+        options: &ChangeOptions,
+    ) -> PathBuf {
+        let convert = |s: &str| {
+            let s = match options.normalization {
+                Some(n) => n.apply(s),
+                None => s.to_owned(),
+            };
+            match options.transliterate {
+                true => transliterate(&s),
+                false => s,
+            }
+        };
+        let dirs: Vec<String> = dirs.iter().map(|d| convert(d)).collect();
+        let dirs: Vec<&str> = dirs.iter().map(|d| d.as_str()).collect();
+        let file_name = convert(file_name);
+        let extension = self.song_extension(song);
+        let extension = extension.as_ref().map(|e| e.to_string_lossy());
+        let mut components =
+            options.filesystem.path(output_dir, &dirs, &file_name, extension.as_deref());
+        let file_name = components.pop().unwrap();
+
+        let mut path = output_dir.to_owned();
+        for c in components {
+            path.push(c);
             self.dir_creation(&path);
+        }
+        path.push(file_name);
+
+        path
+    }
+
+    /// The path that identifies a file on the target filesystem.
+    fn collision_key(path: &Path, options: &ChangeOptions) -> String {
+        match options.filesystem.case_insensitive() {
+            true => path.to_string_lossy().to_lowercase(),
+            false => path.to_string_lossy().into_owned(),
+        }
+    }
+
+    /// Songs that stay where they are keep their path, otherwise the first song does.
+    fn resolve_collisions(&mut self, options: &ChangeOptions) {
+        self.conflicts.clear();
+
+        let mut groups: Vec<Vec<&'a Song>> = Vec::new();
+        let mut keys: HashMap<String, usize> = HashMap::new();
+        for song in self.index.songs.iter() {
+            let key = Self::collision_key(self.new_song_path(song), options);
+            match keys.get(&key) {
+                Some(&i) => groups[i].push(song),
+                None => {
+                    keys.insert(key, groups.len());
+                    groups.push(vec![song]);
+                }
+            }
+        }
 
-            let mut file_name = OsString::new();
-            if total_discs > 1 {
-                file_name.push(disc.to_string());
-                file_name.push(" ");
+        for mut songs in groups.into_iter().filter(|g| g.len() > 1) {
+            let path = self.new_song_path(songs[0]).to_owned();
+            songs.sort_by_key(|s| self.new_song_path(s) != s.path);
+            if options.collisions == CollisionPolicy::KeepBest {
+                songs.sort_by(|a, b| b.properties.cmp_quality(&a.properties));
+
+                // Replacing a song that is already at the path overwrites it
+                let best = songs[0];
+                let kept = songs.iter().position(|s| {
+                    self.new_song_path(s) == s.path
+                        && s != &best
+                        && !options.overwrite.allows(&best.path, &s.path)
+                });
+                if let Some(i) = kept {
+                    let s = songs.remove(i);
+                    songs.insert(0, s);
+                }
             }
-            file_name.push(format!("{:02} - ", track));
-            file_name.push(&artists);
-            file_name.push(" - ");
-            file_name.push(&title);
-            file_name.push(".");
-            file_name.push(extension);
 
-            path.push(file_name);
+            for (i, s) in songs.iter().copied().enumerate().skip(1) {
+                let moving = self.new_song_path(s) != s.path;
+                match (options.collisions, moving) {
+                    (CollisionPolicy::Suffix, _) => {
+                        let new_path = (i + 1..)
+                            .map(|n| options.filesystem.suffixed_path(&path, &format!(" ({})", n)))
+                            .find(|p| !keys.contains_key(&Self::collision_key(p, options)))
+                            .unwrap();
+                        keys.insert(Self::collision_key(&new_path, options), 0);
+                        self.update_song_op(s, |o| o.new_path = Some(new_path));
+                    }
+                    (_, true) => self.update_song_op(s, |o| o.new_path = None),
+                    // The better song replaces this one, so its tags mustn't be touched
+                    (CollisionPolicy::KeepBest, false) => {
+                        self.song_operations.retain(|o| o.song != s)
+                    }
+                    (CollisionPolicy::Skip, false) => (),
+                }
+            }
+            self.song_operations.retain(|o| o.new_path.is_some() || o.tag_update.is_some());
+
+            self.conflicts.push(Conflict { path, songs, policy: options.collisions });
+        }
+    }
+
+    /// The diff starts from the tag updates that were chosen for the checks, since the paths
+    /// are generated from the updated tags.
+    fn generate_diff(
+        &mut self,
+        updates: Vec<SongOperation<'a>>,
+        output_dir: &Path,
+        options: &ChangeOptions,
+    ) {
+        self.dir_creations.clear();
+        self.song_operations = updates;
+
+        if !output_dir.exists() {
+            self.dir_creations.push(DirCreation { path: output_dir.to_owned() })
+        }
+
+        // Paths are generated from the tags like they will be written
+        let updated: Vec<Song> = self.index.songs.iter().map(|s| self.updated_song(s)).collect();
+
+        let years = match options.year_prefix {
+            Some(k) => Self::release_years(&updated, k, options),
+            None => HashMap::new(),
+        };
+        let (template_years, template_original_years) = match options.template {
+            Some(_) => (
+                Self::release_years(&updated, DateKind::Release, options),
+                Self::release_years(&updated, DateKind::Original, options),
+            ),
+            None => Default::default(),
+        };
+        let release_dirs = Self::release_dir_names(&updated, &years, options);
+        let sort_names = match options.sort_artist_dirs {
+            true => Self::release_artists_sort(&updated),
+            false => HashMap::new(),
+        };
+
+        for (song, tags) in self.index.songs.iter().zip(updated.iter()) {
+            let key = Self::release_key(tags, options);
+            let artist_dir = match &options.compilation_dir {
+                Some(d) if Self::is_compilation_routed(tags, options) => d.clone(),
+                _ => Self::artist_dir_name(tags, &sort_names, options),
+            };
+
+            let path = match (&options.template, self.index.mode) {
+                (Some(t), _) => {
+                    let context = Context {
+                        artist_dir,
+                        year: template_years.get(&key).copied(),
+                        original_year: template_original_years.get(&key).copied(),
+                    };
+                    self.template_path(tags, output_dir, t, &context, options)
+                }
+                (None, Mode::Music) => {
+                    let year = years.get(&key).copied();
+                    let release_dir = &release_dirs[&key];
+                    self.song_path(tags, output_dir, &artist_dir, release_dir, year, options)
+                }
+                (None, Mode::Audiobook) => self.audiobook_path(tags, output_dir, options),
+            };
 
             if path != song.path {
                 self.update_song_op(song, |fo| fo.new_path = Some(path))
             }
         }
 
-        for image in self.index.images.iter() {
-            let current_dir = image.parent().unwrap();
-            let mut new_song_dirs = self
-                .index
-                .songs
-                .iter()
-                .filter(|s| s.path.parent().unwrap() == current_dir)
-                .map(|s| self.new_song_path(s).parent().unwrap());
-
-            if let Some(n) = new_song_dirs.next() {
-                let new_song_dir = n;
+        self.resolve_collisions(options);
 
-                if new_song_dir == current_dir {
-                    continue;
+        for file in self.index.images.iter().chain(self.index.sidecars.iter()) {
+            // Files named like a song, for example lyrics, follow its new name
+            let song = self.index.songs.iter().find(|s| {
+                s.path.parent() == file.parent() && s.path.file_stem() == file.file_stem()
+            });
+            if let Some(s) = song {
+                let new_path = self.new_song_path(s).with_extension(file.extension().unwrap());
+                if &new_path != file {
+                    self.file_operations.push(FileOperation {
+                        old_path: file,
+                        new_path,
+                        overwrite: false,
+                    });
                 }
+                continue;
+            }
+
+            let current_dir = file.parent().unwrap();
+            let songs: Vec<&Song> =
+                updated.iter().filter(|s| s.path.parent().unwrap() == current_dir).collect();
+            let new_song_dirs: Vec<&Path> =
+                songs.iter().map(|s| self.new_song_path(s).parent().unwrap()).collect();
+
+            if let Some(&n) = new_song_dirs.first() {
+                let mut new_song_dir = n;
 
-                let mut all_equal = true;
-                for n in new_song_dirs {
-                    if n != new_song_dir {
-                        all_equal = false;
-                        break;
+                // A release that spans several directories, like one per disc, keeps its files
+                // in the directory that contains all of them
+                if new_song_dirs.iter().any(|d| *d != n) {
+                    let key = Self::release_key(songs[0], options);
+                    if songs.iter().any(|s| Self::release_key(s, options) != key) {
+                        continue;
+                    }
+
+                    for d in new_song_dirs.iter() {
+                        while !d.starts_with(new_song_dir) {
+                            new_song_dir = new_song_dir.parent().unwrap();
+                        }
+                    }
+                    if !new_song_dir.starts_with(output_dir) || new_song_dir == output_dir {
+                        continue;
                     }
                 }
 
-                if all_equal {
-                    let new_path = new_song_dir.join(image.file_name().unwrap());
-                    self.file_operations.push(FileOperation { old_path: image, new_path });
+                if new_song_dir != current_dir {
+                    let new_path = new_song_dir.join(file.file_name().unwrap());
+                    self.file_operations.push(FileOperation {
+                        old_path: file,
+                        new_path,
+                        overwrite: false,
+                    });
                 }
             }
         }
@@ -142,10 +592,137 @@ impl<'a> Changes<'a> {
                 let new_path = unknown_dir.join(unknown.file_name().unwrap());
 
                 if &new_path != unknown {
-                    self.file_operations.push(FileOperation { old_path: unknown, new_path });
+                    self.file_operations.push(FileOperation {
+                        old_path: unknown,
+                        new_path,
+                        overwrite: false,
+                    });
+                }
+            }
+        }
+
+        self.check_existing_files(options);
+        self.prune_dir_creations();
+    }
+
+    /// Removes directories that nothing is moved into, because the moves were dropped.
+    fn prune_dir_creations(&mut self) {
+        let new_paths: Vec<&Path> = self
+            .song_operations
+            .iter()
+            .filter_map(|o| o.new_path.as_deref())
+            .chain(self.file_operations.iter().map(|o| o.new_path.as_path()))
+            .collect();
+        self.dir_creations.retain(|d| new_paths.iter().any(|p| p.starts_with(&d.path)));
+    }
+
+    /// Files that already exist at a new path are only overwritten if the policy allows it.
+    /// Paths of songs and files that are moved away are free, unless their move is dropped.
+    fn check_existing_files(&mut self, options: &ChangeOptions) {
+        self.existing_files.clear();
+
+        let key = |p: &Path| Self::collision_key(p, options);
+        let moving = options.op_type == FileOpType::Move;
+        // Songs that are replaced by a better one
+        let mut replaced: HashSet<String> = HashSet::new();
+        for c in self.conflicts.iter().filter(|c| c.policy == CollisionPolicy::KeepBest) {
+            let path = key(&c.path);
+            replaced.extend(c.songs.iter().skip(1).map(|s| key(&s.path)).filter(|k| *k == path));
+        }
+        loop {
+            let song_moves: Vec<(String, Option<String>)> = self
+                .song_operations
+                .iter()
+                .map(|o| (key(&o.song.path), o.new_path.as_deref().map(key)))
+                .collect();
+            let (song_order, song_cycles) = move_order(&song_moves);
+            let file_moves: Vec<(String, Option<String>)> = self
+                .file_operations
+                .iter()
+                .map(|o| (key(o.old_path), Some(key(&o.new_path))))
+                .collect();
+            let (file_order, file_cycles) = move_order(&file_moves);
+            reorder(&mut self.song_operations, &song_order);
+            reorder(&mut self.file_operations, &file_order);
+
+            // Paths that are moved away before a song or file is moved there, songs are moved
+            // before files
+            let mut song_freed = replaced.clone();
+            let mut file_freed = replaced.clone();
+            if moving {
+                let songs = song_moves.iter().enumerate().filter(|(i, m)| {
+                    m.1.is_some() && !song_cycles.contains(i) && m.1.as_ref() != Some(&m.0)
+                });
+                song_freed.extend(songs.map(|(_, m)| m.0.clone()));
+                file_freed.extend(song_freed.iter().cloned());
+                let files = file_moves.iter().enumerate().filter(|(i, _)| !file_cycles.contains(i));
+                file_freed.extend(files.map(|(_, m)| m.0.clone()));
+            }
+            // Files that are still needed by another operation can't be overwritten
+            let pending: HashSet<&String> = song_moves
+                .iter()
+                .filter(|m| m.1.is_some())
+                .chain(file_moves.iter())
+                .map(|m| &m.0)
+                .collect();
+
+            let mut found = Vec::new();
+            let moves = self
+                .song_operations
+                .iter()
+                .filter_map(|o| Some((o.song.path.as_path(), o.new_path.as_ref()?, &song_freed)))
+                .chain(self.file_operations.iter().map(|o| (o.old_path, &o.new_path, &file_freed)));
+            for (source, path, freed) in moves {
+                let target = key(path);
+                if self.existing_files.iter().any(|e| e.source == source)
+                    || (moving && key(source) == target)
+                    || freed.contains(&target)
+                    || !path.exists()
+                {
+                    continue;
                 }
+
+                let overwrite =
+                    !pending.contains(&target) && options.overwrite.allows(source, path);
+                found.push(ExistingFile { source, path: path.clone(), overwrite });
+            }
+
+            // Dropped moves might block other ones
+            let kept: Vec<&Path> =
+                found.iter().filter(|e| !e.overwrite).map(|e| e.source).collect();
+            for o in self.song_operations.iter_mut() {
+                if kept.contains(&o.song.path.as_path()) {
+                    o.new_path = None;
+                }
+            }
+            self.song_operations.retain(|o| o.new_path.is_some() || o.tag_update.is_some());
+            self.file_operations.retain(|o| !kept.contains(&o.old_path));
+
+            self.existing_files.extend(found);
+            if kept.is_empty() {
+                break;
             }
         }
+
+        // Only the files that were planned to be replaced may be overwritten when executing
+        let allowed = |source: &Path, target: &Path| {
+            replaced.contains(&key(target))
+                || (moving && key(source) == key(target))
+                || self.existing_files.iter().any(|e| e.source == source && e.overwrite)
+        };
+        let song_overwrites: Vec<bool> = self
+            .song_operations
+            .iter()
+            .map(|o| matches!(&o.new_path, Some(p) if allowed(&o.song.path, p)))
+            .collect();
+        let file_overwrites: Vec<bool> =
+            self.file_operations.iter().map(|o| allowed(o.old_path, &o.new_path)).collect();
+        for (o, overwrite) in self.song_operations.iter_mut().zip(song_overwrites) {
+            o.overwrite = overwrite;
+        }
+        for (o, overwrite) in self.file_operations.iter_mut().zip(file_overwrites) {
+            o.overwrite = overwrite;
+        }
     }
 
     pub fn dir_creations(&self, f: &mut impl FnMut(&DirCreation, io::Result<()>)) {
@@ -177,3 +754,74 @@ impl<'a> Changes<'a> {
         }
     }
 }
+
+/// Orders moves given as `(source, target)` so a file is moved away before another one is moved
+/// to its path. Also returns the moves that form a cycle, since their paths can't be freed.
+fn move_order(moves: &[(String, Option<String>)]) -> (Vec<usize>, HashSet<usize>) {
+    let sources: HashMap<&String, usize> =
+        moves.iter().enumerate().filter(|(_, m)| m.1.is_some()).map(|(i, m)| (&m.0, i)).collect();
+    // The move that vacates the target of another one
+    let vacating =
+        |i: usize| moves[i].1.as_ref().and_then(|t| sources.get(t)).copied().filter(|&j| j != i);
+
+    let mut done = vec![false; moves.len()];
+    let mut order = Vec::with_capacity(moves.len());
+    let mut cycles = HashSet::new();
+    for start in 0..moves.len() {
+        let mut chain: Vec<usize> = Vec::new();
+        let mut i = start;
+        while !done[i] {
+            if let Some(pos) = chain.iter().position(|&c| c == i) {
+                cycles.extend(chain[pos..].iter().copied());
+                break;
+            }
+            chain.push(i);
+            match vacating(i) {
+                Some(j) => i = j,
+                None => break,
+            }
+        }
+        for &c in chain.iter().rev() {
+            done[c] = true;
+            order.push(c);
+        }
+    }
+    (order, cycles)
+}
+
+/// Sorts the items by their indices in `order`.
+fn reorder<T>(items: &mut Vec<T>, order: &[usize]) {
+    let mut items_opt: Vec<Option<T>> = items.drain(..).map(Some).collect();
+    items.extend(order.iter().filter_map(|&i| items_opt[i].take()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moves(m: &[(&str, Option<&str>)]) -> Vec<(String, Option<String>)> {
+        m.iter().map(|(s, t)| (s.to_string(), t.map(|t| t.to_string()))).collect()
+    }
+
+    #[test]
+    fn vacating_moves_first() {
+        let m = moves(&[("a", Some("b")), ("b", Some("c")), ("c", Some("d"))]);
+        assert_eq!(move_order(&m), (vec![2, 1, 0], HashSet::new()));
+    }
+
+    #[test]
+    fn unmoved_and_self() {
+        let m = moves(&[("a", Some("b")), ("b", None), ("c", Some("c"))]);
+        assert_eq!(move_order(&m), (vec![0, 1, 2], HashSet::new()));
+    }
+
+    #[test]
+    fn cycles() {
+        let m = moves(&[("x", Some("a")), ("a", Some("b")), ("b", Some("a")), ("c", Some("x"))]);
+        let (order, cycles) = move_order(&m);
+        assert_eq!(cycles, vec![1, 2].into_iter().collect());
+        let pos = |i| order.iter().position(|&o| o == i).unwrap();
+        assert!(pos(0) < pos(3));
+        assert_eq!(order.len(), 4);
+    }
+}
@@ -1,223 +1,444 @@
-use crate::{MusicIndex, Release, ReleaseArtists, SongOperation};
+use std::error;
+use std::path::PathBuf;
+
+use crate::fuzzy::{fold_release, similar};
+use crate::{
+    ApePolicy, AudioProperties, Date, DateKind, Format, Loudness, MusicIndex, Property, Release,
+    ReleaseArtists, ReplayGain, Resolutions, Song, SongOperation, TagUpdate, Value,
+};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Checks<'a> {
     pub index: &'a MusicIndex,
     pub updates: Vec<SongOperation<'a>>,
     pub artists: Vec<ReleaseArtists<'a>>,
+    /// Answers that are applied before `f` of [`Checks::run`] is called.
+    pub resolutions: Resolutions,
 }
 
 impl<'a> From<&'a MusicIndex> for Checks<'a> {
     fn from(index: &'a MusicIndex) -> Self {
-        Self { index, updates: Vec::new(), artists: Vec::new() }
+        Self {
+            index,
+            updates: Vec::new(),
+            artists: Vec::new(),
+            resolutions: Resolutions::default(),
+        }
     }
 }
 
 impl<'a> Checks<'a> {
+    /// Groups the songs by their release artists and releases. Songs with MusicBrainz IDs are
+    /// grouped by them, so releases with the same title stay separate and differently spelled
+    /// tags of one release are merged.
     pub fn update(&mut self) {
         self.artists.clear();
 
         for s in self.index.songs.iter() {
-            let mut added = false;
-
-            for a in self.artists.iter_mut() {
-                if &a.names == &s.release_artists {
-                    for r in a.releases.iter_mut() {
-                        if &r.name == &s.release {
-                            r.songs.push(s);
-                            added = true;
-                        }
+            let release_id = s.mb_release_id.as_deref();
+            let artist =
+                self.artists.iter_mut().find(|a| match (a.ids, &s.mb_release_artist_ids) {
+                    (ids, song_ids) if !ids.is_empty() && !song_ids.is_empty() => {
+                        ids == &song_ids[..]
                     }
+                    _ => a.names == &s.release_artists[..],
+                });
+
+            let artist = match artist {
+                Some(a) => a,
+                None => {
+                    self.artists.push(ReleaseArtists {
+                        names: &s.release_artists,
+                        ids: &s.mb_release_artist_ids,
+                        releases: Vec::new(),
+                    });
+                    self.artists.last_mut().unwrap()
+                }
+            };
+
+            // Names are only compared if neither has an ID, so the grouping doesn't depend on
+            // the order of the songs
+            let release = artist.releases.iter_mut().find(|r| match (r.id, release_id) {
+                (Some(a), Some(b)) => a == b,
+                (None, None) => r.name == s.release,
+                _ => false,
+            });
+
+            match release {
+                Some(r) => r.songs.push(s),
+                None => artist.releases.push(Release {
+                    name: &s.release,
+                    id: release_id,
+                    songs: vec![s],
+                }),
+            }
+        }
+    }
+
+    /// Removes the APEv2 tags of all MP3 files that have one next to their ID3 tag.
+    pub fn strip_ape_tags(&mut self) {
+        for s in self.index.songs.iter().filter(|s| s.has_ape_tag) {
+            self.update_tag(s, |tu| tu.ape_policy = ApePolicy::Strip);
+        }
+    }
+
+    /// Measures the loudness of the songs and sets their ReplayGain. The release gain is measured
+    /// over all songs of a release, if all of them could be decoded. Unless `all` is set only
+    /// releases that have songs without a gain are analyzed. `f` is called with the result of
+    /// every song.
+    pub fn analyze_loudness(
+        &mut self,
+        all: bool,
+        mut f: impl FnMut(&Song, &Result<Loudness, Box<dyn error::Error>>),
+    ) {
+        let mut gains = Vec::new();
+        for a in self.artists.iter() {
+            for r in a.releases.iter() {
+                let missing =
+                    r.songs.iter().any(|s| s.track_gain.is_none() || s.release_gain.is_none());
+                if !(all || missing) {
+                    continue;
+                }
+
+                let measured: Vec<(&'a Song, Option<Loudness>)> = r
+                    .songs
+                    .iter()
+                    .map(|s| {
+                        let l = Loudness::analyze(&s.path);
+                        f(s, &l);
+                        (*s, l.ok())
+                    })
+                    .collect();
 
-                    if !added {
-                        a.releases.push(Release { name: &s.release, songs: vec![s] });
-                        added = true;
+                let release = match measured.iter().all(|(_, l)| l.is_some()) {
+                    true => Loudness::combine(measured.iter().filter_map(|(_, l)| l.as_ref()))
+                        .replay_gain(),
+                    false => None,
+                };
+                for (s, l) in measured {
+                    if let Some(track) = l.and_then(|l| l.replay_gain()) {
+                        gains.push((s, track, release));
                     }
                 }
             }
+        }
+
+        for (s, track, release) in gains {
+            if !same_gain(s.track_gain, track) {
+                self.update_tag(s, |tu| tu.track_gain = Value::Update(track));
+            }
+            if let Some(r) = release.filter(|r| !same_gain(s.release_gain, *r)) {
+                self.update_tag(s, |tu| tu.release_gain = Value::Update(r));
+            }
+        }
+    }
+
+    /// Checks whether the extensions of songs match the format detected by their content. If `f`
+    /// returns true the song is renamed to the common extension of its format.
+    pub fn check_extensions(&mut self, mut f: impl FnMut(&Song, Format) -> bool) {
+        for s in self.index.songs.iter() {
+            let format = match s.format {
+                Some(f) => f,
+                None => continue,
+            };
+
+            if !format.matches_extension(&s.path) && f(s, format) {
+                self.rename(s, s.path.with_extension(format.extensions()[0]));
+            }
+        }
+    }
 
-            if !added {
-                self.artists.push(ReleaseArtists {
-                    names: &s.release_artists,
-                    releases: vec![Release { name: &s.release, songs: vec![s] }],
+    fn rename(&mut self, song: &'a Song, new_path: PathBuf) {
+        match self.updates.iter_mut().find(|o| o.song == song) {
+            Some(o) => o.new_path = Some(new_path),
+            None => self.updates.push(SongOperation {
+                song,
+                tag_update: None,
+                new_path: Some(new_path),
+                overwrite: false,
+            }),
+        }
+    }
+
+    pub(crate) fn update_tag(&mut self, song: &'a Song, f: impl FnOnce(&mut TagUpdate)) {
+        match self.updates.iter_mut().find(|o| o.song == song) {
+            Some(o) => f(o.tag_update.get_or_insert_with(TagUpdate::default)),
+            None => {
+                let mut tag_update = TagUpdate::default();
+
+                f(&mut tag_update);
+
+                self.updates.push(SongOperation {
+                    song,
+                    tag_update: Some(tag_update),
+                    new_path: None,
+                    overwrite: false,
                 });
             }
         }
     }
 
-    //pub fn check_inconsitent_release_artists(
-    //    &mut self,
-    //    f: fn(&MusicIndex, &ReleaseArtists, &ReleaseArtists) -> Value<Vec<String>>,
-    //) {
-    //    let mut offset = 1;
-    //    for ar1 in self.index.artists.iter() {
-    //        for ar2 in index.artists.iter().skip(offset) {
-    //            for (n1, n2) in ar1.names.iter().zip(ar2.names.iter()) {
-    //                if !n1.eq_ignore_ascii_case(n2) {
-    //                    continue;
-    //                }
-    //            }
-    //            match f(index, ar1, ar2) {
-    //                Value::Update(names) => {
-    //                    if ar1.names != names {
-    //                        for rl in ar1.releases.iter() {
-    //                            for song in rl.songs.iter().map(|&si| &index.songs[si]) {
-    //                                self.update_tag(&song.path, |tu| {
-    //                                    tu.album_artists = Value::Update(names)
-    //                                });
-    //                            }
-    //                        }
-    //                    }
-
-    //                    if ar2.names != names {
-    //                        for rl in ar2.releases.iter() {
-    //                            for song in rl.songs.iter().map(|&si| &index.songs[si]) {
-    //                                self.update_tag(&song.path, |tu| {
-    //                                    tu.album_artists = Value::Update(names)
-    //                                });
-    //                            }
-    //                        }
-    //                    }
-    //                }
-    //                Value::Remove => {
-    //                    for rl in ar1.releases.iter() {
-    //                        for song in rl.songs.iter().map(|&si| &index.songs[si]) {
-    //                            self.update_tag(&song.path, |tu| tu.album_artists = Value::Remove);
-    //                        }
-    //                    }
-
-    //                    for rl in ar2.releases.iter() {
-    //                        for song in rl.songs.iter().map(|&si| &index.songs[si]) {
-    //                            self.update_tag(&song.path, |tu| tu.album_artists = Value::Remove);
-    //                        }
-    //                    }
-    //                }
-    //                Value::Unchanged => (),
-    //            }
-    //        }
-    //        offset += 1;
-    //    }
-    //}
-
-    //pub fn check_inconsitent_albums(
-    //    &mut self,
-    //    index: &MusicIndex,
-    //    f: fn(&MusicIndex, &ReleaseArtists, &Release, &Release) -> Value<String>,
-    //) {
-    //    for ar in index.artists.iter() {
-    //        let mut offset = 1;
-    //        for al1 in ar.releases.iter() {
-    //            for al2 in ar.releases.iter().skip(offset) {
-    //                if al1.name.eq_ignore_ascii_case(&al2.name) {
-    //                    match f(index, ar, al1, al2) {
-    //                        Value::Update(name) => {
-    //                            if al1.name != name {
-    //                                for song in al1.songs.iter().map(|&si| &index.songs[si]) {
-    //                                    self.update_tag(&song.path, |tu| {
-    //                                        tu.album = Value::Update(name.clone());
-    //                                    });
-    //                                }
-    //                            }
-
-    //                            if al2.name != name {
-    //                                for song in al2.songs.iter().map(|&si| &index.songs[si]) {
-    //                                    self.update_tag(&song.path, |tu| {
-    //                                        tu.album = Value::Update(name.clone());
-    //                                    });
-    //                                }
-    //                            }
-    //                        }
-    //                        Value::Remove => {
-    //                            for song in al1.songs.iter().map(|&si| &index.songs[si]) {
-    //                                self.update_tag(&song.path, |tu| {
-    //                                    tu.album = Value::Remove;
-    //                                });
-    //                            }
-
-    //                            for song in al2.songs.iter().map(|&si| &index.songs[si]) {
-    //                                self.update_tag(&song.path, |tu| {
-    //                                    tu.album = Value::Remove;
-    //                                });
-    //                            }
-    //                        }
-    //                        Value::Unchanged => (),
-    //                    }
-    //                }
-    //            }
-    //            offset += 1;
-    //        }
-    //    }
-    //}
-
-    //pub fn check_inconsitent_total_tracks(
-    //    &mut self,
-    //    index: &MusicIndex,
-    //    f: fn(&ReleaseArtists, &Release, Vec<(Vec<&Song>, Option<u16>)>) -> Value<u16>,
-    //) {
-    //    for ar in index.artists.iter() {
-    //        for al in ar.releases.iter() {
-    //            let mut total_tracks: Vec<(Vec<&Song>, Option<u16>)> = Vec::new();
-
-    //            'songs: for s in al.songs.iter().map(|&si| &index.songs[si]) {
-    //                for (songs, tt) in total_tracks.iter_mut() {
-    //                    if *tt == s.total_tracks {
-    //                        songs.push(s);
-    //                        continue 'songs;
-    //                    }
-    //                }
-
-    //                total_tracks.push((vec![s], s.total_tracks));
-    //            }
-
-    //            if total_tracks.len() > 1 {
-    //                if let Value::Update(t) = f(ar, al, total_tracks) {
-    //                    for song in al.songs.iter().map(|&si| &index.songs[si]) {
-    //                        self.update_tag(&song.path, |tu| {
-    //                            tu.total_tracks = Value::Update(t);
-    //                        });
-    //                    }
-    //                }
-    //            }
-    //        }
-    //    }
-    //}
-
-    //pub fn check_inconsitent_total_discs(
-    //    &mut self,
-    //    index: &MusicIndex,
-    //    f: fn(&ReleaseArtists, &Release, Vec<(Vec<&Song>, Option<u16>)>) -> Value<u16>,
-    //) {
-    //    for ar in index.artists.iter() {
-    //        for rl in ar.releases.iter() {
-    //            let mut total_discs: Vec<(Vec<&Song>, Option<u16>)> = Vec::new();
-
-    //            'songs: for s in rl.songs.iter().map(|&si| &index.songs[si]) {
-    //                for (songs, tt) in total_discs.iter_mut() {
-    //                    if *tt == s.total_discs {
-    //                        songs.push(s);
-    //                        continue 'songs;
-    //                    }
-    //                }
-
-    //                total_discs.push((vec![s], s.total_discs));
-    //            }
-
-    //            if total_discs.len() > 1 {
-    //                match f(ar, rl, total_discs) {
-    //                    Value::Update(t) => {
-    //                        for song in rl.songs.iter().map(|&si| &index.songs[si]) {
-    //                            self.update_tag(&song.path, |tu| tu.total_discs = Value::Update(t));
-    //                        }
-    //                    }
-    //                    Value::Remove => {
-    //                        for song in rl.songs.iter().map(|&si| &index.songs[si]) {
-    //                            self.update_tag(&song.path, |tu| tu.total_discs = Value::Remove);
-    //                        }
-    //                    }
-    //                    Value::Unchanged => (),
-    //                }
-    //            }
-    //        }
-    //    }
-    //}
+    /// Fills in missing total tracks and discs. The value the other songs of the disc or release
+    /// have is used, otherwise the highest track or disc number. Values that were already
+    /// decided on are kept and discs with duplicate track numbers are skipped, since they
+    /// probably mix songs of different releases.
+    pub fn infer_totals(&mut self) {
+        let mut total_tracks = Vec::new();
+        let mut total_discs = Vec::new();
+        for a in self.artists.iter() {
+            for r in a.releases.iter() {
+                let disc_numbers = r.songs.iter().map(|s| (s.disc_number, s.total_discs));
+                if let Some(t) = infer_total(disc_numbers) {
+                    total_discs.extend(
+                        r.songs.iter().filter(|s| s.total_discs.is_none()).map(|s| (*s, t)),
+                    );
+                }
+
+                for (_, songs) in discs(r) {
+                    if !duplicate_tracks(&songs).is_empty() {
+                        continue;
+                    }
+                    if let Some(t) =
+                        infer_total(songs.iter().map(|s| (s.track_number, s.total_tracks)))
+                    {
+                        total_tracks.extend(
+                            songs.iter().filter(|s| s.total_tracks.is_none()).map(|s| (*s, t)),
+                        );
+                    }
+                }
+            }
+        }
+
+        for (s, t) in total_tracks {
+            if !self.is_updated(s, |tu| !tu.total_tracks.is_unchanged()) {
+                self.update_tag(s, |tu| tu.total_tracks = Value::Update(t));
+            }
+        }
+        for (s, t) in total_discs {
+            if !self.is_updated(s, |tu| !tu.total_discs.is_unchanged()) {
+                self.update_tag(s, |tu| tu.total_discs = Value::Update(t));
+            }
+        }
+    }
+
+    pub(crate) fn is_updated(&self, song: &Song, f: impl FnOnce(&TagUpdate) -> bool) -> bool {
+        let update = self.updates.iter().find(|o| o.song == song);
+        update.and_then(|o| o.tag_update.as_ref()).map(f).unwrap_or(false)
+    }
+}
+
+/// Songs that share a total tracks or total discs value.
+pub type Totals<'a> = (Vec<&'a Song>, Option<u16>);
+
+/// A problem with the track numbers of a disc.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NumberingIssue {
+    /// Track numbers that no song of the disc has.
+    Gaps { disc: Option<u16>, tracks: Vec<u16> },
+    /// Track numbers that multiple songs of the disc have.
+    Duplicates { disc: Option<u16>, tracks: Vec<u16> },
+}
+
+/// Whether two release artists are probably the same, because they only differ in case,
+/// diacritics, punctuation, `&` and `and` or a typo. Artists with different MusicBrainz IDs
+/// aren't.
+pub(crate) fn similar_artists(a: &ReleaseArtists, b: &ReleaseArtists) -> bool {
+    a.names != b.names
+        && (a.ids.is_empty() || b.ids.is_empty() || a.ids == b.ids)
+        && similar(&a.names.join(", "), &b.names.join(", "))
+}
+
+/// Groups of differently named releases of an artist that are probably the same, because they
+/// only differ in case, whitespace, bracket style or an edition suffix like `(Deluxe)` or
+/// `[Remastered]`. Releases of different MusicBrainz release groups aren't.
+pub(crate) fn similar_releases<'r, 'a>(
+    artists: &'r ReleaseArtists<'a>,
+) -> Vec<Vec<&'r Release<'a>>> {
+    let mut groups: Vec<(String, Vec<&Release<'a>>)> = Vec::new();
+    for r in artists.releases.iter() {
+        let key = fold_release(r.name);
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, g)) => g.push(r),
+            None => groups.push((key, vec![r])),
+        }
+    }
+
+    // Pressings with the same name are kept, but need another name to be listed
+    groups
+        .into_iter()
+        .map(|(_, g)| g)
+        .filter(|g| g.iter().any(|r| r.name != g[0].name))
+        .filter(|g| {
+            let songs = g.iter().flat_map(|r| r.songs.iter());
+            let mut group_ids = songs.filter_map(|s| s.mb_release_group_id.as_ref());
+            match group_ids.next() {
+                Some(id) => group_ids.all(|i| i == id),
+                None => true,
+            }
+        })
+        .collect()
+}
+
+/// The distinct names of releases.
+pub(crate) fn release_names<'a>(releases: &[&Release<'a>]) -> Vec<&'a str> {
+    let mut names: Vec<&str> = Vec::new();
+    for r in releases.iter() {
+        if !names.contains(&r.name) {
+            names.push(r.name);
+        }
+    }
+    names
+}
+
+/// The distinct dates of a release, if its songs have different years or some of them are
+/// missing a date.
+pub(crate) fn inconsistent_dates(release: &Release, kind: DateKind) -> Option<Vec<Date>> {
+    let mut dates: Vec<Date> = release.songs.iter().filter_map(|s| s.date(kind)).collect();
+    dates.sort();
+    dates.dedup();
+
+    let missing = release.songs.iter().any(|s| s.date(kind).is_none());
+    let years_differ = dates.windows(2).any(|w| w[0].year != w[1].year);
+    match !dates.is_empty() && (missing || years_differ) {
+        true => Some(dates),
+        false => None,
+    }
+}
+
+/// Releases that are probably compilations, but aren't flagged as one, with the release artists
+/// their songs were grouped by. Either their songs are spread over multiple release artists
+/// because they have no album artist, or the release artist is named like `Various Artists`.
+/// Releases are matched by their MusicBrainz ID or by their name and directory.
+pub(crate) fn compilation_candidates<'a>(
+    artists: &[ReleaseArtists<'a>],
+) -> Vec<(Release<'a>, Vec<&'a [String]>)> {
+    let mut groups: Vec<(Release<'a>, Vec<&'a [String]>)> = Vec::new();
+    for a in artists.iter() {
+        for r in a.releases.iter() {
+            let dir = r.songs[0].path.parent();
+            let group = groups.iter_mut().find(|(g, _)| match (g.id, r.id) {
+                (Some(a), Some(b)) => a == b,
+                _ => g.name == r.name && g.songs[0].path.parent() == dir,
+            });
+
+            match group {
+                Some((g, artists)) => {
+                    g.songs.extend_from_slice(&r.songs);
+                    artists.push(a.names);
+                }
+                None => groups.push((r.clone(), vec![a.names])),
+            }
+        }
+    }
+
+    groups.retain(|(release, artists)| {
+        let various = artists.iter().flat_map(|a| a.iter()).any(|n| is_various_artists(n));
+        !release.songs.iter().any(|s| s.compilation) && (artists.len() > 1 || various)
+    });
+    groups
+}
+
+/// The audio properties that differ between the songs of a release.
+pub(crate) fn property_differences(release: &Release) -> Vec<Property> {
+    let properties: Vec<&AudioProperties> =
+        release.songs.iter().map(|s| &s.properties).filter(|p| p.codec.is_some()).collect();
+
+    let mut differences = Vec::new();
+    for (i, p) in properties.iter().enumerate() {
+        for d in properties[i + 1..].iter().flat_map(|o| p.differences(o)) {
+            if !differences.contains(&d) {
+                differences.push(d);
+            }
+        }
+    }
+    differences
+}
+
+/// The gaps and duplicates in the track numbers of each disc of a release.
+pub(crate) fn numbering_issues(release: &Release) -> Vec<NumberingIssue> {
+    let mut issues = Vec::new();
+    for (disc, songs) in discs(release) {
+        let duplicates = duplicate_tracks(&songs);
+        if !duplicates.is_empty() {
+            issues.push(NumberingIssue::Duplicates { disc, tracks: duplicates });
+        }
+
+        let last = songs.iter().flat_map(|s| [s.track_number, s.total_tracks]).flatten().max();
+        let missing: Vec<u16> = (1..=last.unwrap_or(0))
+            .filter(|n| !songs.iter().any(|s| s.track_number == Some(*n)))
+            .collect();
+        if !missing.is_empty() && missing.len() < last.unwrap_or(0) as usize {
+            issues.push(NumberingIssue::Gaps { disc, tracks: missing });
+        }
+    }
+    issues
+}
+
+/// Groups the songs of a release by their disc number.
+pub(crate) fn discs<'a>(release: &Release<'a>) -> Vec<(Option<u16>, Vec<&'a Song>)> {
+    let mut discs: Vec<(Option<u16>, Vec<&'a Song>)> = Vec::new();
+    for s in release.songs.iter() {
+        match discs.iter_mut().find(|(d, _)| *d == s.disc_number) {
+            Some((_, songs)) => songs.push(s),
+            None => discs.push((s.disc_number, vec![s])),
+        }
+    }
+    discs
+}
+
+pub(crate) fn group_totals<'a>(
+    songs: &[&'a Song],
+    total: impl Fn(&Song) -> Option<u16>,
+) -> Vec<Totals<'a>> {
+    let mut totals: Vec<Totals<'a>> = Vec::new();
+    for s in songs.iter() {
+        match totals.iter_mut().find(|(_, t)| *t == total(s)) {
+            Some((songs, _)) => songs.push(s),
+            None => totals.push((vec![s], total(s))),
+        }
+    }
+    totals
+}
+
+/// The highest number or total of the songs.
+pub(crate) fn highest(songs: &[&Song], numbers: impl Fn(&Song) -> [Option<u16>; 2]) -> Value<u16> {
+    match songs.iter().flat_map(|s| numbers(s)).flatten().max() {
+        Some(n) => Value::Update(n),
+        None => Value::Unchanged,
+    }
+}
+
+/// The total that all songs that have one agree on, otherwise the highest number if no song has
+/// a total.
+fn infer_total(numbers: impl Iterator<Item = (Option<u16>, Option<u16>)>) -> Option<u16> {
+    let (numbers, totals): (Vec<Option<u16>>, Vec<Option<u16>>) = numbers.unzip();
+    let mut totals: Vec<u16> = totals.into_iter().flatten().collect();
+    totals.sort_unstable();
+    totals.dedup();
+    match totals[..] {
+        [t] => Some(t),
+        [] => numbers.into_iter().flatten().max(),
+        _ => None,
+    }
+}
+
+fn duplicate_tracks(songs: &[&Song]) -> Vec<u16> {
+    let mut tracks: Vec<u16> = songs.iter().filter_map(|s| s.track_number).collect();
+    tracks.sort_unstable();
+    let mut duplicates: Vec<u16> =
+        tracks.windows(2).filter(|w| w[0] == w[1]).map(|w| w[0]).collect();
+    duplicates.dedup();
+    duplicates
+}
+
+/// Whether the gain is the same as it would be written to the tag.
+fn same_gain(old: Option<ReplayGain>, new: ReplayGain) -> bool {
+    match old {
+        Some(o) => o.gain_str() == new.gain_str() && o.peak_str() == new.peak_str(),
+        None => false,
+    }
+}
+
+fn is_various_artists(name: &str) -> bool {
+    ["Various Artists", "Various", "VA", "V.A."].iter().any(|v| name.eq_ignore_ascii_case(v))
 }
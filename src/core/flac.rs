@@ -0,0 +1,168 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::fs::replace_file;
+use crate::vorbis::VorbisComment;
+
+const MARKER: &[u8; 4] = b"fLaC";
+const PADDING_LEN: usize = 4096;
+const MAX_BLOCK_LEN: usize = 0xFF_FFFF;
+
+const STREAMINFO: u8 = 0;
+const PADDING: u8 = 1;
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tag {
+    pub comment: VorbisComment,
+    pub has_picture: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Block {
+    kind: u8,
+    data: Vec<u8>,
+}
+
+/// The metadata section of a FLAC file.
+struct Metadata {
+    /// The start of the `fLaC` marker, some files are prefixed with an ID3 tag.
+    start: u64,
+    /// The end of the last metadata block, where the audio frames begin.
+    end: u64,
+    blocks: Vec<Block>,
+}
+
+impl Tag {
+    pub fn read_from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let meta = read_metadata(&mut file)?;
+
+        let mut tag = Self::default();
+        for b in meta.blocks.iter() {
+            match b.kind {
+                VORBIS_COMMENT => tag.comment = VorbisComment::parse(&b.data)?,
+                PICTURE => tag.has_picture = true,
+                _ => (),
+            }
+        }
+
+        Ok(tag)
+    }
+
+    /// Writes the vorbis comment, the existing padding is used if the new metadata fits,
+    /// otherwise the file is replaced by a rewritten copy.
+    pub fn write_to_path(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let meta = read_metadata(&mut file)?;
+
+        let comment = self.comment.to_bytes();
+        if comment.len() > MAX_BLOCK_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "vorbis comment too long"));
+        }
+
+        let mut blocks: Vec<Block> =
+            meta.blocks.into_iter().filter(|b| b.kind != PADDING).collect();
+        match blocks.iter_mut().find(|b| b.kind == VORBIS_COMMENT) {
+            Some(b) => b.data = comment,
+            None => {
+                let pos = blocks.iter().position(|b| b.kind == STREAMINFO).map_or(0, |i| i + 1);
+                blocks.insert(pos, Block { kind: VORBIS_COMMENT, data: comment });
+            }
+        }
+
+        let old_len = (meta.end - meta.start) as usize;
+        let new_len = MARKER.len() + blocks.iter().map(|b| 4 + b.data.len()).sum::<usize>();
+
+        if new_len == old_len || new_len + 4 <= old_len {
+            if new_len < old_len {
+                blocks.push(Block { kind: PADDING, data: vec![0; old_len - new_len - 4] });
+            }
+
+            file.seek(SeekFrom::Start(meta.start))?;
+            file.write_all(&encode_metadata(&blocks))?;
+        } else {
+            blocks.push(Block { kind: PADDING, data: vec![0; PADDING_LEN] });
+
+            let mut prefix = vec![0; meta.start as usize];
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut prefix)?;
+
+            file.seek(SeekFrom::Start(meta.end))?;
+            replace_file(path, |new| {
+                new.write_all(&prefix)?;
+                new.write_all(&encode_metadata(&blocks))?;
+                io::copy(&mut file, new)?;
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+fn read_metadata(file: &mut File) -> io::Result<Metadata> {
+    let start = skip_id3(file)?;
+
+    let mut marker = [0; 4];
+    file.read_exact(&mut marker)?;
+    if &marker != MARKER {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "missing fLaC marker"));
+    }
+
+    let mut blocks = Vec::new();
+    loop {
+        let mut header = [0; 4];
+        file.read_exact(&mut header)?;
+
+        let last = header[0] & 0x80 != 0;
+        let kind = header[0] & 0x7F;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+
+        let mut data = vec![0; len];
+        file.read_exact(&mut data)?;
+        blocks.push(Block { kind, data });
+
+        if last {
+            break;
+        }
+    }
+
+    let end = file.stream_position()?;
+
+    Ok(Metadata { start, end, blocks })
+}
+
+fn encode_metadata(blocks: &[Block]) -> Vec<u8> {
+    let mut data = MARKER.to_vec();
+
+    for (i, b) in blocks.iter().enumerate() {
+        let len = (b.data.len() as u32).to_be_bytes();
+        let last = if i + 1 == blocks.len() { 0x80 } else { 0 };
+
+        data.extend_from_slice(&[last | b.kind, len[1], len[2], len[3]]);
+        data.extend_from_slice(&b.data);
+    }
+
+    data
+}
+
+/// Skips an ID3v2 tag at the start of the file if present and returns the position after it.
+fn skip_id3(file: &mut File) -> io::Result<u64> {
+    let mut header = [0; 10];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+
+    if &header[0..3] != b"ID3" {
+        file.seek(SeekFrom::Start(0))?;
+        return Ok(0);
+    }
+
+    let size = header[6..10].iter().fold(0u64, |s, b| (s << 7) | (*b & 0x7F) as u64);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+
+    file.seek(SeekFrom::Start(10 + size + footer))
+}
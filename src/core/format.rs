@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::fs::lowercase_extension;
+
+/// The container format of an audio file, which decides the tag backend that is used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Mp3,
+    Mp4,
+    Flac,
+    Ogg,
+    Opus,
+    Wav,
+    Aiff,
+    Ape,
+    WavPack,
+    Musepack,
+}
+
+impl Format {
+    /// Detects the format by the content of the file, or by its extension if the content isn't
+    /// recognized.
+    pub fn detect(path: &Path) -> Option<Self> {
+        match Self::sniff(path) {
+            Ok(Some(f)) => Some(f),
+            _ => lowercase_extension(path).and_then(|e| Self::from_extension(&e)),
+        }
+    }
+
+    /// Detects the format by the magic bytes at the start of the file.
+    pub fn sniff(path: &Path) -> io::Result<Option<Self>> {
+        let mut file = File::open(path)?;
+        let mut header = [0; 36];
+        let len = read_up_to(&mut file, &mut header)?;
+        let header = &header[..len];
+
+        if header.starts_with(b"ID3") && header.len() >= 10 {
+            // FLAC and APE files are sometimes prefixed with an ID3 tag
+            let size = header[6..10].iter().fold(0u64, |s, b| (s << 7) | (*b & 0x7F) as u64);
+            let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+            let mut marker = [0; 4];
+            file.seek(SeekFrom::Start(10 + size + footer))?;
+            let len = read_up_to(&mut file, &mut marker)?;
+
+            return Ok(match &marker[..len] {
+                b"fLaC" => Some(Self::Flac),
+                b"MAC " => Some(Self::Ape),
+                _ => Some(Self::Mp3),
+            });
+        }
+
+        let format = if header.get(4..8) == Some(b"ftyp") {
+            Self::Mp4
+        } else if header.starts_with(b"fLaC") {
+            Self::Flac
+        } else if header.starts_with(b"OggS") {
+            // The first packet of the stream starts after the page header with one segment
+            match header.get(28..36) == Some(b"OpusHead") {
+                true => Self::Opus,
+                false => Self::Ogg,
+            }
+        } else if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WAVE") {
+            Self::Wav
+        } else if header.starts_with(b"FORM")
+            && matches!(header.get(8..12), Some(b"AIFF") | Some(b"AIFC"))
+        {
+            Self::Aiff
+        } else if header.starts_with(b"MAC ") {
+            Self::Ape
+        } else if header.starts_with(b"wvpk") {
+            Self::WavPack
+        } else if header.starts_with(b"MPCK") || header.starts_with(b"MP+") {
+            Self::Musepack
+        } else if is_mpeg_sync(header) {
+            Self::Mp3
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(format))
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        let format = match ext {
+            "mp3" => Self::Mp3,
+            "m4a" | "m4b" | "m4p" | "m4v" | "mp4" => Self::Mp4,
+            "flac" => Self::Flac,
+            "ogg" | "oga" => Self::Ogg,
+            "opus" => Self::Opus,
+            "wav" => Self::Wav,
+            "aif" | "aiff" => Self::Aiff,
+            "ape" => Self::Ape,
+            "wv" => Self::WavPack,
+            "mpc" => Self::Musepack,
+            _ => return None,
+        };
+
+        Some(format)
+    }
+
+    /// The extensions that are valid for this format, the first one is used when fixing a wrong
+    /// extension.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Self::Mp3 => &["mp3"],
+            Self::Mp4 => &["m4a", "m4b", "m4p", "m4v", "mp4"],
+            Self::Flac => &["flac"],
+            Self::Ogg => &["ogg", "oga"],
+            Self::Opus => &["opus", "ogg", "oga"],
+            Self::Wav => &["wav"],
+            Self::Aiff => &["aiff", "aif"],
+            Self::Ape => &["ape"],
+            Self::WavPack => &["wv"],
+            Self::Musepack => &["mpc"],
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Mp3 => "MP3",
+            Self::Mp4 => "MPEG-4",
+            Self::Flac => "FLAC",
+            Self::Ogg => "Ogg",
+            Self::Opus => "Opus",
+            Self::Wav => "WAV",
+            Self::Aiff => "AIFF",
+            Self::Ape => "Monkey's Audio",
+            Self::WavPack => "WavPack",
+            Self::Musepack => "Musepack",
+        }
+    }
+
+    /// Whether the extension of the path is valid for this format.
+    pub fn matches_extension(self, path: &Path) -> bool {
+        match lowercase_extension(path) {
+            Some(e) => self.extensions().contains(&e.as_str()),
+            None => false,
+        }
+    }
+}
+
+/// An MPEG audio frame header starts with 11 set bits, the layer bits must not be zero, which
+/// would be an AAC ADTS stream.
+fn is_mpeg_sync(header: &[u8]) -> bool {
+    match header {
+        [0xFF, b, ..] => b & 0xE0 == 0xE0 && b & 0x06 != 0,
+        _ => false,
+    }
+}
+
+fn read_up_to(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
@@ -1,11 +1,9 @@
 use std::ffi::OsStr;
 use std::fs;
-use std::io;
+use std::io::{self, BufRead};
 use std::path::PathBuf;
 use std::{error, path::Path};
 
-use regex::Regex;
-
 use crate::update::TagUpdate;
 use crate::Song;
 
@@ -36,11 +34,14 @@ pub struct SongOperation<'a> {
     pub song: &'a Song,
     pub tag_update: Option<TagUpdate>,
     pub new_path: Option<PathBuf>,
+    /// Whether a file that exists at the new path may be overwritten.
+    pub overwrite: bool,
 }
 
 impl SongOperation<'_> {
     pub fn execute(&self, op_type: FileOpType) -> Result<(), Box<dyn error::Error>> {
         if let Some(new) = &self.new_path {
+            check_target(new, self.overwrite)?;
             match op_type {
                 FileOpType::Copy => {
                     fs::copy(&self.song.path, new)?;
@@ -66,10 +67,13 @@ impl SongOperation<'_> {
 pub struct FileOperation<'a> {
     pub old_path: &'a Path,
     pub new_path: PathBuf,
+    /// Whether a file that exists at the new path may be overwritten.
+    pub overwrite: bool,
 }
 
 impl FileOperation<'_> {
     pub fn execute(&self, op_type: FileOpType) -> Result<(), Box<dyn error::Error>> {
+        check_target(&self.new_path, self.overwrite)?;
         match op_type {
             FileOpType::Copy => {
                 fs::copy(&self.old_path, &self.new_path)?;
@@ -82,12 +86,72 @@ impl FileOperation<'_> {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// Refuses to overwrite a file that appeared after the changes were planned.
+fn check_target(path: &Path, overwrite: bool) -> io::Result<()> {
+    match !overwrite && path.exists() {
+        true => Err(io::Error::new(io::ErrorKind::AlreadyExists, "the file already exists")),
+        false => Ok(()),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum FileOpType {
+    #[default]
     Move,
     Copy,
 }
 
+/// What to do with a file that already exists at the new path of a song or file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverwritePolicy {
+    #[default]
+    Never,
+    /// Only overwrite files with the same content.
+    IfIdentical,
+    /// Only overwrite files that were modified before the source.
+    IfNewer,
+    Always,
+}
+
+impl OverwritePolicy {
+    /// Whether the file at `target` may be overwritten by the one at `source`.
+    pub fn allows(self, source: &Path, target: &Path) -> bool {
+        match self {
+            Self::Never => false,
+            Self::IfIdentical => same_content(source, target).unwrap_or(false),
+            Self::IfNewer => {
+                let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified());
+                match (modified(source), modified(target)) {
+                    (Ok(s), Ok(t)) => s > t,
+                    _ => false,
+                }
+            }
+            Self::Always => true,
+        }
+    }
+}
+
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+
+    let mut a = io::BufReader::new(fs::File::open(a)?);
+    let mut b = io::BufReader::new(fs::File::open(b)?);
+    loop {
+        let (buf_a, buf_b) = (a.fill_buf()?, b.fill_buf()?);
+        let len = buf_a.len().min(buf_b.len());
+        if len == 0 {
+            return Ok(buf_a.len() == buf_b.len());
+        }
+        if buf_a[..len] != buf_b[..len] {
+            return Ok(false);
+        }
+        a.consume(len);
+        b.consume(len);
+    }
+}
+
 impl From<bool> for FileOpType {
     fn from(copy: bool) -> Self {
         match copy {
@@ -97,54 +161,80 @@ impl From<bool> for FileOpType {
     }
 }
 
-lazy_static::lazy_static! {
-    static ref RE: Regex = Regex::new(r#"[<>:"/\|?*]"#).unwrap();
-}
+const MUSIC_FILE_EXTENSIONS: [&str; 13] =
+    ["aif", "aiff", "ape", "flac", "m4a", "m4b", "mp3", "mpc", "oga", "ogg", "opus", "wav", "wv"];
+const AUDIOBOOK_FILE_EXTENSIONS: [&str; 2] = ["m4a", "m4b"];
+const IMAGE_FILE_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+const SIDECAR_FILE_EXTENSIONS: [&str; 4] = ["cue", "log", "lrc", "nfo"];
 
-#[inline]
-pub fn valid_os_str_dots(str: &str) -> String {
-    let mut s = RE.replace_all(str, "").to_string();
+/// The recognized file extensions, they are compared case-insensitively.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extensions {
+    pub audio: Vec<String>,
+    /// The audio extensions that are used in audiobook mode.
+    pub audiobook: Vec<String>,
+    pub image: Vec<String>,
+    /// Files like cue sheets, rip logs or lyrics which are moved along with the songs.
+    pub sidecar: Vec<String>,
+}
 
-    if s.starts_with('.') {
-        // This is safe because we know that the first byte has to be present and is character of 1 byte length.
-        unsafe {
-            s.as_bytes_mut()[0] = b'_';
+impl Default for Extensions {
+    fn default() -> Self {
+        let owned = |e: &[&str]| e.iter().map(|e| e.to_string()).collect();
+        Self {
+            audio: owned(&MUSIC_FILE_EXTENSIONS),
+            audiobook: owned(&AUDIOBOOK_FILE_EXTENSIONS),
+            image: owned(&IMAGE_FILE_EXTENSIONS),
+            sidecar: owned(&SIDECAR_FILE_EXTENSIONS),
         }
     }
-    if s.ends_with('.') {
-        s.pop();
-        s.push('_');
-    }
-
-    s
 }
 
-#[inline]
-pub fn valid_os_str(str: &str) -> String {
-    RE.replace_all(str, "").trim().to_string()
-}
-
-const MUSIC_FILE_EXTENSIONS: [&str; 2] = ["m4a", "mp3"];
+impl Extensions {
+    /// Strips a leading dot and lowercases the extension, so it can be added to a set.
+    pub fn normalize(ext: &str) -> String {
+        ext.trim().trim_start_matches('.').to_lowercase()
+    }
 
-#[inline]
-pub fn is_music_extension(s: &OsStr) -> bool {
-    for e in &MUSIC_FILE_EXTENSIONS {
-        if s.eq(*e) {
-            return true;
+    pub fn add(set: &mut Vec<String>, ext: &str) {
+        let ext = Self::normalize(ext);
+        if !ext.is_empty() && !set.contains(&ext) {
+            set.push(ext);
         }
     }
 
-    false
+    /// Returns the configured extension matching the one of a file.
+    pub fn find<'a>(set: &'a [String], ext: &OsStr) -> Option<&'a str> {
+        let ext = ext.to_str()?;
+        set.iter().find(|e| e.eq_ignore_ascii_case(ext)).map(|e| e.as_str())
+    }
+}
+
+/// The lowercase extension of a path, used to dispatch on the file format.
+pub(crate) fn lowercase_extension(path: &Path) -> Option<String> {
+    path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase())
 }
 
-const IMAGE_FILE_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
-#[inline]
-pub fn is_image_extension(s: &OsStr) -> bool {
-    for e in &IMAGE_FILE_EXTENSIONS {
-        if s.eq(*e) {
-            return true;
+/// Writes a new version of a file to a temporary file next to it, which then replaces it, so
+/// the file isn't lost if writing is interrupted. The permissions of the file are kept.
+pub(crate) fn replace_file(
+    path: &Path,
+    write: impl FnOnce(&mut fs::File) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let result = fs::File::create(&tmp).and_then(|mut file| {
+        write(&mut file)?;
+        file.set_permissions(fs::metadata(path)?.permissions())?;
+        file.sync_all()
+    });
+    match result.and_then(|_| fs::rename(&tmp, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
         }
     }
-
-    false
 }
@@ -0,0 +1,90 @@
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Words in brackets or after a dash that mark an edition of a release.
+const EDITION_WORDS: [&str; 11] = [
+    "anniversary",
+    "bonus",
+    "collector",
+    "collectors",
+    "deluxe",
+    "edition",
+    "expanded",
+    "limited",
+    "remaster",
+    "remastered",
+    "reissue",
+];
+
+/// Folds a name for comparisons, `Beyoncé & The Band` becomes `beyonce and the band`. Case and
+/// diacritics are folded, `&` is spelled out and punctuation is removed.
+pub(crate) fn fold(name: &str) -> String {
+    let mut folded = String::with_capacity(name.len());
+    for c in name.nfkd().filter(|c| !is_combining_mark(*c)).flat_map(char::to_lowercase) {
+        match c {
+            '&' | '+' => folded.push_str(" and "),
+            c if c.is_alphanumeric() => folded.push(c),
+            c if c.is_whitespace() || c == '-' || c == '_' => folded.push(' '),
+            _ => (),
+        }
+    }
+    folded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The number of inserted, removed, substituted or swapped adjacent characters that turn `a`
+/// into `b`.
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut previous: Vec<usize> = Vec::new();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut next = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            next[j] = (row[j - 1] + cost).min(row[j] + 1).min(next[j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                next[j] = next[j].min(previous[j - 2] + 1);
+            }
+        }
+        previous = std::mem::replace(&mut row, next);
+    }
+    row[b.len()]
+}
+
+/// Whether two names probably mean the same, because they only differ in case, diacritics,
+/// punctuation or a typo. Short names have to be equal after folding.
+pub(crate) fn similar(a: &str, b: &str) -> bool {
+    let (a, b) = (fold(a), fold(b));
+    let max_distance = match a.chars().count().min(b.chars().count()) {
+        0..=4 => 0,
+        5..=9 => 1,
+        _ => 2,
+    };
+    edit_distance(&a, &b) <= max_distance
+}
+
+/// Folds a release name like [`fold`] and removes edition suffixes, so `Album`,
+/// `Album (Deluxe Edition)` and `album [2011 Remaster]` become `album`.
+pub(crate) fn fold_release(name: &str) -> String {
+    let mut name = name.trim();
+    while let Some(n) = strip_edition(name) {
+        name = n.trim_end();
+    }
+    fold(name)
+}
+
+/// Strips a trailing `(...)`, `[...]`, `{...}` or ` - ...` if it contains an edition word.
+fn strip_edition(name: &str) -> Option<&str> {
+    let start = match name.chars().last()? {
+        ')' => name.rfind('(')?,
+        ']' => name.rfind('[')?,
+        '}' => name.rfind('{')?,
+        _ => name.rfind(" - ")?,
+    };
+    let suffix = fold(&name[start..]);
+    let edition = suffix.split(' ').any(|w| EDITION_WORDS.contains(&w));
+    match edition && start > 0 {
+        true => Some(&name[..start]),
+        false => None,
+    }
+}
@@ -2,19 +2,74 @@ use std::path::{Path, PathBuf};
 
 use walkdir::WalkDir;
 
-use crate::fs::{is_image_extension, is_music_extension};
-use crate::{Metadata, Song};
+use crate::fs::Extensions;
+use crate::{mp4, AudioProperties, Format, Metadata, Song};
+
+/// What kind of library is organized.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mode {
+    #[default]
+    Music,
+    /// Only m4a and m4b files are indexed and laid out as `Author/Series/NN - Title/`.
+    Audiobook,
+}
+
+/// The rule that classified a file while indexing, containing the configured extension that
+/// matched.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rule {
+    Audio(String),
+    Image(String),
+    Sidecar(String),
+    /// The file has no extension, but its content was recognized as audio.
+    Content(Format),
+    /// The file didn't match any extension and is left alone.
+    Unmatched,
+}
 
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct MusicIndex {
     pub music_dir: PathBuf,
+    pub mode: Mode,
+    pub extensions: Extensions,
     pub songs: Vec<Song>,
     pub unknown: Vec<PathBuf>,
     pub images: Vec<PathBuf>,
+    pub sidecars: Vec<PathBuf>,
+    pub classifications: Vec<(PathBuf, Rule)>,
 }
 
 impl MusicIndex {
-    pub fn read(&mut self, f: &mut impl FnMut(&Path)) {
+    /// Classifies a file by its extension, songs are only recognized by the audio extensions of
+    /// the current mode.
+    pub fn classify(&self, path: &Path) -> Rule {
+        let extension = match path.extension() {
+            Some(e) => e,
+            None => {
+                return match Format::sniff(path) {
+                    Ok(Some(Format::Mp4)) => Rule::Content(Format::Mp4),
+                    Ok(Some(f)) if self.mode == Mode::Music => Rule::Content(f),
+                    _ => Rule::Unmatched,
+                };
+            }
+        };
+        let audio = match self.mode {
+            Mode::Music => &self.extensions.audio,
+            Mode::Audiobook => &self.extensions.audiobook,
+        };
+
+        if let Some(e) = Extensions::find(audio, extension) {
+            Rule::Audio(e.to_owned())
+        } else if let Some(e) = Extensions::find(&self.extensions.image, extension) {
+            Rule::Image(e.to_owned())
+        } else if let Some(e) = Extensions::find(&self.extensions.sidecar, extension) {
+            Rule::Sidecar(e.to_owned())
+        } else {
+            Rule::Unmatched
+        }
+    }
+
+    pub fn read(&mut self, f: &mut impl FnMut(&Path, &Rule)) {
         let iter = WalkDir::new(&self.music_dir)
             .follow_links(true)
             .into_iter()
@@ -24,65 +79,115 @@ impl MusicIndex {
             .map(|e| e.into_path());
 
         for p in iter {
-            f(&p);
-
-            let extension = match p.extension() {
-                Some(e) => e,
-                None => continue,
-            };
-
-            if is_music_extension(extension) {
-                let m = Metadata::read_from(&p);
-
-                let release_artists = match m.release_artists() {
-                    Some(a) => a,
-                    None => {
-                        self.unknown.push(p.clone());
-                        continue;
-                    }
-                };
+            let rule = self.classify(&p);
+            f(&p, &rule);
 
-                let song_artists = match m.song_artists() {
-                    Some(a) => a,
-                    None => {
-                        self.unknown.push(p.clone());
-                        continue;
-                    }
-                };
+            match &rule {
+                Rule::Audio(_) | Rule::Content(_) => self.read_song(&p),
+                Rule::Image(_) => self.images.push(p.clone()),
+                Rule::Sidecar(_) => self.sidecars.push(p.clone()),
+                Rule::Unmatched => (),
+            }
 
-                let release = match &m.release {
-                    Some(rl) => rl,
-                    None => {
-                        self.unknown.push(p.clone());
-                        continue;
-                    }
-                };
+            self.classifications.push((p, rule));
+        }
+    }
 
-                let title = match &m.title {
-                    Some(t) => t,
-                    None => {
-                        self.unknown.push(p.clone());
-                        continue;
-                    }
-                };
+    fn read_song(&mut self, p: &Path) {
+        let format = Format::detect(p);
+        let mut m = format.map(|f| Metadata::read_as(p, f)).unwrap_or_default();
+        let properties = format.and_then(|f| AudioProperties::read(p, f).ok()).unwrap_or_default();
+        if self.mode == Mode::Audiobook {
+            if format == Some(Format::Mp4) {
+                m.chapters = mp4::read_chapters(p).unwrap_or_default();
+            }
 
-                self.songs.push(Song {
-                    track_number: m.track_number,
-                    total_tracks: m.total_tracks,
-                    disc_number: m.disc_number,
-                    total_discs: m.total_discs,
-                    release_artists: release_artists.to_owned(),
-                    artists: song_artists.to_owned(),
-                    release: release.to_owned(),
-                    title: title.to_owned(),
-                    has_artwork: m.has_artwork,
-                    path: p.to_owned(),
-                });
-            } else if is_image_extension(extension) {
-                // TODO move images to new dir
-                self.images.push(p);
+            // Books are often tagged with only one of both
+            if m.release.is_none() {
+                m.release = m.title.clone();
+            } else if m.title.is_none() {
+                m.title = m.release.clone();
             }
         }
+
+        let release_artists = match m.release_artists() {
+            Some(a) => a,
+            None => {
+                self.unknown.push(p.to_owned());
+                return;
+            }
+        };
+
+        let song_artists = match m.song_artists() {
+            Some(a) => a,
+            None => {
+                self.unknown.push(p.to_owned());
+                return;
+            }
+        };
+
+        let release = match &m.release {
+            Some(rl) => rl,
+            None => {
+                self.unknown.push(p.to_owned());
+                return;
+            }
+        };
+
+        let title = match &m.title {
+            Some(t) => t,
+            None => {
+                self.unknown.push(p.to_owned());
+                return;
+            }
+        };
+
+        self.songs.push(Song {
+            track_number: m.track_number,
+            total_tracks: m.total_tracks,
+            disc_number: m.disc_number,
+            total_discs: m.total_discs,
+            disc_subtitle: m.disc_subtitle.clone(),
+            release_artists: release_artists.to_owned(),
+            has_release_artists: !m.release_artists.is_empty(),
+            artists: song_artists.to_owned(),
+            release: release.to_owned(),
+            title: title.to_owned(),
+            release_artists_sort: match m.release_artists.is_empty() {
+                true => m.artists_sort.clone(),
+                false => m.release_artists_sort.clone(),
+            },
+            artists_sort: m.artists_sort.clone(),
+            release_sort: m.release_sort.clone(),
+            title_sort: m.title_sort.clone(),
+            release_date: m.release_date,
+            original_date: m.original_date,
+            track_gain: m.track_gain,
+            release_gain: m.release_gain,
+            mb_recording_id: m.mb_recording_id.clone(),
+            mb_release_id: m.mb_release_id.clone(),
+            mb_release_group_id: m.mb_release_group_id.clone(),
+            mb_artist_ids: m.mb_artist_ids.clone(),
+            mb_release_artist_ids: match m.release_artists.is_empty() {
+                true => m.mb_artist_ids.clone(),
+                false => m.mb_release_artist_ids.clone(),
+            },
+            genres: m.genres.clone(),
+            composer: m.composer.clone(),
+            conductor: m.conductor.clone(),
+            comment: m.comment.clone(),
+            grouping: m.grouping.clone(),
+            compilation: m.compilation,
+            has_artwork: m.has_artwork,
+            has_ape_tag: m.has_ape_tag,
+            narrator: m.narrator.clone(),
+            series: m.series.clone(),
+            series_part: m.series_part,
+            chapters: m.chapters.clone(),
+            properties,
+            path: p.to_owned(),
+            format,
+        });
     }
 }
 
@@ -1,15 +1,39 @@
+mod ape;
 mod changes;
 mod checks;
 mod cleanup;
+mod flac;
+mod format;
 mod fs;
+mod fuzzy;
+mod genre;
 mod index;
+mod loudness;
 mod meta;
+mod mp4;
+mod ogg;
+mod properties;
+mod resolutions;
+mod riff;
+mod rules;
+mod sanitize;
+mod sort;
+mod template;
 mod update;
+mod vorbis;
 
-pub use changes::Changes;
-pub use checks::Checks;
+pub use changes::{ChangeOptions, Changes, CollisionPolicy, Conflict, ExistingFile};
+pub use checks::{Checks, NumberingIssue, Totals};
 pub use cleanup::Cleanup;
-pub use fs::{DirCreation, FileOpType, FileOperation, SongOperation};
-pub use index::MusicIndex;
-pub use meta::{Metadata, Release, ReleaseArtists, Song};
-pub use update::{TagUpdate, Value};
+pub use format::Format;
+pub use fs::{DirCreation, Extensions, FileOpType, FileOperation, OverwritePolicy, SongOperation};
+pub use index::{Mode, MusicIndex, Rule};
+pub use loudness::Loudness;
+pub use meta::{Chapter, Date, DateKind, Metadata, Release, ReleaseArtists, ReplayGain, Song};
+pub use properties::{AudioProperties, Property};
+pub use resolutions::{ArtistNames, ArtistResolution, ReleaseResolution, Resolutions};
+pub use rules::{Check, CheckRegistry, Field, Finding, Proposal, Severity, Subject};
+pub use sanitize::{Filesystem, Normalization};
+pub use sort::Articles;
+pub use template::{Context, Template, TemplateError};
+pub use update::{ApePolicy, TagUpdate, Value};
@@ -0,0 +1,466 @@
+use std::convert::TryFrom;
+use std::error;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::path::Path;
+
+use symphonia::core::audio::{Channels, SampleBuffer, SignalSpec};
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS};
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::{FormatOptions, Packet};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::ReplayGain;
+
+/// The loudness ReplayGain 2.0 normalizes to.
+const REFERENCE_LUFS: f64 = -18.0;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+/// Taps of one phase of the interpolation filter used for the true peak.
+const PHASE_TAPS: usize = 12;
+/// Opus is always decoded at 48kHz.
+const OPUS_RATE: u32 = 48000;
+/// The frames of the longest Opus packet, 120ms at 48kHz.
+const OPUS_MAX_FRAMES: usize = 5760;
+
+/// The loudness of a song according to EBU R128 / ITU-R BS.1770.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Loudness {
+    /// The mean square of the K-weighted samples of each overlapping 400ms block.
+    blocks: Vec<f64>,
+    /// The true peak as a linear amplitude.
+    pub peak: f64,
+}
+
+impl Loudness {
+    /// Decodes the file and measures its loudness. Opus is decoded with libopus since
+    /// symphonia has no decoder for it, other codecs that aren't supported by symphonia return
+    /// an error.
+    pub fn analyze(path: &Path) -> Result<Self, Box<dyn error::Error>> {
+        let file = File::open(path)?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(e) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(e);
+        }
+
+        let probed = symphonia::default::get_probe().format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+        let mut reader = probed.format;
+        let track = reader
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or("no audio track")?;
+        let track_id = track.id;
+        let mut decoder = match track.codec_params.codec {
+            CODEC_TYPE_OPUS => {
+                let head = track.codec_params.extra_data.as_deref().ok_or("missing Opus header")?;
+                AudioDecoder::Opus(OpusDecoder::new(head)?)
+            }
+            _ => AudioDecoder::Symphonia(
+                symphonia::default::get_codecs()
+                    .make(&track.codec_params, &DecoderOptions::default())?,
+                None,
+            ),
+        };
+
+        let mut meter: Option<Meter> = None;
+        loop {
+            let packet = match reader.next_packet() {
+                Ok(p) => p,
+                Err(DecodeError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    break
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+
+            let (spec, samples) = match decoder.decode(&packet)? {
+                Some(d) => d,
+                None => continue,
+            };
+            meter.get_or_insert_with(|| Meter::new(spec.rate, spec.channels)).process(samples);
+        }
+
+        meter.map(Meter::finish).ok_or_else(|| "no audio data".into())
+    }
+
+    /// Combines the measurements of multiple songs, as if they were played back to back.
+    pub fn combine<'a>(songs: impl IntoIterator<Item = &'a Loudness>) -> Self {
+        let mut combined = Self::default();
+        for l in songs {
+            combined.blocks.extend_from_slice(&l.blocks);
+            combined.peak = combined.peak.max(l.peak);
+        }
+        combined
+    }
+
+    /// The gated integrated loudness in LUFS, `None` if the audio is silent.
+    pub fn integrated(&self) -> Option<f64> {
+        let gated: Vec<f64> =
+            self.blocks.iter().copied().filter(|e| lufs(*e) > ABSOLUTE_GATE).collect();
+        if gated.is_empty() {
+            return None;
+        }
+
+        let threshold = lufs(mean(&gated)) + RELATIVE_GATE;
+        let gated: Vec<f64> = gated.into_iter().filter(|e| lufs(*e) > threshold).collect();
+
+        Some(lufs(mean(&gated)))
+    }
+
+    pub fn replay_gain(&self) -> Option<ReplayGain> {
+        let loudness = self.integrated()?;
+        Some(ReplayGain { gain: REFERENCE_LUFS - loudness, peak: Some(self.peak) })
+    }
+}
+
+/// The interleaved samples of a packet.
+type Decoded<'a> = (SignalSpec, &'a [f32]);
+
+enum AudioDecoder {
+    Symphonia(Box<dyn Decoder>, Option<SampleBuffer<f32>>),
+    Opus(OpusDecoder),
+}
+
+impl AudioDecoder {
+    /// Decodes a packet into interleaved samples, corrupt packets are skipped like a player
+    /// would by returning `None`.
+    fn decode(&mut self, packet: &Packet) -> Result<Option<Decoded<'_>>, Box<dyn error::Error>> {
+        match self {
+            Self::Symphonia(decoder, buf) => {
+                let decoded = match decoder.decode(packet) {
+                    Ok(d) => d,
+                    Err(DecodeError::DecodeError(_)) => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+                let spec = *decoded.spec();
+                if spec.channels.count() == 0 {
+                    return Err("no audio channels".into());
+                }
+                let len = decoded.capacity() * spec.channels.count();
+                if !matches!(buf, Some(b) if b.capacity() >= len) {
+                    *buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+                }
+                let samples = buf.as_mut().unwrap();
+                samples.copy_interleaved_ref(decoded);
+                Ok(Some((spec, samples.samples())))
+            }
+            Self::Opus(decoder) => {
+                let spec = decoder.spec;
+                Ok(decoder.decode(&packet.data).map(|s| (spec, s)))
+            }
+        }
+    }
+}
+
+/// Decodes Opus streams of one or two channels with libopus.
+struct OpusDecoder {
+    decoder: audiopus::coder::Decoder,
+    spec: SignalSpec,
+    /// The samples at the start that only prime the decoder.
+    pre_skip: usize,
+    buf: Vec<f32>,
+}
+
+impl OpusDecoder {
+    /// Creates a decoder from the identification header, see RFC 7845 section 5.1.
+    fn new(head: &[u8]) -> Result<Self, Box<dyn error::Error>> {
+        if head.len() < 19 {
+            return Err("invalid Opus header".into());
+        }
+        // Streams of more than two channels consist of multiple Opus streams
+        let (channels, layout) = match (head[18], head[9]) {
+            (0, 1) => (audiopus::Channels::Mono, Channels::FRONT_LEFT),
+            (0, 2) => (audiopus::Channels::Stereo, Channels::FRONT_LEFT | Channels::FRONT_RIGHT),
+            _ => return Err("Opus streams with more than two channels can't be decoded".into()),
+        };
+        let pre_skip = u16::from_le_bytes([head[10], head[11]]);
+        let output_gain = i16::from_le_bytes([head[16], head[17]]);
+
+        let decoder = audiopus::coder::Decoder::new(audiopus::SampleRate::Hz48000, channels)?;
+        // The output gain of the header is applied on playback, the R128 gains are relative to it
+        decoder.set_gain(output_gain as i32)?;
+
+        Ok(Self {
+            decoder,
+            spec: SignalSpec::new(OPUS_RATE, layout),
+            pre_skip: pre_skip as usize,
+            buf: vec![0.0; OPUS_MAX_FRAMES * layout.count()],
+        })
+    }
+
+    /// Decodes a packet, `None` if it's corrupt.
+    fn decode(&mut self, data: &[u8]) -> Option<&[f32]> {
+        let channels = self.spec.channels.count();
+        let packet = audiopus::packet::Packet::try_from(data).ok()?;
+        let signals = audiopus::MutSignals::try_from(&mut self.buf[..]).ok()?;
+        let frames = self.decoder.decode_float(Some(packet), signals, false).ok()?;
+
+        let skip = self.pre_skip.min(frames);
+        self.pre_skip -= skip;
+        Some(&self.buf[skip * channels..frames * channels])
+    }
+}
+
+fn lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+struct Meter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    peaks: Vec<Interpolator>,
+    /// Samples per 100ms, a block consists of four of these.
+    quarter_len: usize,
+    quarter_pos: usize,
+    quarter_sum: f64,
+    quarters: Vec<f64>,
+    peak: f64,
+}
+
+impl Meter {
+    fn new(rate: u32, channels: Channels) -> Self {
+        // The LFE channel is ignored, surround channels are weighted by about +1.5 dB
+        let weights: Vec<f64> = channels
+            .iter()
+            .map(|c| match c {
+                Channels::LFE1 | Channels::LFE2 => 0.0,
+                Channels::SIDE_LEFT
+                | Channels::SIDE_RIGHT
+                | Channels::REAR_LEFT
+                | Channels::REAR_RIGHT => 1.41,
+                _ => 1.0,
+            })
+            .collect();
+        let count = weights.len();
+        let rate = rate as f64;
+        let factor = match rate {
+            r if r < 96000.0 => 4,
+            r if r < 192000.0 => 2,
+            _ => 1,
+        };
+
+        Self {
+            channels: count,
+            weights,
+            filters: vec![[Biquad::shelving(rate), Biquad::high_pass(rate)]; count],
+            peaks: vec![Interpolator::new(factor); count],
+            quarter_len: (rate / 10.0).round() as usize,
+            quarter_pos: 0,
+            quarter_sum: 0.0,
+            quarters: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (c, s) in frame.iter().enumerate() {
+                let s = *s as f64;
+                self.peak = self.peak.max(self.peaks[c].process(s));
+
+                let [shelving, high_pass] = &mut self.filters[c];
+                let k = high_pass.process(shelving.process(s));
+                self.quarter_sum += self.weights[c] * k * k;
+            }
+
+            self.quarter_pos += 1;
+            if self.quarter_pos == self.quarter_len {
+                self.quarters.push(self.quarter_sum);
+                self.quarter_pos = 0;
+                self.quarter_sum = 0.0;
+            }
+        }
+    }
+
+    /// Blocks of 400ms overlap by 75%, incomplete blocks at the end are dropped.
+    fn finish(self) -> Loudness {
+        let block_len = (4 * self.quarter_len) as f64;
+        let blocks = self.quarters.windows(4).map(|w| w.iter().sum::<f64>() / block_len).collect();
+
+        Loudness { blocks, peak: self.peak }
+    }
+}
+
+/// A second order IIR filter in direct form I.
+#[derive(Clone, Copy, Debug)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self { b, a, x: [0.0; 2], y: [0.0; 2] }
+    }
+
+    /// The high shelf of the K-weighting that models the acoustic effect of the head, the
+    /// coefficients are derived for any sample rate.
+    fn shelving(rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain = 3.999843853973347;
+        let q = 0.7071752369554196;
+
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+
+        Self::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    }
+
+    /// The RLB high pass of the K-weighting.
+    fn high_pass(rate: f64) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+
+        Self::new([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0])
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// Oversamples a channel with a windowed sinc filter to find peaks between samples.
+#[derive(Clone, Debug)]
+struct Interpolator {
+    phases: Vec<[f64; PHASE_TAPS]>,
+    history: [f64; PHASE_TAPS],
+}
+
+impl Interpolator {
+    fn new(factor: usize) -> Self {
+        let len = factor * PHASE_TAPS;
+        let center = (len - 1) as f64 / 2.0;
+        let mut phases = vec![[0.0; PHASE_TAPS]; factor];
+        for i in 0..len {
+            let t = (i as f64 - center) / factor as f64;
+            let sinc = match t == 0.0 {
+                true => 1.0,
+                false => (PI * t).sin() / (PI * t),
+            };
+            let window = 0.5 * (1.0 - (2.0 * PI * i as f64 / (len - 1) as f64).cos());
+            phases[i % factor][i / factor] = sinc * window;
+        }
+
+        Self { phases, history: [0.0; PHASE_TAPS] }
+    }
+
+    /// Returns the highest absolute value of the interpolated samples.
+    fn process(&mut self, sample: f64) -> f64 {
+        self.history.copy_within(0..PHASE_TAPS - 1, 1);
+        self.history[0] = sample;
+
+        if self.phases.len() == 1 {
+            return sample.abs();
+        }
+
+        self.phases
+            .iter()
+            .map(|p| p.iter().zip(self.history.iter()).map(|(c, h)| c * h).sum::<f64>().abs())
+            .fold(sample.abs(), f64::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    /// Interleaved stereo samples of a 997 Hz sine with the peak amplitude in dBFS.
+    fn sine(seconds: usize, dbfs: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        (0..seconds * RATE as usize)
+            .map(|i| (amplitude * (2.0 * PI * 997.0 * i as f64 / RATE as f64).sin()) as f32)
+            .flat_map(|s| [s, s])
+            .collect()
+    }
+
+    fn measure(samples: &[f32]) -> Loudness {
+        let mut meter = Meter::new(RATE, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        meter.process(samples);
+        meter.finish()
+    }
+
+    #[test]
+    fn stereo_sine() {
+        // EBU Tech 3341, test case 1
+        let loudness = measure(&sine(20, -23.0));
+
+        let integrated = loudness.integrated().unwrap();
+        assert!((integrated - -23.0).abs() < 0.1, "{}", integrated);
+        let peak = 20.0 * loudness.peak.log10();
+        assert!((peak - -23.0).abs() < 0.2, "{}", peak);
+    }
+
+    #[test]
+    fn relative_gate() {
+        // The quiet part is 20 dB below the loud one and doesn't count
+        let mut samples = sine(10, -23.0);
+        samples.extend(sine(10, -43.0));
+
+        let integrated = measure(&samples).integrated().unwrap();
+        assert!((integrated - -23.0).abs() < 0.1, "{}", integrated);
+    }
+
+    #[test]
+    fn absolute_gate() {
+        let mut samples = sine(10, -23.0);
+        samples.extend(sine(10, -80.0));
+
+        let integrated = measure(&samples).integrated().unwrap();
+        assert!((integrated - -23.0).abs() < 0.1, "{}", integrated);
+        assert_eq!(measure(&sine(10, -80.0)).integrated(), None);
+    }
+
+    #[test]
+    fn combined() {
+        let loud = measure(&sine(10, -23.0));
+        let quiet = measure(&sine(10, -33.0));
+
+        let integrated = Loudness::combine([&loud, &quiet]).integrated().unwrap();
+        // Both are above the relative gate, their energies are averaged
+        let expected = 10.0 * ((1.0 + 0.1) / 2.0f64).log10() - 23.0;
+        assert!((integrated - expected).abs() < 0.1, "{}", integrated);
+    }
+
+    #[test]
+    fn replay_gain() {
+        let gain = measure(&sine(10, -23.0)).replay_gain().unwrap();
+        assert!((gain.gain - 5.0).abs() < 0.1, "{}", gain.gain);
+    }
+}
@@ -1,29 +1,129 @@
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use mp4ameta::{Fourcc, FreeformIdent};
+
+use crate::vorbis::VorbisComment;
+use crate::{ape, flac, genre, ogg, riff, AudioProperties, Format};
+
+pub(crate) const NARRATOR: Fourcc = Fourcc(*b"\xa9nrt");
+pub(crate) const NARRATOR_FREEFORM: FreeformIdent =
+    FreeformIdent::new("com.apple.iTunes", "NARRATOR");
+const SERIES: FreeformIdent = FreeformIdent::new("com.apple.iTunes", "SERIES");
+const SERIES_PART: FreeformIdent = FreeformIdent::new("com.apple.iTunes", "SERIES-PART");
+pub(crate) const ORIGINAL_DATE: FreeformIdent =
+    FreeformIdent::new("com.apple.iTunes", "ORIGINALDATE");
+pub(crate) const ARTISTS_SORT: Fourcc = Fourcc(*b"soar");
+pub(crate) const RELEASE_ARTISTS_SORT: Fourcc = Fourcc(*b"soaa");
+pub(crate) const RELEASE_SORT: Fourcc = Fourcc(*b"soal");
+pub(crate) const TITLE_SORT: Fourcc = Fourcc(*b"sonm");
+pub(crate) const MB_RECORDING_ID: FreeformIdent =
+    FreeformIdent::new("com.apple.iTunes", "MusicBrainz Track Id");
+pub(crate) const MB_RELEASE_ID: FreeformIdent =
+    FreeformIdent::new("com.apple.iTunes", "MusicBrainz Album Id");
+pub(crate) const MB_RELEASE_GROUP_ID: FreeformIdent =
+    FreeformIdent::new("com.apple.iTunes", "MusicBrainz Release Group Id");
+pub(crate) const MB_ARTIST_ID: FreeformIdent =
+    FreeformIdent::new("com.apple.iTunes", "MusicBrainz Artist Id");
+pub(crate) const MB_RELEASE_ARTIST_ID: FreeformIdent =
+    FreeformIdent::new("com.apple.iTunes", "MusicBrainz Album Artist Id");
+/// The owner of the `UFID` frame that contains the MusicBrainz recording ID.
+pub(crate) const MB_UFID_OWNER: &str = "http://musicbrainz.org";
+pub(crate) const CONDUCTOR: FreeformIdent = FreeformIdent::new("com.apple.iTunes", "CONDUCTOR");
+pub(crate) const DISC_SUBTITLE: FreeformIdent =
+    FreeformIdent::new("com.apple.iTunes", "DISCSUBTITLE");
 
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct ReleaseArtists<'a> {
     pub names: &'a [String],
+    /// The MusicBrainz IDs of the artists, if the first song of the group has them.
+    pub ids: &'a [String],
     pub releases: Vec<Release<'a>>,
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct Release<'a> {
     pub name: &'a str,
+    /// The MusicBrainz release ID, which all songs of the release share.
+    pub id: Option<&'a str>,
     pub songs: Vec<&'a Song>,
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct Song {
     pub path: PathBuf,
+    /// The format detected by the content of the file.
+    pub format: Option<Format>,
     pub track_number: Option<u16>,
     pub total_tracks: Option<u16>,
     pub disc_number: Option<u16>,
     pub total_discs: Option<u16>,
+    /// The title of a single disc of a multi-disc release.
+    pub disc_subtitle: Option<String>,
     pub release_artists: Vec<String>,
+    /// Whether the release artists are tagged, otherwise they are the track artists.
+    pub has_release_artists: bool,
     pub artists: Vec<String>,
     pub release: String,
     pub title: String,
+    pub release_artists_sort: Option<String>,
+    pub artists_sort: Option<String>,
+    pub release_sort: Option<String>,
+    pub title_sort: Option<String>,
+    pub release_date: Option<Date>,
+    pub original_date: Option<Date>,
+    pub track_gain: Option<ReplayGain>,
+    pub release_gain: Option<ReplayGain>,
+    pub mb_recording_id: Option<String>,
+    pub mb_release_id: Option<String>,
+    pub mb_release_group_id: Option<String>,
+    pub mb_artist_ids: Vec<String>,
+    pub mb_release_artist_ids: Vec<String>,
+    pub genres: Vec<String>,
+    pub composer: Option<String>,
+    pub conductor: Option<String>,
+    pub comment: Option<String>,
+    pub grouping: Option<String>,
+    /// Whether the release is a compilation of songs by different artists.
+    pub compilation: bool,
     pub has_artwork: bool,
+    pub has_ape_tag: bool,
+    pub narrator: Option<String>,
+    pub series: Option<String>,
+    pub series_part: Option<u16>,
+    pub chapters: Vec<Chapter>,
+    pub properties: AudioProperties,
+}
+
+/// A date as it is stored in tags, the month and day are optional.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub year: i32,
+    pub month: Option<u8>,
+    pub day: Option<u8>,
+}
+
+/// A ReplayGain 2.0 adjustment in dB relative to -18 LUFS and the peak as a linear amplitude.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayGain {
+    pub gain: f64,
+    pub peak: Option<f64>,
+}
+
+/// Which of the dates of a song is meant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DateKind {
+    /// The date of this release, which might be a reissue.
+    Release,
+    /// The date the release was first published.
+    Original,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Chapter {
+    pub start: Duration,
+    pub title: String,
 }
 
 impl Song {
@@ -34,6 +134,89 @@ impl Song {
     pub fn release_artists_str(&self) -> String {
         self.release_artists.join(", ")
     }
+
+    pub fn date(&self, kind: DateKind) -> Option<Date> {
+        match kind {
+            DateKind::Release => self.release_date,
+            DateKind::Original => self.original_date,
+        }
+    }
+}
+
+impl Date {
+    pub fn year(year: i32) -> Self {
+        Self { year, month: None, day: None }
+    }
+
+    /// Parses dates like `1997`, `1997-05` or `1997-05-12`, a following time is ignored.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let date = s.split(['T', ' ']).next()?;
+        let mut parts = date.splitn(3, ['-', '/']);
+
+        let year = parts.next().filter(|y| y.len() == 4)?.parse().ok()?;
+        let month = match parts.next() {
+            Some(m) => Some(m.parse().ok().filter(|m| (1..=12).contains(m))?),
+            None => None,
+        };
+        let day = match parts.next() {
+            Some(d) => Some(d.parse().ok().filter(|d| (1..=31).contains(d))?),
+            None => None,
+        };
+
+        Some(Self { year, month, day })
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}", self.year)?;
+        if let Some(m) = self.month {
+            write!(f, "-{:02}", m)?;
+            if let Some(d) = self.day {
+                write!(f, "-{:02}", d)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReplayGain {
+    /// Parses the values of the `REPLAYGAIN_*_GAIN` and `REPLAYGAIN_*_PEAK` fields, the gain is
+    /// written like `-6.52 dB`.
+    pub fn parse(gain: &str, peak: Option<&str>) -> Option<Self> {
+        let gain = gain.trim();
+        let gain = gain.strip_suffix("dB").or_else(|| gain.strip_suffix("db")).unwrap_or(gain);
+        Some(Self {
+            gain: gain.trim().parse().ok()?,
+            peak: peak.and_then(|p| p.trim().parse().ok()),
+        })
+    }
+
+    /// Opus uses `R128_*_GAIN` fields that store a Q7.8 number relative to -23 LUFS.
+    pub fn from_r128(gain: &str) -> Option<Self> {
+        let q78: i16 = gain.trim().parse().ok()?;
+        Some(Self { gain: q78 as f64 / 256.0 + 5.0, peak: None })
+    }
+
+    pub fn gain_str(&self) -> String {
+        format!("{:.2} dB", self.gain)
+    }
+
+    pub fn peak_str(&self) -> Option<String> {
+        self.peak.map(|p| format!("{:.6}", p))
+    }
+
+    pub fn r128_str(&self) -> String {
+        let q78 = ((self.gain - 5.0) * 256.0).round();
+        (q78.max(i16::MIN as f64).min(i16::MAX as f64) as i16).to_string()
+    }
+}
+
+impl From<id3::Timestamp> for Date {
+    fn from(t: id3::Timestamp) -> Self {
+        Self { year: t.year, month: t.month, day: t.day }
+    }
 }
 
 #[derive(Clone, Debug, Default, PartialEq)]
@@ -42,53 +225,137 @@ pub struct Metadata {
     pub total_tracks: Option<u16>,
     pub disc_number: Option<u16>,
     pub total_discs: Option<u16>,
+    /// The title of a single disc of a multi-disc release.
+    pub disc_subtitle: Option<String>,
     pub artists: Vec<String>,
     pub release_artists: Vec<String>,
     pub release: Option<String>,
     pub title: Option<String>,
+    pub release_artists_sort: Option<String>,
+    pub artists_sort: Option<String>,
+    pub release_sort: Option<String>,
+    pub title_sort: Option<String>,
+    pub release_date: Option<Date>,
+    pub original_date: Option<Date>,
+    pub track_gain: Option<ReplayGain>,
+    pub release_gain: Option<ReplayGain>,
+    pub mb_recording_id: Option<String>,
+    pub mb_release_id: Option<String>,
+    pub mb_release_group_id: Option<String>,
+    pub mb_artist_ids: Vec<String>,
+    pub mb_release_artist_ids: Vec<String>,
+    pub genres: Vec<String>,
+    pub composer: Option<String>,
+    pub conductor: Option<String>,
+    pub comment: Option<String>,
+    pub grouping: Option<String>,
+    /// Whether the release is a compilation of songs by different artists.
+    pub compilation: bool,
     pub has_artwork: bool,
+    /// Whether an MP3 file carries an APEv2 tag next to its ID3 tag.
+    pub has_ape_tag: bool,
+    pub narrator: Option<String>,
+    pub series: Option<String>,
+    pub series_part: Option<u16>,
+    /// Only read for audiobooks.
+    pub chapters: Vec<Chapter>,
 }
 
 impl Metadata {
     pub fn read_from(path: &Path) -> Self {
-        match path.extension().unwrap().to_str().unwrap() {
-            "mp3" => {
-                if let Some(meta) = Self::read_mp3(path) {
-                    return meta;
-                }
-            }
-            "m4a" => {
-                if let Some(meta) = Self::read_mp4(path) {
-                    return meta;
-                }
-            }
-            _ => (),
+        match Format::detect(path) {
+            Some(f) => Self::read_as(path, f),
+            None => Self::default(),
         }
+    }
+
+    /// Reads the metadata using the tag backend of the format, regardless of the extension.
+    pub fn read_as(path: &Path, format: Format) -> Self {
+        let meta = match format {
+            Format::Mp3 => Self::read_mp3(path),
+            Format::Mp4 => Self::read_mp4(path),
+            Format::Flac => Self::read_flac(path),
+            Format::Ogg | Format::Opus => Self::read_ogg(path),
+            Format::Wav => Self::read_wav(path),
+            Format::Aiff => Self::read_aiff(path),
+            Format::Ape | Format::WavPack | Format::Musepack => Self::read_ape(path),
+        };
 
-        Self::default()
+        meta.unwrap_or_default()
     }
 
+    /// Reads the ID3 tag and fills the fields it lacks, like a missing album artist, from an
+    /// APEv2 tag. Files with only an APEv2 tag are read from that one.
     fn read_mp3(path: &Path) -> Option<Self> {
-        let tag = id3::Tag::read_from_path(&path).ok()?;
-        let m = Self {
+        let ape = ape::Tag::read_from_path(path).ok();
+        let mut m = match id3::Tag::read_from_path(path) {
+            Ok(tag) => Self::from_id3(&tag),
+            Err(_) => Self::from_ape(ape.as_ref()?),
+        };
+
+        if let Some(tag) = &ape {
+            m.fill_missing(Self::from_ape(tag));
+            m.has_ape_tag = true;
+        }
+
+        Some(m)
+    }
+
+    fn from_id3(tag: &id3::Tag) -> Self {
+        Self {
             track_number: zero_none(tag.track().map(|u| u as u16)),
             total_tracks: zero_none(tag.total_tracks().map(|u| u as u16)),
             disc_number: zero_none(tag.disc().map(|u| u as u16)),
             total_discs: zero_none(tag.total_discs().map(|u| u as u16)),
+            disc_subtitle: id3_text(tag, "TSST"),
             artists: tag
                 .artist()
                 .map(|s| s.split('\u{0}').map(|s| s.to_string()).collect())
-                .unwrap_or(Vec::new()),
+                .unwrap_or_default(),
             release_artists: tag
                 .album_artist()
                 .map(|s| s.split('\u{0}').map(|s| s.to_string()).collect())
-                .unwrap_or(Vec::new()),
+                .unwrap_or_default(),
             release: tag.album().map(|s| s.to_string()),
             title: tag.title().map(|s| s.to_string()),
+            release_artists_sort: id3_text(tag, "TSO2"),
+            artists_sort: id3_text(tag, "TSOP"),
+            release_sort: id3_text(tag, "TSOA"),
+            title_sort: id3_text(tag, "TSOT"),
+            release_date: tag
+                .date_recorded()
+                .map(Date::from)
+                .or_else(|| tag.year().map(Date::year)),
+            original_date: ["TDOR", "TORY"]
+                .iter()
+                .filter_map(|id| tag.get(id).and_then(|f| f.content().text()))
+                .find_map(Date::parse),
+            track_gain: replay_gain(|k| id3_extended_text_ignore_case(tag, k), "TRACK"),
+            release_gain: replay_gain(|k| id3_extended_text_ignore_case(tag, k), "ALBUM"),
+            mb_recording_id: tag
+                .frames()
+                .filter(|f| f.id() == "UFID")
+                .filter_map(|f| f.content().unknown())
+                .find_map(|d| {
+                    let mut split = d.splitn(2, |b| *b == 0);
+                    match split.next() == Some(MB_UFID_OWNER.as_bytes()) {
+                        true => String::from_utf8(split.next()?.to_vec()).ok(),
+                        false => None,
+                    }
+                }),
+            mb_release_id: id3_extended_text(tag, "MusicBrainz Album Id"),
+            mb_release_group_id: id3_extended_text(tag, "MusicBrainz Release Group Id"),
+            mb_artist_ids: id3_extended_texts(tag, "MusicBrainz Artist Id"),
+            mb_release_artist_ids: id3_extended_texts(tag, "MusicBrainz Album Artist Id"),
+            genres: tag.genre().map(genre::parse_id3_genres).unwrap_or_default(),
+            composer: id3_text(tag, "TCOM"),
+            conductor: id3_text(tag, "TPE3"),
+            comment: tag.comments().find(|c| c.description.is_empty()).map(|c| c.text.clone()),
+            grouping: id3_text(tag, "TIT1"),
+            compilation: parse_flag(id3_text(tag, "TCMP").as_deref()),
             has_artwork: tag.pictures().next().is_some(),
-        };
-
-        Some(m)
+            ..Default::default()
+        }
     }
 
     fn read_mp4(path: &Path) -> Option<Self> {
@@ -98,16 +365,255 @@ impl Metadata {
             total_tracks: tag.total_tracks(),
             disc_number: tag.disc_number(),
             total_discs: tag.total_discs(),
+            disc_subtitle: mp4_string(&tag, &DISC_SUBTITLE),
             artists: tag.take_artists().collect(),
             release_artists: tag.take_album_artists().collect(),
             release: tag.take_album(),
             title: tag.take_title(),
+            release_artists_sort: mp4_string(&tag, &RELEASE_ARTISTS_SORT),
+            artists_sort: mp4_string(&tag, &ARTISTS_SORT),
+            release_sort: mp4_string(&tag, &RELEASE_SORT),
+            title_sort: mp4_string(&tag, &TITLE_SORT),
+            release_date: tag.year().and_then(Date::parse),
+            original_date: tag.strings_of(&ORIGINAL_DATE).find_map(Date::parse),
+            track_gain: replay_gain(|k| mp4_freeform_ignore_case(&tag, k), "TRACK"),
+            release_gain: replay_gain(|k| mp4_freeform_ignore_case(&tag, k), "ALBUM"),
+            mb_recording_id: mp4_string(&tag, &MB_RECORDING_ID),
+            mb_release_id: mp4_string(&tag, &MB_RELEASE_ID),
+            mb_release_group_id: mp4_string(&tag, &MB_RELEASE_GROUP_ID),
+            mb_artist_ids: tag.strings_of(&MB_ARTIST_ID).map(|s| s.to_string()).collect(),
+            mb_release_artist_ids: tag
+                .strings_of(&MB_RELEASE_ARTIST_ID)
+                .map(|s| s.to_string())
+                .collect(),
+            genres: tag.genres().map(|s| s.to_string()).collect(),
+            composer: tag.composer().map(|s| s.to_string()),
+            conductor: mp4_string(&tag, &CONDUCTOR),
+            comment: tag.comment().map(|s| s.to_string()),
+            grouping: tag.grouping().map(|s| s.to_string()),
+            compilation: tag.compilation(),
             has_artwork: tag.artwork().is_some(),
+            narrator: tag
+                .strings_of(&NARRATOR)
+                .chain(tag.strings_of(&NARRATOR_FREEFORM))
+                .next()
+                .map(|s| s.to_string()),
+            series: tag
+                .strings_of(&SERIES)
+                .next()
+                .or_else(|| tag.movement())
+                .map(|s| s.to_string()),
+            series_part: tag
+                .strings_of(&SERIES_PART)
+                .find_map(|s| s.trim().parse().ok())
+                .or_else(|| tag.movement_index()),
+            ..Default::default()
         };
 
         Some(m)
     }
 
+    fn read_flac(path: &Path) -> Option<Self> {
+        let tag = flac::Tag::read_from_path(path).ok()?;
+        let mut m = Self::from_vorbis(&tag.comment);
+        m.has_artwork |= tag.has_picture;
+
+        Some(m)
+    }
+
+    fn read_ogg(path: &Path) -> Option<Self> {
+        let tag = ogg::Tag::read_from_path(path).ok()?;
+        Some(Self::from_vorbis(&tag.comment))
+    }
+
+    fn from_vorbis(comment: &VorbisComment) -> Self {
+        let (track_number, track_total) =
+            comment.value("TRACKNUMBER").map_or((None, None), parse_pair);
+        let (disc_number, disc_total) =
+            comment.value("DISCNUMBER").map_or((None, None), parse_pair);
+        let total = |keys: &[&str], fallback: Option<u16>| {
+            keys.iter()
+                .find_map(|k| comment.value(k))
+                .and_then(|v| v.trim().parse().ok())
+                .or(fallback)
+        };
+        let strings = |keys: &[&str]| -> Vec<String> {
+            keys.iter()
+                .map(|k| comment.values(k).map(|s| s.to_string()).collect::<Vec<_>>())
+                .find(|v| !v.is_empty())
+                .unwrap_or_default()
+        };
+
+        Self {
+            track_number: zero_none(track_number),
+            total_tracks: zero_none(total(&["TRACKTOTAL", "TOTALTRACKS"], track_total)),
+            disc_number: zero_none(disc_number),
+            total_discs: zero_none(total(&["DISCTOTAL", "TOTALDISCS"], disc_total)),
+            disc_subtitle: comment.value("DISCSUBTITLE").map(|s| s.to_string()),
+            artists: strings(&["ARTIST"]),
+            release_artists: strings(&["ALBUMARTIST", "ALBUM ARTIST"]),
+            release: comment.value("ALBUM").map(|s| s.to_string()),
+            title: comment.value("TITLE").map(|s| s.to_string()),
+            release_artists_sort: comment.value("ALBUMARTISTSORT").map(|s| s.to_string()),
+            artists_sort: comment.value("ARTISTSORT").map(|s| s.to_string()),
+            release_sort: comment.value("ALBUMSORT").map(|s| s.to_string()),
+            title_sort: comment.value("TITLESORT").map(|s| s.to_string()),
+            release_date: comment.value("DATE").and_then(Date::parse),
+            original_date: ["ORIGINALDATE", "ORIGINALYEAR"]
+                .iter()
+                .find_map(|k| comment.value(k).and_then(Date::parse)),
+            track_gain: replay_gain(|k| comment.value(k), "TRACK")
+                .or_else(|| comment.value("R128_TRACK_GAIN").and_then(ReplayGain::from_r128)),
+            release_gain: replay_gain(|k| comment.value(k), "ALBUM")
+                .or_else(|| comment.value("R128_ALBUM_GAIN").and_then(ReplayGain::from_r128)),
+            mb_recording_id: comment.value("MUSICBRAINZ_TRACKID").map(|s| s.to_string()),
+            mb_release_id: comment.value("MUSICBRAINZ_ALBUMID").map(|s| s.to_string()),
+            mb_release_group_id: comment.value("MUSICBRAINZ_RELEASEGROUPID").map(|s| s.to_string()),
+            mb_artist_ids: strings(&["MUSICBRAINZ_ARTISTID"]),
+            mb_release_artist_ids: strings(&["MUSICBRAINZ_ALBUMARTISTID"]),
+            genres: strings(&["GENRE"]),
+            composer: comment.value("COMPOSER").map(|s| s.to_string()),
+            conductor: comment.value("CONDUCTOR").map(|s| s.to_string()),
+            comment: ["COMMENT", "DESCRIPTION"]
+                .iter()
+                .find_map(|k| comment.value(k))
+                .map(|s| s.to_string()),
+            grouping: comment.value("GROUPING").map(|s| s.to_string()),
+            compilation: parse_flag(comment.value("COMPILATION")),
+            has_artwork: comment.contains("METADATA_BLOCK_PICTURE"),
+            ..Default::default()
+        }
+    }
+
+    fn read_wav(path: &Path) -> Option<Self> {
+        match id3::Tag::read_from_wav(path) {
+            Ok(tag) => Some(Self::from_id3(&tag)),
+            Err(_) => {
+                let info = riff::Info::read_from_path(path).ok()?;
+                Some(Self::from_riff_info(&info))
+            }
+        }
+    }
+
+    fn read_aiff(path: &Path) -> Option<Self> {
+        let tag = id3::Tag::read_from_aiff(path).ok()?;
+        Some(Self::from_id3(&tag))
+    }
+
+    pub(crate) fn from_riff_info(info: &riff::Info) -> Self {
+        let (track_number, total_tracks) =
+            info.value(b"ITRK").or_else(|| info.value(b"IPRT")).map_or((None, None), parse_pair);
+
+        Self {
+            track_number: zero_none(track_number),
+            total_tracks: zero_none(total_tracks),
+            artists: info.value(b"IART").map(|s| vec![s.to_string()]).unwrap_or_default(),
+            release: info.value(b"IPRD").map(|s| s.to_string()),
+            title: info.value(b"INAM").map(|s| s.to_string()),
+            release_date: info.value(b"ICRD").and_then(Date::parse),
+            genres: info.value(b"IGNR").map(|s| vec![s.to_string()]).unwrap_or_default(),
+            comment: info.value(b"ICMT").map(|s| s.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn read_ape(path: &Path) -> Option<Self> {
+        let tag = ape::Tag::read_from_path(path).ok()?;
+        Some(Self::from_ape(&tag))
+    }
+
+    pub(crate) fn from_ape(tag: &ape::Tag) -> Self {
+        let (track_number, total_tracks) = tag.value("Track").map_or((None, None), parse_pair);
+        let (disc_number, total_discs) = tag.value("Disc").map_or((None, None), parse_pair);
+        let strings = |keys: &[&str]| -> Vec<String> {
+            keys.iter()
+                .map(|k| tag.values(k).map(|s| s.to_string()).collect::<Vec<_>>())
+                .find(|v| !v.is_empty())
+                .unwrap_or_default()
+        };
+
+        Self {
+            track_number: zero_none(track_number),
+            total_tracks: zero_none(total_tracks),
+            disc_number: zero_none(disc_number),
+            total_discs: zero_none(total_discs),
+            disc_subtitle: tag.value("DiscSubtitle").map(|s| s.to_string()),
+            artists: strings(&["Artist"]),
+            release_artists: strings(&["Album Artist", "AlbumArtist"]),
+            release: tag.value("Album").map(|s| s.to_string()),
+            title: tag.value("Title").map(|s| s.to_string()),
+            release_artists_sort: tag.value("AlbumArtistSort").map(|s| s.to_string()),
+            artists_sort: tag.value("ArtistSort").map(|s| s.to_string()),
+            release_sort: tag.value("AlbumSort").map(|s| s.to_string()),
+            title_sort: tag.value("TitleSort").map(|s| s.to_string()),
+            release_date: tag.value("Year").and_then(Date::parse),
+            track_gain: replay_gain(|k| tag.value(k), "TRACK"),
+            release_gain: replay_gain(|k| tag.value(k), "ALBUM"),
+            mb_recording_id: tag.value("MUSICBRAINZ_TRACKID").map(|s| s.to_string()),
+            mb_release_id: tag.value("MUSICBRAINZ_ALBUMID").map(|s| s.to_string()),
+            mb_release_group_id: tag.value("MUSICBRAINZ_RELEASEGROUPID").map(|s| s.to_string()),
+            mb_artist_ids: strings(&["MUSICBRAINZ_ARTISTID"]),
+            mb_release_artist_ids: strings(&["MUSICBRAINZ_ALBUMARTISTID"]),
+            genres: strings(&["Genre"]),
+            composer: tag.value("Composer").map(|s| s.to_string()),
+            conductor: tag.value("Conductor").map(|s| s.to_string()),
+            comment: tag.value("Comment").map(|s| s.to_string()),
+            grouping: tag.value("Grouping").map(|s| s.to_string()),
+            compilation: parse_flag(tag.value("Compilation")),
+            has_artwork: tag.has_cover(),
+            ..Default::default()
+        }
+    }
+
+    /// Fills fields that are missing with the values of another tag.
+    fn fill_missing(&mut self, other: Self) {
+        self.track_number = self.track_number.or(other.track_number);
+        self.total_tracks = self.total_tracks.or(other.total_tracks);
+        self.disc_number = self.disc_number.or(other.disc_number);
+        self.total_discs = self.total_discs.or(other.total_discs);
+        self.disc_subtitle = self.disc_subtitle.take().or(other.disc_subtitle);
+        if self.artists.is_empty() {
+            self.artists = other.artists;
+        }
+        if self.release_artists.is_empty() {
+            self.release_artists = other.release_artists;
+        }
+        self.release = self.release.take().or(other.release);
+        self.title = self.title.take().or(other.title);
+        self.release_artists_sort = self.release_artists_sort.take().or(other.release_artists_sort);
+        self.artists_sort = self.artists_sort.take().or(other.artists_sort);
+        self.release_sort = self.release_sort.take().or(other.release_sort);
+        self.title_sort = self.title_sort.take().or(other.title_sort);
+        self.release_date = self.release_date.or(other.release_date);
+        self.original_date = self.original_date.or(other.original_date);
+        self.track_gain = self.track_gain.or(other.track_gain);
+        self.release_gain = self.release_gain.or(other.release_gain);
+        self.mb_recording_id = self.mb_recording_id.take().or(other.mb_recording_id);
+        self.mb_release_id = self.mb_release_id.take().or(other.mb_release_id);
+        self.mb_release_group_id = self.mb_release_group_id.take().or(other.mb_release_group_id);
+        if self.mb_artist_ids.is_empty() {
+            self.mb_artist_ids = other.mb_artist_ids;
+        }
+        if self.mb_release_artist_ids.is_empty() {
+            self.mb_release_artist_ids = other.mb_release_artist_ids;
+        }
+        if self.genres.is_empty() {
+            self.genres = other.genres;
+        }
+        self.composer = self.composer.take().or(other.composer);
+        self.conductor = self.conductor.take().or(other.conductor);
+        self.comment = self.comment.take().or(other.comment);
+        self.grouping = self.grouping.take().or(other.grouping);
+        self.compilation |= other.compilation;
+        self.has_artwork |= other.has_artwork;
+        self.narrator = self.narrator.take().or(other.narrator);
+        self.series = self.series.take().or(other.series);
+        self.series_part = self.series_part.or(other.series_part);
+        if self.chapters.is_empty() {
+            self.chapters = other.chapters;
+        }
+    }
+
     pub fn release_artists(&self) -> Option<&[String]> {
         if !self.release_artists.is_empty() {
             Some(&self.release_artists)
@@ -129,6 +635,48 @@ impl Metadata {
     }
 }
 
+fn id3_text(tag: &id3::Tag, id: &str) -> Option<String> {
+    tag.get(id).and_then(|f| f.content().text()).map(|s| s.to_string())
+}
+
+fn id3_extended_text(tag: &id3::Tag, description: &str) -> Option<String> {
+    tag.extended_texts().find(|t| t.description == description).map(|t| t.value.clone())
+}
+
+/// Multiple values of a `TXXX` frame are separated by null bytes, or by slashes in ID3v2.3.
+fn id3_extended_texts(tag: &id3::Tag, description: &str) -> Vec<String> {
+    match tag.extended_texts().find(|t| t.description == description) {
+        Some(t) => t.value.split(['\0', '/']).map(|s| s.trim().to_string()).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Some taggers write the ReplayGain keys in lowercase.
+fn id3_extended_text_ignore_case<'a>(tag: &'a id3::Tag, description: &str) -> Option<&'a str> {
+    tag.extended_texts()
+        .find(|t| t.description.eq_ignore_ascii_case(description))
+        .map(|t| t.value.as_str())
+}
+
+fn mp4_freeform_ignore_case<'a>(tag: &'a mp4ameta::Tag, name: &str) -> Option<&'a str> {
+    tag.data().find_map(|(ident, data)| match ident {
+        mp4ameta::DataIdent::Freeform { name: n, .. } if n.eq_ignore_ascii_case(name) => {
+            data.string()
+        }
+        _ => None,
+    })
+}
+
+/// Reads the `REPLAYGAIN_{scope}_GAIN` and `REPLAYGAIN_{scope}_PEAK` fields.
+fn replay_gain<'a>(value: impl Fn(&str) -> Option<&'a str>, scope: &str) -> Option<ReplayGain> {
+    let gain = value(&format!("REPLAYGAIN_{}_GAIN", scope))?;
+    ReplayGain::parse(gain, value(&format!("REPLAYGAIN_{}_PEAK", scope)))
+}
+
+fn mp4_string(tag: &mp4ameta::Tag, ident: &impl mp4ameta::Ident) -> Option<String> {
+    tag.strings_of(ident).next().map(|s| s.to_string())
+}
+
 #[inline]
 pub fn zero_none(n: Option<u16>) -> Option<u16> {
     n.and_then(|n| match n {
@@ -136,3 +684,19 @@ pub fn zero_none(n: Option<u16>) -> Option<u16> {
         _ => Some(n),
     })
 }
+
+/// Parses a number field that might be written as `number/total`.
+pub(crate) fn parse_pair(s: &str) -> (Option<u16>, Option<u16>) {
+    let mut split = s.splitn(2, '/');
+    let number = split.next().and_then(|s| s.trim().parse().ok());
+    let total = split.next().and_then(|s| s.trim().parse().ok());
+    (number, total)
+}
+
+/// Flags like `TCMP` or `COMPILATION` are stored as `1`, some taggers write `true`.
+fn parse_flag(s: Option<&str>) -> bool {
+    match s.map(str::trim) {
+        Some(s) => s == "1" || s.eq_ignore_ascii_case("true"),
+        None => false,
+    }
+}
@@ -0,0 +1,310 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::Chapter;
+
+/// Nero chapters are stored in units of 100 nanoseconds.
+const CHPL_TIMESCALE: u64 = 10_000_000;
+
+/// Reads the chapter list of an MPEG-4 file, either from a Nero `chpl` atom or from a QuickTime
+/// chapter text track.
+pub fn read_chapters(path: impl AsRef<Path>) -> io::Result<Vec<Chapter>> {
+    let mut file = File::open(path)?;
+    let moov = read_moov(&mut file)?;
+
+    if let Some(chpl) = find(&moov, &[b"udta", b"chpl"]) {
+        if let Some(chapters) = parse_chpl(chpl) {
+            if !chapters.is_empty() {
+                return Ok(chapters);
+            }
+        }
+    }
+
+    match read_chapter_track(&mut file, &moov)? {
+        Some(chapters) => Ok(chapters),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// The size of the media data of an MPEG-4 file, which is used to calculate the bitrate.
+pub(crate) fn media_data_len(path: impl AsRef<Path>) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut len = None;
+    top_level_atoms(&mut file, |ident, size, _| {
+        if ident == b"mdat" {
+            *len.get_or_insert(0) += size;
+        }
+        Ok(false)
+    })?;
+
+    len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing mdat atom"))
+}
+
+fn read_moov(file: &mut File) -> io::Result<Vec<u8>> {
+    let mut moov = None;
+    top_level_atoms(file, |ident, size, file| {
+        if ident != b"moov" {
+            return Ok(false);
+        }
+
+        let mut data = vec![0; size as usize];
+        file.read_exact(&mut data)?;
+        moov = Some(data);
+        Ok(true)
+    })?;
+
+    moov.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing moov atom"))
+}
+
+/// Calls `f` with the identifier and content size of each top level atom, while the file is
+/// positioned at the start of the content. Stops when `f` returns true.
+fn top_level_atoms(
+    file: &mut File,
+    mut f: impl FnMut(&[u8], u64, &mut File) -> io::Result<bool>,
+) -> io::Result<()> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut pos = 0;
+
+    while pos + 8 <= len {
+        let mut header = [0; 8];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut header)?;
+
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            let mut large = [0; 8];
+            file.read_exact(&mut large)?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        } else if size == 0 {
+            size = len - pos;
+        }
+        // Atoms that extend past the end of the file are corrupt, so their size isn't trusted
+        if size < header_len || size > len - pos {
+            break;
+        }
+
+        if f(&header[4..8], size - header_len, file)? {
+            break;
+        }
+
+        pos += size;
+    }
+
+    Ok(())
+}
+
+/// Iterates over the child atoms contained in the data.
+fn atoms(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let header = data.get(pos..pos + 8)?;
+        let size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let (start, end) = match size {
+            0 => (pos + 8, data.len()),
+            1 => {
+                let large = data.get(pos + 8..pos + 16)?;
+                let size = u64::from_be_bytes(large.try_into().unwrap()).try_into().ok()?;
+                (pos + 16, pos.checked_add(size)?)
+            }
+            _ => (pos + 8, pos + size),
+        };
+
+        let content = data.get(start..end)?;
+        pos = end;
+        Some((&header[4..8], content))
+    })
+}
+
+fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    let (first, rest) = path.split_first()?;
+    let content = atoms(data).find(|(ident, _)| ident == *first).map(|(_, c)| c)?;
+
+    match rest.is_empty() {
+        true => Some(content),
+        false => find(content, rest),
+    }
+}
+
+fn parse_chpl(data: &[u8]) -> Option<Vec<Chapter>> {
+    let version = *data.first()?;
+    let mut pos = if version == 0 { 4 } else { 8 };
+
+    let count = *data.get(pos)?;
+    pos += 1;
+
+    let mut chapters = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let start = be_u64(data, pos)?;
+        let len = *data.get(pos + 8)? as usize;
+        let title = data.get(pos + 9..pos + 9 + len)?;
+        pos += 9 + len;
+
+        // Chapters that start too late to be represented are dropped
+        if let Some(nanos) = start.checked_mul(1_000_000_000 / CHPL_TIMESCALE) {
+            chapters.push(Chapter {
+                start: Duration::from_nanos(nanos),
+                title: String::from_utf8_lossy(title).into_owned(),
+            });
+        }
+    }
+
+    Some(chapters)
+}
+
+/// Reads the text samples of the track that is referenced by a `tref/chap` atom.
+fn read_chapter_track(file: &mut File, moov: &[u8]) -> io::Result<Option<Vec<Chapter>>> {
+    let chapter_id = atoms(moov)
+        .filter(|(ident, _)| ident == b"trak")
+        .find_map(|(_, trak)| find(trak, &[b"tref", b"chap"]))
+        .and_then(|chap| chap.get(0..4))
+        .map(|id| u32::from_be_bytes(id.try_into().unwrap()));
+    let chapter_id = match chapter_id {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+
+    let trak = atoms(moov)
+        .filter(|(ident, _)| ident == b"trak")
+        .map(|(_, trak)| trak)
+        .find(|trak| track_id(trak) == Some(chapter_id));
+    let len = file.seek(SeekFrom::End(0))?;
+    let table = match trak.and_then(|t| SampleTable::parse(t, len)) {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+
+    let mut deltas =
+        table.durations.iter().flat_map(|&(count, delta)| (0..count).map(move |_| delta));
+    let mut chapters = Vec::with_capacity(table.samples.len());
+    let mut time = 0u64;
+    for (offset, size) in table.samples.iter() {
+        let mut sample = vec![0; *size as usize];
+        file.seek(SeekFrom::Start(*offset))?;
+        file.read_exact(&mut sample)?;
+
+        // Chapters that start too late to be represented are dropped
+        if let Some(millis) = time.checked_mul(1000) {
+            chapters.push(Chapter {
+                start: Duration::from_millis(millis / table.timescale),
+                title: parse_text_sample(&sample),
+            });
+        }
+        time = time.saturating_add(deltas.next().unwrap_or(0));
+    }
+
+    Ok(Some(chapters))
+}
+
+fn track_id(trak: &[u8]) -> Option<u32> {
+    let tkhd = find(trak, &[b"tkhd"])?;
+    let pos = if *tkhd.first()? == 1 { 20 } else { 12 };
+    Some(u32::from_be_bytes(tkhd.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn parse_text_sample(sample: &[u8]) -> String {
+    let len = match sample.get(0..2) {
+        Some(l) => u16::from_be_bytes([l[0], l[1]]) as usize,
+        None => return String::new(),
+    };
+    let text = sample.get(2..2 + len).unwrap_or(&sample[2..]);
+
+    if text.starts_with(&[0xFE, 0xFF]) {
+        let utf16: Vec<u16> =
+            text[2..].chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+        String::from_utf16_lossy(&utf16)
+    } else {
+        String::from_utf8_lossy(text).into_owned()
+    }
+}
+
+struct SampleTable {
+    timescale: u64,
+    /// Runs of samples with the same duration, as stored in the `stts` atom.
+    durations: Vec<(u32, u64)>,
+    /// The offset and size of each sample.
+    samples: Vec<(u64, u32)>,
+}
+
+impl SampleTable {
+    /// Only samples that lie within the file length `len` are kept.
+    fn parse(trak: &[u8], len: u64) -> Option<Self> {
+        let mdhd = find(trak, &[b"mdia", b"mdhd"])?;
+        let pos = if *mdhd.first()? == 1 { 20 } else { 12 };
+        let timescale = u32::from_be_bytes(mdhd.get(pos..pos + 4)?.try_into().ok()?) as u64;
+        if timescale == 0 {
+            return None;
+        }
+
+        let stbl = find(trak, &[b"mdia", b"minf", b"stbl"])?;
+
+        let durations = table(find(stbl, &[b"stts"])?, 8)?
+            .map(|e| Some((be_u32(e, 0)?, be_u32(e, 4)? as u64)))
+            .collect::<Option<_>>()?;
+
+        let stsz = find(stbl, &[b"stsz"])?;
+        let sample_size = be_u32(stsz, 4)?;
+        let sample_count = be_u32(stsz, 8)? as usize;
+        let sizes: Vec<u32> = match sample_size {
+            0 => stsz.get(12..)?.chunks_exact(4).map(|c| be_u32(c, 0)).collect::<Option<_>>()?,
+            _ => Vec::new(),
+        };
+        let size = |i: usize| match sample_size {
+            0 => sizes.get(i).copied(),
+            s => Some(s).filter(|_| i < sample_count),
+        };
+
+        let chunk_offsets: Vec<u64> = match find(stbl, &[b"stco"]) {
+            Some(stco) => {
+                table(stco, 4)?.map(|e| be_u32(e, 0).map(u64::from)).collect::<Option<_>>()?
+            }
+            None => {
+                table(find(stbl, &[b"co64"])?, 8)?.map(|e| be_u64(e, 0)).collect::<Option<_>>()?
+            }
+        };
+
+        let stsc: Vec<(u32, u32)> = table(find(stbl, &[b"stsc"])?, 12)?
+            .map(|e| Some((be_u32(e, 0)?, be_u32(e, 4)?)))
+            .collect::<Option<_>>()?;
+
+        let mut samples = Vec::with_capacity(sizes.len());
+        let mut sample = 0;
+        for (i, chunk_offset) in chunk_offsets.iter().enumerate() {
+            let chunk = i as u32 + 1;
+            let samples_per_chunk =
+                stsc.iter().rev().find(|(first, _)| *first <= chunk).map_or(0, |(_, n)| *n);
+
+            let mut offset = *chunk_offset;
+            for _ in 0..samples_per_chunk {
+                let size = match size(sample) {
+                    Some(s) if s > 0 && s as u64 <= len.saturating_sub(offset) => s,
+                    _ => break,
+                };
+                samples.push((offset, size));
+                offset += size as u64;
+                sample += 1;
+            }
+        }
+
+        Some(Self { timescale, durations, samples })
+    }
+}
+
+/// Returns the entries of a sample table atom with a version, flags and entry count header.
+fn table(data: &[u8], entry_len: usize) -> Option<impl Iterator<Item = &[u8]>> {
+    let count = be_u32(data, 4)? as usize;
+    let entries = data.get(8..8 + count * entry_len)?;
+    Some(entries.chunks_exact(entry_len))
+}
+
+fn be_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn be_u64(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
@@ -0,0 +1,361 @@
+use std::borrow::Borrow;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+
+use crate::fs::replace_file;
+use crate::vorbis::VorbisComment;
+
+const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+const HEADER_LEN: usize = 27;
+const MAX_SEGMENTS: usize = 255;
+
+const CONTINUED: u8 = 0x01;
+
+const VORBIS_HEAD: &[u8] = b"\x01vorbis";
+const VORBIS_TAGS: &[u8] = b"\x03vorbis";
+const OPUS_HEAD: &[u8] = b"OpusHead";
+const OPUS_TAGS: &[u8] = b"OpusTags";
+const FLAC_HEAD: &[u8] = b"\x7fFLAC";
+const FLAC_VORBIS_COMMENT: u8 = 4;
+
+lazy_static::lazy_static! {
+    static ref CRC_TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        for (i, t) in table.iter_mut().enumerate() {
+            let mut r = (i as u32) << 24;
+            for _ in 0..8 {
+                r = if r & 0x8000_0000 != 0 { (r << 1) ^ 0x04c1_1db7 } else { r << 1 };
+            }
+            *t = r;
+        }
+        table
+    };
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tag {
+    pub comment: VorbisComment,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Codec {
+    Vorbis,
+    Opus,
+    Flac,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Page {
+    header_type: u8,
+    granule: u64,
+    serial: u32,
+    sequence: u32,
+    lacing: Vec<u8>,
+    data: Vec<u8>,
+}
+
+/// The header packets of the first logical stream in a file.
+struct Headers {
+    codec: Codec,
+    serial: u32,
+    packets: Vec<Vec<u8>>,
+    /// The index of the page after the last header page.
+    end_page: usize,
+}
+
+impl Tag {
+    pub fn read_from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        // Only the pages up to the comment header are read
+        let pages = Pages { reader: BufReader::new(File::open(path)?) };
+        let headers = read_headers(pages)?;
+
+        let (i, prefix, _) = headers.comment_packet()?;
+        let comment = VorbisComment::parse(&headers.packets[i][prefix..])?;
+
+        Ok(Self { comment })
+    }
+
+    /// Rewrites the comment header packet and repaginates the header pages. The following audio
+    /// pages are copied as they are, only their sequence numbers and checksums are updated.
+    pub fn write_to_path(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        let pages = Pages { reader: &data[..] }.collect::<io::Result<Vec<Page>>>()?;
+        let mut headers = read_headers(pages.iter().map(Ok))?;
+
+        let (i, prefix, suffix) = headers.comment_packet()?;
+        let old = &headers.packets[i];
+        let mut packet = old[..prefix].to_vec();
+        packet.extend_from_slice(&self.comment.to_bytes());
+        packet.extend_from_slice(&old[old.len() - suffix..]);
+        if headers.codec == Codec::Flac {
+            let len = ((packet.len() - prefix) as u32).to_be_bytes();
+            packet[1..4].copy_from_slice(&len[1..]);
+        }
+        headers.packets[i] = packet;
+
+        // Pages of other multiplexed streams are kept in front of the new header pages.
+        let mut new_pages = vec![pages[0].clone()];
+        new_pages.extend(
+            pages[1..headers.end_page].iter().filter(|p| p.serial != headers.serial).cloned(),
+        );
+        let mut sequence = pages[0].sequence + 1;
+        for p in paginate(headers.serial, sequence, &headers.packets[1..]) {
+            sequence = p.sequence + 1;
+            new_pages.push(p);
+        }
+
+        for p in pages[headers.end_page..].iter() {
+            let mut p = p.clone();
+            if p.serial == headers.serial {
+                p.sequence = sequence;
+                sequence += 1;
+            }
+            new_pages.push(p);
+        }
+
+        let mut out = Vec::with_capacity(data.len() + 1024);
+        for p in new_pages.iter() {
+            p.write_to(&mut out);
+        }
+
+        replace_file(path, |file| file.write_all(&out))
+    }
+}
+
+impl Headers {
+    /// Returns the index of the comment packet and the length of the codec specific prefix and
+    /// suffix surrounding the vorbis comment.
+    fn comment_packet(&self) -> io::Result<(usize, usize, usize)> {
+        match self.codec {
+            Codec::Vorbis => {
+                let packet = &self.packets[1];
+                if !packet.starts_with(VORBIS_TAGS) {
+                    return Err(invalid_data("missing vorbis comment header"));
+                }
+                // The framing bit
+                Ok((1, VORBIS_TAGS.len(), 1))
+            }
+            Codec::Opus => {
+                let packet = &self.packets[1];
+                if !packet.starts_with(OPUS_TAGS) {
+                    return Err(invalid_data("missing opus comment header"));
+                }
+                // Opus allows binary data after the comments which has to be preserved
+                let (_, len) = VorbisComment::parse_prefix(&packet[OPUS_TAGS.len()..])?;
+                let suffix = packet.len() - OPUS_TAGS.len() - len;
+                Ok((1, OPUS_TAGS.len(), suffix))
+            }
+            Codec::Flac => {
+                let i = self
+                    .packets
+                    .iter()
+                    .skip(1)
+                    .position(|p| p.first().map(|b| b & 0x7F) == Some(FLAC_VORBIS_COMMENT))
+                    .ok_or_else(|| invalid_data("missing flac vorbis comment block"))?;
+                Ok((i + 1, 4, 0))
+            }
+        }
+    }
+}
+
+impl Page {
+    fn write_to(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+
+        out.extend_from_slice(CAPTURE_PATTERN);
+        out.push(0);
+        out.push(self.header_type);
+        out.extend_from_slice(&self.granule.to_le_bytes());
+        out.extend_from_slice(&self.serial.to_le_bytes());
+        out.extend_from_slice(&self.sequence.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.push(self.lacing.len() as u8);
+        out.extend_from_slice(&self.lacing);
+        out.extend_from_slice(&self.data);
+
+        let crc = crc32(&out[start..]);
+        out[start + 22..start + 26].copy_from_slice(&crc.to_le_bytes());
+    }
+}
+
+/// Reads the pages of a file one by one.
+struct Pages<R> {
+    reader: R,
+}
+
+impl<R: Read> Iterator for Pages<R> {
+    type Item = io::Result<Page>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        read_page(&mut self.reader).transpose()
+    }
+}
+
+fn read_page(reader: &mut impl Read) -> io::Result<Option<Page>> {
+    let mut header = [0; HEADER_LEN];
+    let mut read = 0;
+    while read < HEADER_LEN {
+        match reader.read(&mut header[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(invalid_data("truncated page")),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    if &header[0..4] != CAPTURE_PATTERN {
+        return Err(invalid_data("missing ogg capture pattern"));
+    }
+
+    let truncated = |e: io::Error| match e.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_data("truncated page"),
+        _ => e,
+    };
+    let mut lacing = vec![0; header[26] as usize];
+    reader.read_exact(&mut lacing).map_err(truncated)?;
+    let len: usize = lacing.iter().map(|l| *l as usize).sum();
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data).map_err(truncated)?;
+
+    Ok(Some(Page {
+        header_type: header[5],
+        granule: u64::from_le_bytes(header[6..14].try_into().unwrap()),
+        serial: u32::from_le_bytes(header[14..18].try_into().unwrap()),
+        sequence: u32::from_le_bytes(header[18..22].try_into().unwrap()),
+        lacing,
+        data,
+    }))
+}
+
+/// Collects the header packets, no pages after the last header page are read.
+fn read_headers<P: Borrow<Page>>(
+    pages: impl Iterator<Item = io::Result<P>>,
+) -> io::Result<Headers> {
+    let mut serial = None;
+    let mut codec = None;
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut packet = Vec::new();
+
+    for (i, p) in pages.enumerate() {
+        let p = p?;
+        let p = p.borrow();
+        let serial = *serial.get_or_insert(p.serial);
+        if p.serial != serial {
+            continue;
+        }
+
+        let mut offset = 0;
+        for l in p.lacing.iter() {
+            let l = *l as usize;
+            packet.extend_from_slice(&p.data[offset..offset + l]);
+            offset += l;
+
+            if l < 255 {
+                packets.push(std::mem::take(&mut packet));
+
+                if codec.is_none() {
+                    codec = Some(detect_codec(&packets[0])?);
+                }
+            }
+        }
+
+        if let Some(c) = codec {
+            if headers_complete(c, &packets) {
+                if offset != p.data.len() || !packet.is_empty() {
+                    return Err(invalid_data("header packets don't end on a page boundary"));
+                }
+                return Ok(Headers { codec: c, serial, packets, end_page: i + 1 });
+            }
+        }
+    }
+
+    match serial {
+        Some(_) => Err(invalid_data("incomplete ogg header packets")),
+        None => Err(invalid_data("empty ogg file")),
+    }
+}
+
+fn detect_codec(packet: &[u8]) -> io::Result<Codec> {
+    if packet.starts_with(VORBIS_HEAD) {
+        Ok(Codec::Vorbis)
+    } else if packet.starts_with(OPUS_HEAD) {
+        Ok(Codec::Opus)
+    } else if packet.starts_with(FLAC_HEAD) {
+        Ok(Codec::Flac)
+    } else {
+        Err(invalid_data("unsupported ogg codec"))
+    }
+}
+
+fn headers_complete(codec: Codec, packets: &[Vec<u8>]) -> bool {
+    match codec {
+        Codec::Vorbis => packets.len() >= 3,
+        Codec::Opus => packets.len() >= 2,
+        // Every metadata block is a packet, the last one is flagged.
+        Codec::Flac => {
+            packets.len() >= 2
+                && matches!(packets.last().and_then(|p| p.first()), Some(b) if b & 0x80 != 0)
+        }
+    }
+}
+
+/// Splits the packets into pages, the last page is always completed. Pages on which no packet
+/// ends have a granule position of -1.
+fn paginate(serial: u32, first_sequence: u32, packets: &[Vec<u8>]) -> Vec<Page> {
+    let mut pages = Vec::new();
+    let mut page = Page {
+        header_type: 0,
+        granule: 0,
+        serial,
+        sequence: first_sequence,
+        lacing: Vec::new(),
+        data: Vec::new(),
+    };
+
+    for packet in packets.iter() {
+        let mut remaining = packet.as_slice();
+        loop {
+            if page.lacing.len() == MAX_SEGMENTS {
+                let sequence = page.sequence + 1;
+                if page.lacing.iter().all(|l| *l == 255) {
+                    page.granule = u64::MAX;
+                }
+                let next = Page {
+                    header_type: if page.lacing.last() == Some(&255) { CONTINUED } else { 0 },
+                    granule: 0,
+                    serial,
+                    sequence,
+                    lacing: Vec::new(),
+                    data: Vec::new(),
+                };
+                pages.push(std::mem::replace(&mut page, next));
+            }
+
+            let len = remaining.len().min(255);
+            page.lacing.push(len as u8);
+            page.data.extend_from_slice(&remaining[..len]);
+            remaining = &remaining[len..];
+
+            if len < 255 {
+                break;
+            }
+        }
+    }
+
+    if !page.lacing.is_empty() {
+        pages.push(page);
+    }
+
+    pages
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    data.iter().fold(0, |crc, b| (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ b) as usize])
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,596 @@
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use symphonia::core::codecs::{self, CodecParameters, CodecType, CODEC_TYPE_NULL};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::{mp4, Format};
+
+/// Properties of the audio stream, read from the headers of the file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AudioProperties {
+    pub duration: Option<Duration>,
+    /// The average bitrate in kbit/s.
+    pub bitrate: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u8>,
+    pub channels: Option<u8>,
+    pub codec: Option<String>,
+    /// Like `CBR` or `VBR` for MP3 and `LC` or `HE` for AAC.
+    pub profile: Option<String>,
+    pub lossless: Option<bool>,
+}
+
+/// A property that might differ between the songs of a release.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Property {
+    Codec,
+    Bitrate,
+    SampleRate,
+    BitDepth,
+    Channels,
+}
+
+const MP3_BITRATES: [[u16; 15]; 5] = [
+    [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+    [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+const MUSEPACK_SAMPLE_RATES: [u32; 4] = [44100, 48000, 37800, 32000];
+const WAVPACK_SAMPLE_RATES: [u32; 15] = [
+    6000, 8000, 9600, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000, 64000, 88200, 96000,
+    192000,
+];
+
+impl AudioProperties {
+    pub fn read(path: &Path, format: Format) -> io::Result<Self> {
+        match format {
+            Format::Mp3 => read_mp3(path),
+            Format::Ape => read_ape(path),
+            Format::WavPack => read_wavpack(path),
+            Format::Musepack => read_musepack(path),
+            Format::Mp4
+            | Format::Flac
+            | Format::Ogg
+            | Format::Opus
+            | Format::Wav
+            | Format::Aiff => read_container(path, format),
+        }
+    }
+
+    /// Differences that matter when comparing the songs of a release. Bitrates of lossy songs
+    /// have to differ by more than a quarter, so VBR songs don't get flagged.
+    pub fn differences(&self, other: &Self) -> Vec<Property> {
+        let mut diff = Vec::new();
+        if self.codec != other.codec || self.lossless != other.lossless {
+            diff.push(Property::Codec);
+        }
+        if let (Some(false), Some(a), Some(b)) = (self.lossless, self.bitrate, other.bitrate) {
+            if a.max(b) * 4 > a.min(b) * 5 {
+                diff.push(Property::Bitrate);
+            }
+        }
+        if self.sample_rate != other.sample_rate {
+            diff.push(Property::SampleRate);
+        }
+        if self.bit_depth != other.bit_depth {
+            diff.push(Property::BitDepth);
+        }
+        if self.channels != other.channels {
+            diff.push(Property::Channels);
+        }
+        diff
+    }
+
+    /// Compares the quality of two songs. Lossless beats lossy, lossless songs are compared by
+    /// bit depth and sample rate and lossy ones by bitrate.
+    pub fn cmp_quality(&self, other: &Self) -> Ordering {
+        let key = |p: &Self| match p.lossless {
+            Some(true) => (true, p.bit_depth.map(|b| b as u32), p.sample_rate),
+            _ => (false, p.bitrate, p.sample_rate),
+        };
+        key(self).cmp(&key(other))
+    }
+}
+
+impl fmt::Display for AudioProperties {
+    /// Formats the known properties like `MP3 VBR 245 kbit/s 44.1 kHz 2 ch`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts = [
+            self.codec.clone(),
+            self.profile.clone(),
+            self.bitrate.map(|b| format!("{} kbit/s", b)),
+            self.sample_rate.map(|r| format!("{} kHz", r as f64 / 1000.0)),
+            self.bit_depth.map(|b| format!("{} bit", b)),
+            self.channels.map(|c| format!("{} ch", c)),
+        ];
+        let parts: Vec<String> = parts.iter().flatten().cloned().collect();
+        match parts.is_empty() {
+            true => write!(f, "unknown"),
+            false => write!(f, "{}", parts.join(" ")),
+        }
+    }
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Self::Codec => "codec",
+            Self::Bitrate => "bitrate",
+            Self::SampleRate => "sample rate",
+            Self::BitDepth => "bit depth",
+            Self::Channels => "channels",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Probes the container with symphonia, which only reads the headers.
+fn read_container(path: &Path, format: Format) -> io::Result<AudioProperties> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(format.extensions()[0]);
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let params = probed
+        .format
+        .tracks()
+        .iter()
+        .map(|t| &t.codec_params)
+        .find(|p| p.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no audio track"))?;
+
+    let duration = params_duration(params);
+    let (codec, lossless) = codec_name(params.codec);
+    // The size of the media data is only known for MPEG-4, otherwise the tags are included
+    let media_len = match format {
+        Format::Mp4 => mp4::media_data_len(path).unwrap_or(len),
+        _ => len,
+    };
+
+    Ok(AudioProperties {
+        duration,
+        bitrate: duration.and_then(|d| bitrate(media_len, d)),
+        sample_rate: params.sample_rate,
+        bit_depth: params.bits_per_sample.map(|b| b as u8),
+        channels: params.channels.map(|c| c.count() as u8),
+        codec: codec.map(|c| c.to_string()),
+        profile: aac_profile(params).map(|p| p.to_string()),
+        lossless,
+    })
+}
+
+fn params_duration(params: &CodecParameters) -> Option<Duration> {
+    let frames = params.n_frames?;
+    match (params.time_base, params.sample_rate) {
+        (Some(tb), _) => {
+            let time = tb.calc_time(frames);
+            Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+        }
+        (None, Some(rate)) => samples_duration(frames, rate),
+        (None, None) => None,
+    }
+}
+
+fn codec_name(codec: CodecType) -> (Option<&'static str>, Option<bool>) {
+    let (name, lossless) = match codec {
+        codecs::CODEC_TYPE_MP1 => ("MP1", false),
+        codecs::CODEC_TYPE_MP2 => ("MP2", false),
+        codecs::CODEC_TYPE_MP3 => ("MP3", false),
+        codecs::CODEC_TYPE_AAC => ("AAC", false),
+        codecs::CODEC_TYPE_VORBIS => ("Vorbis", false),
+        codecs::CODEC_TYPE_OPUS => ("Opus", false),
+        codecs::CODEC_TYPE_FLAC => ("FLAC", true),
+        codecs::CODEC_TYPE_ALAC => ("ALAC", true),
+        c => {
+            // There are lots of PCM variants, which are named like `pcm_s16le`
+            let name = symphonia::default::get_codecs().get_codec(c).map(|d| d.short_name);
+            match name {
+                Some(n) if n.starts_with("pcm") => ("PCM", true),
+                Some(n) if n.starts_with("adpcm") => ("ADPCM", false),
+                _ => return (None, None),
+            }
+        }
+    };
+    (Some(name), Some(lossless))
+}
+
+/// The audio object type is stored in the first 5 bits of the AudioSpecificConfig.
+fn aac_profile(params: &CodecParameters) -> Option<&'static str> {
+    if params.codec != codecs::CODEC_TYPE_AAC {
+        return None;
+    }
+    match params.extra_data.as_ref()?.first()? >> 3 {
+        1 => Some("Main"),
+        2 => Some("LC"),
+        5 => Some("HE"),
+        29 => Some("HEv2"),
+        _ => None,
+    }
+}
+
+/// The duration of a number of samples, `None` if the sample rate from the file is invalid.
+fn samples_duration(samples: u64, rate: u32) -> Option<Duration> {
+    match rate {
+        0 => None,
+        r => Duration::try_from_secs_f64(samples as f64 / r as f64).ok(),
+    }
+}
+
+fn bitrate(bytes: u64, duration: Duration) -> Option<u32> {
+    match duration.as_secs_f64() {
+        s if s > 0.0 => Some((bytes as f64 * 8.0 / s / 1000.0).round() as u32),
+        _ => None,
+    }
+}
+
+/// Reads the first MPEG frame header and a following Xing, Info or VBRI header.
+fn read_mp3(path: &Path) -> io::Result<AudioProperties> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let mut header = [0; 10];
+    file.read_exact(&mut header)?;
+    let mut start = 0;
+    if &header[0..3] == b"ID3" {
+        let size = header[6..10].iter().fold(0u64, |s, b| (s << 7) | (*b & 0x7F) as u64);
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        start = 10 + size + footer;
+    }
+
+    let mut end = len;
+    if len >= 128 {
+        let mut tag = [0; 3];
+        file.seek(SeekFrom::Start(len - 128))?;
+        file.read_exact(&mut tag)?;
+        if &tag == b"TAG" {
+            end -= 128;
+        }
+    }
+
+    let mut buf = vec![0; 64 * 1024];
+    file.seek(SeekFrom::Start(start))?;
+    let read = file.read(&mut buf)?;
+    buf.truncate(read);
+
+    let (offset, frame) = (0..buf.len().saturating_sub(4))
+        .find_map(|i| Mp3Frame::parse_synced(&buf[i..]).map(|f| (i, f)))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no MPEG frame found"))?;
+    let frame_data = &buf[offset..];
+
+    let side_info = match (frame.version == 3, frame.mono) {
+        (true, false) => 32,
+        (true, true) => 17,
+        (false, false) => 17,
+        (false, true) => 9,
+    };
+    let xing = frame_data.get(4 + side_info..4 + side_info + 16);
+    let vbri = frame_data.get(36..54);
+
+    let (vbr, frames, bytes) = match (xing, vbri) {
+        (Some(x), _) if &x[0..4] == b"Xing" || &x[0..4] == b"Info" => {
+            let flags = u32::from_be_bytes(x[4..8].try_into().unwrap());
+            let mut pos = 8;
+            let mut field = |present: bool| {
+                if !present {
+                    return None;
+                }
+                let v = u32::from_be_bytes(x[pos..pos + 4].try_into().unwrap());
+                pos += 4;
+                Some(v as u64)
+            };
+            let frames = field(flags & 1 != 0);
+            let bytes = field(flags & 2 != 0);
+            (&x[0..4] == b"Xing", frames, bytes)
+        }
+        (_, Some(v)) if &v[0..4] == b"VBRI" => {
+            let bytes = u32::from_be_bytes(v[10..14].try_into().unwrap());
+            let frames = u32::from_be_bytes(v[14..18].try_into().unwrap());
+            (true, Some(frames as u64), Some(bytes as u64))
+        }
+        _ => (false, None, None),
+    };
+
+    let audio_len = bytes.unwrap_or_else(|| end.saturating_sub(start + offset as u64));
+    let duration = match frames {
+        Some(f) => {
+            let samples = f * frame.samples_per_frame() as u64;
+            samples_duration(samples, frame.sample_rate)
+        }
+        None if frame.bitrate > 0 => {
+            Some(Duration::from_secs_f64(audio_len as f64 * 8.0 / (frame.bitrate as f64 * 1000.0)))
+        }
+        None => None,
+    };
+    let bitrate = match (vbr, duration) {
+        (true, Some(d)) => bitrate(audio_len, d),
+        _ => Some(frame.bitrate as u32).filter(|b| *b > 0),
+    };
+
+    Ok(AudioProperties {
+        duration,
+        bitrate,
+        sample_rate: Some(frame.sample_rate),
+        bit_depth: None,
+        channels: Some(if frame.mono { 1 } else { 2 }),
+        codec: Some(format!("MP{}", 4 - frame.layer)),
+        profile: Some(if vbr { "VBR" } else { "CBR" }.to_string()),
+        lossless: Some(false),
+    })
+}
+
+struct Mp3Frame {
+    /// 0 is MPEG 2.5, 2 is MPEG 2 and 3 is MPEG 1.
+    version: u8,
+    /// 1 is layer III, 3 is layer I.
+    layer: u8,
+    bitrate: u16,
+    sample_rate: u32,
+    mono: bool,
+    padding: bool,
+}
+
+impl Mp3Frame {
+    /// Parses a frame header that is followed by another one of the same stream, so bytes that
+    /// only look like a header aren't mistaken for the first frame. Frames at the end of the data
+    /// and free format frames, whose length is unknown, can't be checked.
+    fn parse_synced(data: &[u8]) -> Option<Self> {
+        let frame = Self::parse(data)?;
+        let next = match frame.len().and_then(|l| data.get(l..)) {
+            Some(n) if n.len() >= 4 => n,
+            _ => return Some(frame),
+        };
+
+        match Self::parse(next) {
+            Some(n)
+                if (n.version, n.layer, n.sample_rate)
+                    == (frame.version, frame.layer, frame.sample_rate) =>
+            {
+                Some(frame)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse(h: &[u8]) -> Option<Self> {
+        if h.len() < 4 || h[0] != 0xFF || h[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+
+        let version = (h[1] >> 3) & 3;
+        let layer = (h[1] >> 1) & 3;
+        let bitrate_index = (h[2] >> 4) as usize;
+        let rate_index = ((h[2] >> 2) & 3) as usize;
+        if version == 1 || layer == 0 || bitrate_index == 15 || rate_index == 3 {
+            return None;
+        }
+
+        let table = match (version, layer) {
+            (3, 3) => 0,
+            (3, 2) => 1,
+            (3, _) => 2,
+            (_, 3) => 3,
+            _ => 4,
+        };
+        let base_rate = [44100, 48000, 32000][rate_index];
+        let sample_rate = match version {
+            3 => base_rate,
+            2 => base_rate / 2,
+            _ => base_rate / 4,
+        };
+
+        Some(Self {
+            version,
+            layer,
+            bitrate: MP3_BITRATES[table][bitrate_index],
+            sample_rate,
+            mono: h[3] >> 6 == 3,
+            padding: (h[2] >> 1) & 1 == 1,
+        })
+    }
+
+    /// The length of the frame including its header, unknown for free format frames.
+    fn len(&self) -> Option<usize> {
+        if self.bitrate == 0 {
+            return None;
+        }
+
+        let bits = self.bitrate as usize * 1000;
+        let rate = self.sample_rate as usize;
+        let padding = self.padding as usize;
+        match (self.layer, self.version) {
+            (3, _) => Some((12 * bits / rate + padding) * 4),
+            (1, 0) | (1, 2) => Some(72 * bits / rate + padding),
+            _ => Some(144 * bits / rate + padding),
+        }
+    }
+
+    fn samples_per_frame(&self) -> u32 {
+        match (self.layer, self.version) {
+            (3, _) => 384,
+            (1, 0) | (1, 2) => 576,
+            _ => 1152,
+        }
+    }
+}
+
+/// Reads the header of Monkey's Audio files since version 3.98.
+fn read_ape(path: &Path) -> io::Result<AudioProperties> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut data = vec![0; 1024];
+    let read = file.read(&mut data)?;
+    data.truncate(read);
+
+    // The file might start with an ID3 tag
+    let start = data.windows(4).position(|w| w == b"MAC ").unwrap_or(0);
+    let d = &data[start..];
+    let le_u16 = |p: usize| d.get(p..p + 2).map(|b| u16::from_le_bytes(b.try_into().unwrap()));
+    let le_u32 = |p: usize| d.get(p..p + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+
+    let mut props = AudioProperties {
+        codec: Some("Monkey's Audio".to_string()),
+        lossless: Some(true),
+        ..Default::default()
+    };
+    if !matches!(le_u16(4), Some(v) if v >= 3980) {
+        return Ok(props);
+    }
+
+    let h = le_u32(8).unwrap_or(0) as usize;
+    let (blocks_per_frame, final_blocks, total_frames) =
+        match (le_u32(h + 4), le_u32(h + 8), le_u32(h + 12)) {
+            (Some(a), Some(b), Some(c)) => (a as u64, b as u64, c as u64),
+            _ => return Ok(props),
+        };
+    props.bit_depth = le_u16(h + 16).map(|b| b as u8);
+    props.channels = le_u16(h + 18).map(|c| c as u8);
+    props.sample_rate = le_u32(h + 20).filter(|r| *r > 0);
+
+    if let (Some(rate), true) = (props.sample_rate, total_frames > 0) {
+        let samples = (total_frames - 1) * blocks_per_frame + final_blocks;
+        props.duration = samples_duration(samples, rate);
+        props.bitrate = props.duration.and_then(|d| bitrate(len, d));
+    }
+
+    Ok(props)
+}
+
+/// Reads the stream header of Musepack SV7 and SV8 files.
+fn read_musepack(path: &Path) -> io::Result<AudioProperties> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut data = vec![0; 1024];
+    let read = file.read(&mut data)?;
+    data.truncate(read);
+
+    let mut props = AudioProperties {
+        codec: Some("Musepack".to_string()),
+        lossless: Some(false),
+        ..Default::default()
+    };
+
+    // The file might start with an ID3 tag
+    let samples = if let Some(start) = data.windows(4).position(|w| w == b"MPCK") {
+        musepack_sv8(&data[start + 4..], &mut props)
+    } else if let Some(start) = data.windows(3).position(|w| w == b"MP+") {
+        musepack_sv7(&data[start..], &mut props)
+    } else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "missing Musepack header"));
+    };
+
+    if let (Some(samples), Some(rate)) = (samples, props.sample_rate) {
+        props.duration = samples_duration(samples, rate);
+        props.bitrate = props.duration.and_then(|d| bitrate(len, d));
+    }
+
+    Ok(props)
+}
+
+/// SV7 streams are always stereo and consist of frames with 1152 samples. Returns the number of
+/// samples.
+fn musepack_sv7(d: &[u8], props: &mut AudioProperties) -> Option<u64> {
+    let le_u32 = |p: usize| d.get(p..p + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+    if d.get(3)? & 0xF < 7 {
+        return None;
+    }
+
+    let frames = le_u32(4)? as u64;
+    let flags = le_u32(8)?;
+    props.sample_rate = Some(MUSEPACK_SAMPLE_RATES[((flags >> 16) & 3) as usize]);
+    props.channels = Some(2);
+
+    // The last frame is only half used when the stream isn't gapless
+    Some((frames * 1152).saturating_sub(576))
+}
+
+/// SV8 files consist of packets with a two letter key and a variable length size, the stream
+/// header packet `SH` contains the properties. Returns the number of samples.
+fn musepack_sv8(d: &[u8], props: &mut AudioProperties) -> Option<u64> {
+    let mut pos = 0;
+    loop {
+        let key = d.get(pos..pos + 2)?;
+        let mut header_pos = pos + 2;
+        let size = musepack_size(d, &mut header_pos)? as usize;
+        if key == b"SH" {
+            pos = header_pos;
+            break;
+        }
+        if size == 0 {
+            return None;
+        }
+        // The size comes from the file, packets beyond the read data end the search
+        pos = pos.checked_add(size).filter(|p| *p < d.len())?;
+    }
+
+    // Skip the CRC and the version
+    pos += 5;
+    let samples = musepack_size(d, &mut pos)?;
+    let silence = musepack_size(d, &mut pos)?;
+    let flags = u16::from_be_bytes(d.get(pos..pos + 2)?.try_into().unwrap());
+    props.sample_rate = MUSEPACK_SAMPLE_RATES.get((flags >> 13) as usize).copied();
+    props.channels = Some(((flags >> 4) & 0xF) as u8 + 1);
+
+    Some(samples.saturating_sub(silence))
+}
+
+/// Reads a size, which is stored in 7 bits per byte with the highest bit marking that more
+/// bytes follow.
+fn musepack_size(d: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut size = 0u64;
+    for _ in 0..9 {
+        let b = *d.get(*pos)?;
+        *pos += 1;
+        size = (size << 7) | (b & 0x7F) as u64;
+        if b & 0x80 == 0 {
+            return Some(size);
+        }
+    }
+    None
+}
+
+/// Reads the header of the first WavPack block.
+fn read_wavpack(path: &Path) -> io::Result<AudioProperties> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut h = [0; 32];
+    file.read_exact(&mut h)?;
+    if &h[0..4] != b"wvpk" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "missing WavPack block"));
+    }
+
+    let total_samples = u32::from_le_bytes(h[12..16].try_into().unwrap());
+    let flags = u32::from_le_bytes(h[24..28].try_into().unwrap());
+    let sample_rate = WAVPACK_SAMPLE_RATES.get(((flags >> 23) & 0xF) as usize).copied();
+    let hybrid = flags & 0x8 != 0;
+
+    let duration = match (sample_rate, total_samples) {
+        (Some(r), t) if t != u32::MAX => samples_duration(t as u64, r),
+        _ => None,
+    };
+
+    Ok(AudioProperties {
+        duration,
+        bitrate: duration.and_then(|d| bitrate(len, d)),
+        sample_rate,
+        bit_depth: Some(((flags & 3) as u8 + 1) * 8),
+        channels: Some(if flags & 0x4 != 0 { 1 } else { 2 }),
+        codec: Some("WavPack".to_string()),
+        profile: Some(if hybrid { "Hybrid" } else { "Lossless" }.to_string()),
+        lossless: Some(!hybrid),
+    })
+}
@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+use std::error;
+use std::fs;
+use std::path::Path;
+
+use crate::{Finding, Proposal, Subject, Value};
+
+/// Answers to the checks that are applied before anything is asked, so they can run
+/// unattended. Empty fields are skipped when writing, since TOML doesn't allow values after
+/// tables.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Resolutions {
+    /// Fill in missing and unify inconsistent total tracks and discs without asking.
+    #[serde(skip_serializing_if = "is_false")]
+    pub infer_totals: bool,
+    /// Release artists that are named similarly, but aren't the same.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub distinct_artists: Vec<Vec<ArtistNames>>,
+    /// Release artists that are always renamed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub artists: Vec<ArtistResolution>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub releases: Vec<ReleaseResolution>,
+}
+
+/// The names of one or multiple release artists, written as a string or an array of strings.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "OneOrMany", into = "OneOrMany")]
+pub struct ArtistNames(pub Vec<String>);
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<OneOrMany> for ArtistNames {
+    fn from(names: OneOrMany) -> Self {
+        match names {
+            OneOrMany::One(n) => Self(vec![n]),
+            OneOrMany::Many(n) => Self(n),
+        }
+    }
+}
+
+impl From<ArtistNames> for OneOrMany {
+    fn from(mut names: ArtistNames) -> Self {
+        match names.0.len() {
+            1 => Self::One(names.0.remove(0)),
+            _ => Self::Many(names.0),
+        }
+    }
+}
+
+impl ArtistNames {
+    fn new(names: &[String]) -> Self {
+        Self(names.to_vec())
+    }
+}
+
+/// Release artists that are renamed, like `from = "Beyonce"` and `to = "Beyoncé"`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArtistResolution {
+    pub from: ArtistNames,
+    pub to: ArtistNames,
+}
+
+/// Releases of an artist that are named similarly.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReleaseResolution {
+    pub artist: ArtistNames,
+    pub names: Vec<String>,
+    /// The name the releases are merged into, they are kept separate if there is none.
+    pub merge: Option<String>,
+}
+
+impl Resolutions {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn error::Error>> {
+        let content = fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Writes to a temporary file next to `path` first, which then replaces it, so the file
+    /// isn't lost if writing fails.
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn error::Error>> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, toml::to_string(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Adds the answers of `other`, the existing ones take precedence.
+    pub fn merge(&mut self, other: Resolutions) {
+        self.infer_totals |= other.infer_totals;
+        self.distinct_artists.extend(other.distinct_artists);
+        self.artists.extend(other.artists);
+        self.releases.extend(other.releases);
+    }
+
+    /// The names artists are renamed to, `None` if none of them are. Every run of names that
+    /// is listed is replaced, so `Beyonce` is also renamed in `Beyonce, Jay-Z`, longer runs are
+    /// preferred.
+    pub fn artist_mapping(&self, names: &[String]) -> Option<Vec<String>> {
+        let mut mapped: Vec<String> = Vec::with_capacity(names.len());
+        let mut pos = 0;
+        while pos < names.len() {
+            let rest = &names[pos..];
+            let renamed = self
+                .artists
+                .iter()
+                .filter(|a| !a.from.0.is_empty() && rest.starts_with(&a.from.0))
+                .max_by_key(|a| a.from.0.len());
+            let (len, to) = match renamed {
+                Some(a) => (a.from.0.len(), &a.to.0[..]),
+                None => (1, &rest[..1]),
+            };
+            for n in to {
+                if !mapped.contains(n) {
+                    mapped.push(n.clone());
+                }
+            }
+            pos += len;
+        }
+
+        Some(mapped).filter(|m| m != names)
+    }
+
+    pub fn are_distinct_artists(&self, a: &[String], b: &[String]) -> bool {
+        self.distinct_artists
+            .iter()
+            .any(|d| d.iter().any(|n| n.0 == a) && d.iter().any(|n| n.0 == b))
+    }
+
+    /// The resolution of similarly named releases, if all of them are listed.
+    pub fn release_resolution(
+        &self,
+        artists: &[String],
+        names: &[&str],
+    ) -> Option<&ReleaseResolution> {
+        self.releases
+            .iter()
+            .find(|r| r.artist.0 == artists && names.iter().all(|n| r.names.iter().any(|o| o == n)))
+    }
+
+    /// Records the proposal that was applied to a finding about similarly named release artists
+    /// or releases, `None` if they were kept.
+    pub fn record(&mut self, finding: &Finding, proposal: Option<&Proposal>) {
+        let update = proposal.and_then(|p| p.updates.first()).map(|(_, u)| u);
+        match &finding.subject {
+            Subject::ReleaseArtists(a, b) => {
+                let value = update.map_or(Value::Unchanged, |u| u.release_artists.clone());
+                self.record_artists(a, b, &value);
+            }
+            Subject::Releases(artists, names) => {
+                let value = update.map_or(Value::Unchanged, |u| u.release.clone());
+                self.record_releases(artists, names, &value);
+            }
+            Subject::Other => (),
+        }
+    }
+
+    /// Records the decision on two similarly named release artists.
+    pub fn record_artists(&mut self, a: &[String], b: &[String], value: &Value<Vec<String>>) {
+        match value {
+            Value::Update(names) => {
+                // Artists that were renamed to one of these follow the new name
+                for r in self.artists.iter_mut() {
+                    if r.to.0 == a || r.to.0 == b {
+                        r.to = ArtistNames::new(names);
+                    }
+                }
+                for n in [a, b].iter().filter(|n| **n != &names[..]) {
+                    self.artists.retain(|r| r.from.0 != *n);
+                    self.artists.push(ArtistResolution {
+                        from: ArtistNames::new(n),
+                        to: ArtistNames::new(names),
+                    });
+                }
+            }
+            Value::Remove => (),
+            Value::Unchanged => {
+                if !self.are_distinct_artists(a, b) {
+                    self.distinct_artists.push(vec![ArtistNames::new(a), ArtistNames::new(b)]);
+                }
+            }
+        }
+    }
+
+    /// Records the decision on similarly named releases of an artist.
+    pub fn record_releases(&mut self, artists: &[String], names: &[&str], value: &Value<String>) {
+        let merge = match value {
+            Value::Update(n) => Some(n.clone()),
+            Value::Remove => return,
+            Value::Unchanged => None,
+        };
+
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        self.releases
+            .retain(|r| !(r.artist.0 == artists && names.iter().all(|n| r.names.contains(n))));
+        self.releases.push(ReleaseResolution { artist: ArtistNames::new(artists), names, merge });
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(n: &[&str]) -> Vec<String> {
+        n.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn rename(from: &[&str], to: &[&str]) -> ArtistResolution {
+        ArtistResolution { from: ArtistNames(names(from)), to: ArtistNames(names(to)) }
+    }
+
+    #[test]
+    fn artist_mapping() {
+        let r = Resolutions {
+            artists: vec![
+                rename(&["Beyonce"], &["Beyoncé"]),
+                rename(&["A", "B"], &["A & B"]),
+                rename(&["A"], &["Alice"]),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(r.artist_mapping(&names(&["Beyonce"])), Some(names(&["Beyoncé"])));
+        assert_eq!(
+            r.artist_mapping(&names(&["Jay-Z", "Beyonce"])),
+            Some(names(&["Jay-Z", "Beyoncé"]))
+        );
+        // Longer runs are preferred
+        assert_eq!(r.artist_mapping(&names(&["A", "B", "C"])), Some(names(&["A & B", "C"])));
+        assert_eq!(r.artist_mapping(&names(&["A", "C"])), Some(names(&["Alice", "C"])));
+        // Names aren't listed twice
+        assert_eq!(r.artist_mapping(&names(&["Beyonce", "Beyoncé"])), Some(names(&["Beyoncé"])));
+        assert_eq!(r.artist_mapping(&names(&["Jay-Z"])), None);
+    }
+}
@@ -0,0 +1,84 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const RIFF: &[u8; 4] = b"RIFF";
+const WAVE: &[u8; 4] = b"WAVE";
+const LIST: &[u8; 4] = b"LIST";
+const INFO: &[u8; 4] = b"INFO";
+
+/// The fields of a RIFF `LIST/INFO` chunk.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Info {
+    pub fields: Vec<([u8; 4], String)>,
+}
+
+impl Info {
+    pub fn read_from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+
+        let mut header = [0; 12];
+        file.read_exact(&mut header)?;
+        if &header[0..4] != RIFF || &header[8..12] != WAVE {
+            return Err(invalid_data("not a RIFF WAVE file"));
+        }
+        let end = 8 + u32::from_le_bytes(header[4..8].try_into().unwrap()) as u64;
+
+        let mut pos = 12;
+        while pos + 8 <= end {
+            let mut chunk = [0; 8];
+            file.seek(SeekFrom::Start(pos))?;
+            file.read_exact(&mut chunk)?;
+            let len = u32::from_le_bytes(chunk[4..8].try_into().unwrap()) as u64;
+
+            // Chunks that extend past the end of the file are corrupt and skipped
+            if &chunk[0..4] == LIST && len >= 4 && len <= file_len - (pos + 8) {
+                let mut data = vec![0; len as usize];
+                file.read_exact(&mut data)?;
+
+                if &data[0..4] == INFO {
+                    return Ok(Self { fields: parse_fields(&data[4..]) });
+                }
+            }
+
+            // Chunks are padded to an even length
+            pos += 8 + len + (len & 1);
+        }
+
+        Err(io::Error::new(io::ErrorKind::NotFound, "no LIST/INFO chunk found"))
+    }
+
+    pub fn value(&self, id: &[u8; 4]) -> Option<&str> {
+        self.fields.iter().find(|(i, _)| i == id).map(|(_, v)| v.as_str())
+    }
+}
+
+fn parse_fields(data: &[u8]) -> Vec<([u8; 4], String)> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+
+    while pos + 8 <= data.len() {
+        let id: [u8; 4] = data[pos..pos + 4].try_into().unwrap();
+        let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let value = match data.get(pos + 8..pos + 8 + len) {
+            Some(v) => v,
+            None => break,
+        };
+
+        let value = value.split(|b| *b == 0).next().unwrap_or_default();
+        let value = String::from_utf8_lossy(value).trim().to_string();
+        if !value.is_empty() {
+            fields.push((id, value));
+        }
+
+        pos += 8 + len + (len & 1);
+    }
+
+    fields
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
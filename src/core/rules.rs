@@ -0,0 +1,724 @@
+use crate::checks::{
+    compilation_candidates, discs, group_totals, highest, inconsistent_dates, numbering_issues,
+    property_differences, release_names, similar_artists, similar_releases,
+};
+use crate::{
+    Checks, DateKind, NumberingIssue, Release, ReleaseArtists, Song, TagUpdate, Totals, Value,
+};
+
+/// How serious a finding is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something that might be intended, like a release that mixes bitrates.
+    Info,
+    /// Tags that are probably inconsistent.
+    Warning,
+    /// Tags that are certainly wrong.
+    Error,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// A way to resolve a finding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Proposal<'a> {
+    pub description: String,
+    pub updates: Vec<(&'a Song, TagUpdate)>,
+}
+
+impl<'a> Proposal<'a> {
+    /// Proposes to apply the update to all songs that it changes.
+    pub fn new(description: String, songs: &[&'a Song], update: TagUpdate) -> Self {
+        let updates = songs
+            .iter()
+            .filter(|s| {
+                let mut updated = (**s).clone();
+                update.apply_to(&mut updated);
+                // Release artists that fall back to the artists aren't written again
+                updated.has_release_artists = s.has_release_artists;
+                updated != ***s
+            })
+            .map(|s| (*s, update.clone()))
+            .collect();
+        Self { description, updates }
+    }
+}
+
+/// A tag field that a value can be entered for, which all songs of a finding are set to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    ReleaseArtists,
+    Release,
+    Date(DateKind),
+    TotalTracks,
+    TotalDiscs,
+}
+
+impl Field {
+    /// Whether the update sets or removes the field.
+    pub fn is_updated(self, update: &TagUpdate) -> bool {
+        match self {
+            Self::ReleaseArtists => !update.release_artists.is_unchanged(),
+            Self::Release => !update.release.is_unchanged(),
+            Self::Date(DateKind::Release) => !update.release_date.is_unchanged(),
+            Self::Date(DateKind::Original) => !update.original_date.is_unchanged(),
+            Self::TotalTracks => !update.total_tracks.is_unchanged(),
+            Self::TotalDiscs => !update.total_discs.is_unchanged(),
+        }
+    }
+
+    /// Whether the songs have the same value.
+    fn same(self, a: &Song, b: &Song) -> bool {
+        match self {
+            Self::ReleaseArtists => a.release_artists == b.release_artists,
+            Self::Release => a.release == b.release,
+            Self::Date(kind) => a.date(kind) == b.date(kind),
+            Self::TotalTracks => a.total_tracks == b.total_tracks,
+            Self::TotalDiscs => a.total_discs == b.total_discs,
+        }
+    }
+
+    fn copy(self, from: &TagUpdate, to: &mut TagUpdate) {
+        match self {
+            Self::ReleaseArtists => to.release_artists = from.release_artists.clone(),
+            Self::Release => to.release = from.release.clone(),
+            Self::Date(DateKind::Release) => to.release_date = from.release_date,
+            Self::Date(DateKind::Original) => to.original_date = from.original_date,
+            Self::TotalTracks => to.total_tracks = from.total_tracks,
+            Self::TotalDiscs => to.total_discs = from.total_discs,
+        }
+    }
+}
+
+/// What a finding is about, so the resolutions can decide on it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Subject<'a> {
+    /// Two release artists that are named similarly.
+    ReleaseArtists(&'a [String], &'a [String]),
+    /// Releases of a release artist that are named similarly.
+    Releases(&'a [String], Vec<&'a str>),
+    Other,
+}
+
+/// Something a check found, with the changes that would resolve it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding<'a> {
+    pub severity: Severity,
+    pub message: String,
+    /// Lines that are shown below the message, like the values of the songs.
+    pub details: Vec<String>,
+    pub songs: Vec<&'a Song>,
+    pub proposals: Vec<Proposal<'a>>,
+    /// The field the proposals set, which can also be set to an entered value.
+    pub field: Option<Field>,
+    pub subject: Subject<'a>,
+}
+
+/// A rule that checks the songs grouped by their release artists and releases.
+pub trait Check {
+    /// The name the check is enabled and disabled by, like `release-artists`.
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    fn check<'a>(&self, artists: &[ReleaseArtists<'a>]) -> Vec<Finding<'a>>;
+}
+
+/// The checks that can be run, all built-in checks are registered and enabled by default.
+pub struct CheckRegistry {
+    checks: Vec<(Box<dyn Check>, bool)>,
+}
+
+impl Default for CheckRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.add(ReleaseArtistsCheck);
+        registry.add(ReleasesCheck);
+        registry.add(DatesCheck(DateKind::Release));
+        registry.add(DatesCheck(DateKind::Original));
+        registry.add(CompilationsCheck);
+        registry.add(MixedPropertiesCheck);
+        registry.add(TotalTracksCheck);
+        registry.add(TotalDiscsCheck);
+        registry.add(TrackNumbersCheck);
+        registry
+    }
+}
+
+impl CheckRegistry {
+    /// A registry without any checks.
+    pub fn empty() -> Self {
+        Self { checks: Vec::new() }
+    }
+
+    /// Adds an enabled check, a check with the same name is replaced.
+    pub fn add(&mut self, check: impl Check + 'static) {
+        let check: Box<dyn Check> = Box::new(check);
+        match self.checks.iter_mut().find(|(c, _)| c.name() == check.name()) {
+            Some(c) => *c = (check, true),
+            None => self.checks.push((check, true)),
+        }
+    }
+
+    /// Enables or disables a check, returns false if there is no check with the name.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.checks.iter_mut().find(|(c, _)| c.name() == name) {
+            Some((_, e)) => {
+                *e = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Only enables the checks with the names, returns the first name without a check.
+    pub fn enable_only<'n>(&mut self, names: &[&'n str]) -> Result<(), &'n str> {
+        if let Some(n) = names.iter().find(|n| !self.checks.iter().any(|(c, _)| c.name() == **n)) {
+            return Err(n);
+        }
+        for (c, e) in self.checks.iter_mut() {
+            *e = names.contains(&c.name());
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.checks.iter().any(|(c, e)| *e && c.name() == name)
+    }
+
+    /// All checks and whether they are enabled.
+    pub fn checks(&self) -> impl Iterator<Item = (&dyn Check, bool)> {
+        self.checks.iter().map(|(c, e)| (c.as_ref(), *e))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.checks.iter().map(|(c, _)| c.name())
+    }
+}
+
+impl<'a> Checks<'a> {
+    /// Runs the enabled checks of the registry. Release artists are renamed by the resolutions
+    /// first and findings they decide on are applied without asking. `f` is called with every
+    /// other finding and returns the proposal that is applied. Findings whose field was already
+    /// changed for one of their songs are skipped and songs that were changed to a value of a
+    /// finding before follow the new one, so decisions can be chained.
+    pub fn run(
+        &mut self,
+        registry: &CheckRegistry,
+        mut f: impl FnMut(&dyn Check, &Finding<'a>) -> Option<Proposal<'a>>,
+    ) {
+        self.rename_artists();
+        for (check, _) in registry.checks().filter(|(_, e)| *e) {
+            for finding in check.check(&self.artists) {
+                if let Some(field) = finding.field {
+                    if finding.songs.iter().any(|s| self.is_updated(s, |u| field.is_updated(u))) {
+                        continue;
+                    }
+                }
+
+                let proposal = match self.resolve(&finding) {
+                    Some(p) => p,
+                    None => f(check, &finding),
+                };
+                if let Some(p) = proposal {
+                    self.apply(&finding, p);
+                }
+            }
+        }
+    }
+
+    /// Renames the release artists and track artists that are listed in the resolutions.
+    fn rename_artists(&mut self) {
+        let resolutions = &self.resolutions;
+        let mut decisions = Vec::new();
+        for s in self.artists.iter().flat_map(|a| a.releases.iter()).flat_map(|r| r.songs.iter()) {
+            let update = update(|tu| {
+                if let Some(names) = resolutions.artist_mapping(&s.release_artists) {
+                    tu.release_artists = Value::Update(names);
+                }
+                if let Some(names) = resolutions.artist_mapping(&s.artists) {
+                    tu.artists = Value::Update(names);
+                }
+            });
+            decisions.extend(Proposal::new(String::new(), &[s], update).updates);
+        }
+
+        for (s, u) in decisions {
+            self.update_tag(s, |tu| tu.merge(u));
+        }
+    }
+
+    /// The decision of the resolutions on a finding, `None` if they don't decide on it.
+    fn resolve(&self, finding: &Finding<'a>) -> Option<Option<Proposal<'a>>> {
+        let resolutions = &self.resolutions;
+        let songs = &finding.songs;
+        match (&finding.subject, finding.field) {
+            (Subject::ReleaseArtists(a, b), _) if resolutions.are_distinct_artists(a, b) => {
+                Some(None)
+            }
+            (Subject::Releases(artists, names), _) => {
+                let merge = resolutions.release_resolution(artists, names)?.merge.clone();
+                Some(merge.map(|m| {
+                    let description = format!("merge into {}", m);
+                    Proposal::new(description, songs, update(|tu| tu.release = Value::Update(m)))
+                }))
+            }
+            (_, Some(Field::TotalTracks)) if resolutions.infer_totals => {
+                let total = highest(songs, |s| [s.track_number, s.total_tracks]);
+                let update = update(|tu| tu.total_tracks = total);
+                Some(Some(Proposal::new(String::new(), songs, update)))
+            }
+            (_, Some(Field::TotalDiscs)) if resolutions.infer_totals => {
+                let total = highest(songs, |s| [s.disc_number, s.total_discs]);
+                let update = update(|tu| tu.total_discs = total);
+                Some(Some(Proposal::new(String::new(), songs, update)))
+            }
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, finding: &Finding<'a>, proposal: Proposal<'a>) {
+        if let (Some(field), Some((_, new))) = (finding.field, proposal.updates.first()) {
+            // Songs that were changed to a value of the finding follow the new one
+            for o in self.updates.iter_mut().filter(|o| !finding.songs.contains(&o.song)) {
+                if let Some(u) = o.tag_update.as_mut().filter(|u| field.is_updated(u)) {
+                    let mut updated = o.song.clone();
+                    u.apply_to(&mut updated);
+                    if finding.songs.iter().any(|s| field.same(s, &updated)) {
+                        field.copy(new, u);
+                    }
+                }
+            }
+        }
+
+        for (s, u) in proposal.updates {
+            self.update_tag(s, |tu| tu.merge(u));
+        }
+    }
+}
+
+/// An update that only changes what `f` sets.
+fn update(f: impl FnOnce(&mut TagUpdate)) -> TagUpdate {
+    let mut update = TagUpdate::default();
+    f(&mut update);
+    update
+}
+
+fn release_songs<'a>(releases: &[&Release<'a>]) -> Vec<&'a Song> {
+    releases.iter().flat_map(|r| r.songs.iter().copied()).collect()
+}
+
+/// The releases with their number of songs, like `Album (12 songs)`.
+fn release_list(releases: &[Release]) -> String {
+    let mut list: Vec<String> =
+        releases.iter().take(5).map(|r| format!("{} ({} songs)", r.name, r.songs.len())).collect();
+    if releases.len() > 5 {
+        list.push("...".to_owned());
+    }
+    list.join(", ")
+}
+
+/// A song with its position on the release and a value, like `1|02 - Title 2001`.
+fn song_line(song: &Song, value: impl std::fmt::Display) -> String {
+    let position = match song.disc_number {
+        Some(d) => format!("{}|{:02}", d, song.track_number.unwrap_or(0)),
+        None => format!("{:02}", song.track_number.unwrap_or(0)),
+    };
+    format!("{} - {} {}", position, song.title, value)
+}
+
+fn or_none<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "none".to_owned(), |v| v.to_string())
+}
+
+fn totals_proposals<'a>(
+    songs: &[&'a Song],
+    totals: &[Totals<'a>],
+    set: fn(&mut TagUpdate, u16),
+) -> Vec<Proposal<'a>> {
+    let values = totals.iter().filter_map(|(_, t)| *t);
+    values
+        .map(|t| Proposal::new(format!("set all to {}", t), songs, update(|tu| set(tu, t))))
+        .collect()
+}
+
+struct ReleaseArtistsCheck;
+
+impl Check for ReleaseArtistsCheck {
+    fn name(&self) -> &str {
+        "release-artists"
+    }
+
+    fn description(&self) -> &str {
+        "Release artists that are named similarly"
+    }
+
+    fn check<'a>(&self, artists: &[ReleaseArtists<'a>]) -> Vec<Finding<'a>> {
+        let mut findings = Vec::new();
+        for (i, a) in artists.iter().enumerate() {
+            for b in artists.iter().skip(i + 1).filter(|b| similar_artists(a, b)) {
+                let songs: Vec<&'a Song> = [a, b]
+                    .iter()
+                    .flat_map(|a| a.releases.iter())
+                    .flat_map(|r| r.songs.clone())
+                    .collect();
+                let proposals = [b.names, a.names]
+                    .iter()
+                    .map(|names| {
+                        let description = format!("rename all to {}", names.join(", "));
+                        let update =
+                            update(|tu| tu.release_artists = Value::Update(names.to_vec()));
+                        Proposal::new(description, &songs, update)
+                    })
+                    .collect();
+                findings.push(Finding {
+                    severity: Severity::Warning,
+                    message: format!(
+                        "{} and {} are named similarly",
+                        a.names.join(", "),
+                        b.names.join(", ")
+                    ),
+                    details: [a, b]
+                        .iter()
+                        .map(|a| format!("{}: {}", a.names.join(", "), release_list(&a.releases)))
+                        .collect(),
+                    songs,
+                    proposals,
+                    field: Some(Field::ReleaseArtists),
+                    subject: Subject::ReleaseArtists(a.names, b.names),
+                });
+            }
+        }
+        findings
+    }
+}
+
+struct ReleasesCheck;
+
+impl Check for ReleasesCheck {
+    fn name(&self) -> &str {
+        "releases"
+    }
+
+    fn description(&self) -> &str {
+        "Releases of an artist that only differ in case, brackets or an edition suffix"
+    }
+
+    fn check<'a>(&self, artists: &[ReleaseArtists<'a>]) -> Vec<Finding<'a>> {
+        let mut findings = Vec::new();
+        for a in artists.iter() {
+            for releases in similar_releases(a) {
+                let songs = release_songs(&releases);
+                let names = release_names(&releases);
+                let proposals = names
+                    .iter()
+                    .map(|n| {
+                        let description = format!("merge into {}", n);
+                        let update = update(|tu| tu.release = Value::Update(n.to_string()));
+                        Proposal::new(description, &songs, update)
+                    })
+                    .collect();
+                findings.push(Finding {
+                    severity: Severity::Warning,
+                    message: format!(
+                        "{} has releases that are named similarly",
+                        a.names.join(", ")
+                    ),
+                    details: releases
+                        .iter()
+                        .map(|r| format!("{} ({} songs)", r.name, r.songs.len()))
+                        .collect(),
+                    songs,
+                    proposals,
+                    field: Some(Field::Release),
+                    subject: Subject::Releases(a.names, names),
+                });
+            }
+        }
+        findings
+    }
+}
+
+struct DatesCheck(DateKind);
+
+impl Check for DatesCheck {
+    fn name(&self) -> &str {
+        match self.0 {
+            DateKind::Release => "release-dates",
+            DateKind::Original => "original-dates",
+        }
+    }
+
+    fn description(&self) -> &str {
+        match self.0 {
+            DateKind::Release => "Releases whose songs have different release years",
+            DateKind::Original => "Releases whose songs have different original years",
+        }
+    }
+
+    fn check<'a>(&self, artists: &[ReleaseArtists<'a>]) -> Vec<Finding<'a>> {
+        let kind = self.0;
+        let mut findings = Vec::new();
+        for a in artists.iter() {
+            for r in a.releases.iter() {
+                let dates = match inconsistent_dates(r, kind) {
+                    Some(d) => d,
+                    None => continue,
+                };
+
+                let proposals = dates
+                    .iter()
+                    .map(|d| {
+                        let description = format!("set all to {}", d);
+                        Proposal::new(
+                            description,
+                            &r.songs,
+                            update(|tu| *tu.date_mut(kind) = Value::Update(*d)),
+                        )
+                    })
+                    .collect();
+                findings.push(Finding {
+                    severity: Severity::Warning,
+                    message: format!(
+                        "{} - {} has inconsistent {}",
+                        a.names.join(", "),
+                        r.name,
+                        self.name()
+                    ),
+                    details: r.songs.iter().map(|s| song_line(s, or_none(s.date(kind)))).collect(),
+                    songs: r.songs.clone(),
+                    proposals,
+                    field: Some(Field::Date(kind)),
+                    subject: Subject::Other,
+                });
+            }
+        }
+        findings
+    }
+}
+
+struct CompilationsCheck;
+
+impl Check for CompilationsCheck {
+    fn name(&self) -> &str {
+        "compilations"
+    }
+
+    fn description(&self) -> &str {
+        "Releases that seem to be compilations, but aren't flagged as one"
+    }
+
+    fn check<'a>(&self, artists: &[ReleaseArtists<'a>]) -> Vec<Finding<'a>> {
+        compilation_candidates(artists)
+            .into_iter()
+            .map(|(release, artists)| {
+                let artists: Vec<String> = artists.iter().map(|a| a.join(", ")).collect();
+                let update = update(|tu| tu.compilation = Value::Update(true));
+                Finding {
+                    severity: Severity::Info,
+                    message: format!(
+                        "{} seems to be a compilation by {}",
+                        release.name,
+                        artists.join("; ")
+                    ),
+                    details: Vec::new(),
+                    proposals: vec![Proposal::new(
+                        "flag it as a compilation".to_owned(),
+                        &release.songs,
+                        update,
+                    )],
+                    songs: release.songs,
+                    field: None,
+                    subject: Subject::Other,
+                }
+            })
+            .collect()
+    }
+}
+
+struct MixedPropertiesCheck;
+
+impl Check for MixedPropertiesCheck {
+    fn name(&self) -> &str {
+        "mixed-properties"
+    }
+
+    fn description(&self) -> &str {
+        "Releases whose songs differ in their audio properties"
+    }
+
+    fn check<'a>(&self, artists: &[ReleaseArtists<'a>]) -> Vec<Finding<'a>> {
+        let mut findings = Vec::new();
+        for a in artists.iter() {
+            for r in a.releases.iter() {
+                let differences: Vec<String> =
+                    property_differences(r).iter().map(|p| p.to_string()).collect();
+                if differences.is_empty() {
+                    continue;
+                }
+
+                findings.push(Finding {
+                    severity: Severity::Info,
+                    message: format!(
+                        "{} - {} has mixed {}",
+                        a.names.join(", "),
+                        r.name,
+                        differences.join(", ")
+                    ),
+                    details: r.songs.iter().map(|s| song_line(s, &s.properties)).collect(),
+                    songs: r.songs.clone(),
+                    proposals: Vec::new(),
+                    field: None,
+                    subject: Subject::Other,
+                });
+            }
+        }
+        findings
+    }
+}
+
+struct TotalTracksCheck;
+
+impl Check for TotalTracksCheck {
+    fn name(&self) -> &str {
+        "total-tracks"
+    }
+
+    fn description(&self) -> &str {
+        "Discs whose songs have different total tracks"
+    }
+
+    fn check<'a>(&self, artists: &[ReleaseArtists<'a>]) -> Vec<Finding<'a>> {
+        let mut findings = Vec::new();
+        for a in artists.iter() {
+            for r in a.releases.iter() {
+                for (disc, songs) in discs(r) {
+                    let totals = group_totals(&songs, |s| s.total_tracks);
+                    if totals.len() <= 1 {
+                        continue;
+                    }
+
+                    let disc = disc.map(|d| format!(" disc {}", d)).unwrap_or_default();
+                    findings.push(Finding {
+                        severity: Severity::Warning,
+                        message: format!(
+                            "{} - {}{} has inconsistent total tracks",
+                            a.names.join(", "),
+                            r.name,
+                            disc
+                        ),
+                        details: songs
+                            .iter()
+                            .map(|s| song_line(s, or_none(s.total_tracks)))
+                            .collect(),
+                        proposals: totals_proposals(&songs, &totals, |tu, t| {
+                            tu.total_tracks = Value::Update(t)
+                        }),
+                        songs,
+                        field: Some(Field::TotalTracks),
+                        subject: Subject::Other,
+                    });
+                }
+            }
+        }
+        findings
+    }
+}
+
+struct TotalDiscsCheck;
+
+impl Check for TotalDiscsCheck {
+    fn name(&self) -> &str {
+        "total-discs"
+    }
+
+    fn description(&self) -> &str {
+        "Releases whose songs have different total discs"
+    }
+
+    fn check<'a>(&self, artists: &[ReleaseArtists<'a>]) -> Vec<Finding<'a>> {
+        let mut findings = Vec::new();
+        for a in artists.iter() {
+            for r in a.releases.iter() {
+                let totals = group_totals(&r.songs, |s| s.total_discs);
+                if totals.len() <= 1 {
+                    continue;
+                }
+
+                findings.push(Finding {
+                    severity: Severity::Warning,
+                    message: format!(
+                        "{} - {} has inconsistent total discs",
+                        a.names.join(", "),
+                        r.name
+                    ),
+                    details: r.songs.iter().map(|s| song_line(s, or_none(s.total_discs))).collect(),
+                    proposals: totals_proposals(&r.songs, &totals, |tu, t| {
+                        tu.total_discs = Value::Update(t)
+                    }),
+                    songs: r.songs.clone(),
+                    field: Some(Field::TotalDiscs),
+                    subject: Subject::Other,
+                });
+            }
+        }
+        findings
+    }
+}
+
+struct TrackNumbersCheck;
+
+impl Check for TrackNumbersCheck {
+    fn name(&self) -> &str {
+        "track-numbers"
+    }
+
+    fn description(&self) -> &str {
+        "Discs with gaps or duplicates in their track numbers"
+    }
+
+    fn check<'a>(&self, artists: &[ReleaseArtists<'a>]) -> Vec<Finding<'a>> {
+        let mut findings = Vec::new();
+        for a in artists.iter() {
+            for r in a.releases.iter() {
+                for issue in numbering_issues(r) {
+                    let (severity, kind, disc, tracks) = match &issue {
+                        NumberingIssue::Gaps { disc, tracks } => {
+                            (Severity::Warning, "is missing", disc, tracks)
+                        }
+                        NumberingIssue::Duplicates { disc, tracks } => {
+                            (Severity::Error, "has duplicate", disc, tracks)
+                        }
+                    };
+                    let disc = disc.map(|d| format!(" disc {}", d)).unwrap_or_default();
+                    let tracks: Vec<String> = tracks.iter().map(|t| t.to_string()).collect();
+                    findings.push(Finding {
+                        severity,
+                        message: format!(
+                            "{} - {}{} {} tracks {}",
+                            a.names.join(", "),
+                            r.name,
+                            disc,
+                            kind,
+                            tracks.join(", ")
+                        ),
+                        details: Vec::new(),
+                        songs: r.songs.clone(),
+                        proposals: Vec::new(),
+                        field: None,
+                        subject: Subject::Other,
+                    });
+                }
+            }
+        }
+        findings
+    }
+}
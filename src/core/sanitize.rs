@@ -0,0 +1,452 @@
+use std::path::{Path, PathBuf};
+
+use unicode_normalization::UnicodeNormalization;
+
+/// The filesystem of the output directory, which decides how names are sanitized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filesystem {
+    Ext4,
+    /// FAT32 and exFAT, which are common on portable players and SD cards.
+    Fat,
+    #[default]
+    Ntfs,
+    /// Shares that are served by Samba to Windows clients.
+    Samba,
+}
+
+const FILESYSTEMS: [(&str, Filesystem); 4] = [
+    ("ext4", Filesystem::Ext4),
+    ("fat", Filesystem::Fat),
+    ("ntfs", Filesystem::Ntfs),
+    ("samba", Filesystem::Samba),
+];
+
+/// A Unicode normalization form, names that look the same but are composed differently would
+/// otherwise end up in different directories.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Composed characters, which most systems use.
+    #[default]
+    Nfc,
+    /// Decomposed characters, which macOS uses.
+    Nfd,
+    /// Composed characters where compatibility characters like `ﬁ` or `²` are replaced.
+    Nfkc,
+    Nfkd,
+}
+
+const NORMALIZATIONS: [(&str, Normalization); 4] = [
+    ("nfc", Normalization::Nfc),
+    ("nfd", Normalization::Nfd),
+    ("nfkc", Normalization::Nfkc),
+    ("nfkd", Normalization::Nfkd),
+];
+
+/// Characters that Windows doesn't allow in names, Samba would mangle them.
+const WINDOWS_FORBIDDEN: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names that can't be used as a name, even with an extension like `CON.mp3`. Windows
+/// also treats the superscript digits as numbers.
+const RESERVED_NAMES: [&str; 30] = [
+    "CON", "PRN", "AUX", "NUL", "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7",
+    "COM8", "COM9", "COM¹", "COM²", "COM³", "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6",
+    "LPT7", "LPT8", "LPT9", "LPT¹", "LPT²", "LPT³",
+];
+
+/// The unit name lengths are measured in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Unit {
+    Bytes,
+    /// UTF-16 code units, which Windows filesystems store names in.
+    Utf16,
+}
+
+struct Rules {
+    forbidden: &'static [char],
+    reserved_names: bool,
+    /// Windows strips trailing dots and spaces, so names would silently change.
+    trailing_dots: bool,
+    /// The maximum length of a single name.
+    component: (Unit, usize),
+    /// The maximum length of a whole path, including the output directory.
+    path: (Unit, usize),
+}
+
+impl Filesystem {
+    pub fn from_name(name: &str) -> Option<Self> {
+        FILESYSTEMS.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, f)| *f)
+    }
+
+    pub fn name(self) -> &'static str {
+        FILESYSTEMS.iter().find(|(_, f)| *f == self).map(|(n, _)| *n).unwrap()
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        FILESYSTEMS.iter().map(|(n, _)| *n)
+    }
+
+    /// Whether names that only differ in case refer to the same file.
+    pub fn case_insensitive(self) -> bool {
+        self != Self::Ext4
+    }
+
+    fn rules(self) -> Rules {
+        match self {
+            Self::Ext4 => Rules {
+                forbidden: &['/'],
+                reserved_names: false,
+                trailing_dots: false,
+                component: (Unit::Bytes, 255),
+                path: (Unit::Bytes, 4095),
+            },
+            Self::Fat | Self::Ntfs => Rules {
+                forbidden: WINDOWS_FORBIDDEN,
+                reserved_names: true,
+                trailing_dots: true,
+                component: (Unit::Utf16, 255),
+                path: (Unit::Utf16, 259),
+            },
+            Self::Samba => Rules {
+                forbidden: WINDOWS_FORBIDDEN,
+                reserved_names: true,
+                trailing_dots: true,
+                component: (Unit::Bytes, 255),
+                path: (Unit::Utf16, 259),
+            },
+        }
+    }
+
+    /// Sanitizes the name of a directory. Forbidden and control characters are removed, a
+    /// leading dot is replaced so it isn't hidden and too long names are cut off.
+    pub fn dir_name(self, name: &str) -> String {
+        let rules = self.rules();
+        let name = self.clean(name, true);
+        truncate(&name, rules.component.0, rules.component.1, &rules)
+    }
+
+    /// Sanitizes a file name like [`dir_name`](Self::dir_name). Too long names are shortened
+    /// in the middle, so the extension and a leading track number like `1 01 - ` are kept.
+    pub fn file_name(self, stem: &str, extension: Option<&str>) -> String {
+        let rules = self.rules();
+        let extension = extension.map(|e| format!(".{}", self.clean(e, true))).unwrap_or_default();
+        let stem = match self.clean(stem, extension.is_empty()) {
+            s if s.is_empty() => "_".to_owned(),
+            s => s,
+        };
+
+        let (unit, max) = rules.component;
+        let stem =
+            truncate_file_stem(&stem, unit, max.saturating_sub(len(&extension, unit)), &rules);
+        stem + &extension
+    }
+
+    /// Sanitizes the directories and file name of a path relative to the output directory.
+    /// Empty directories are skipped and the longest names are shortened until the whole path,
+    /// including the output directory, fits.
+    pub fn path(
+        self,
+        output_dir: &Path,
+        dirs: &[&str],
+        stem: &str,
+        extension: Option<&str>,
+    ) -> Vec<String> {
+        let rules = self.rules();
+        let file_name = self.file_name(stem, extension);
+        let extension = extension.map(|e| format!(".{}", self.clean(e, true))).unwrap_or_default();
+
+        let mut components: Vec<String> =
+            dirs.iter().map(|d| self.dir_name(d)).filter(|d| !d.is_empty()).collect();
+        components.push(file_name[..file_name.len() - extension.len()].to_owned());
+
+        let (unit, max) = rules.path;
+        let max = max.saturating_sub(len(&output_dir.to_string_lossy(), unit) + 1);
+        loop {
+            let separators = components.len() - 1;
+            let total = components.iter().map(|c| len(c, unit)).sum::<usize>()
+                + len(&extension, unit)
+                + separators;
+            let lens: Vec<usize> = components.iter().map(|c| len(c, unit)).collect();
+            let (i, l) = lens.iter().copied().enumerate().max_by_key(|(_, l)| *l).unwrap();
+            if total <= max || l <= 1 {
+                break;
+            }
+
+            // The longest name is only shortened to the length of the next one, so the cuts are
+            // spread over all long names
+            let next = lens.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, l)| *l).max();
+            let new_len = l.saturating_sub(total - max).max(next.unwrap_or(0)).clamp(1, l - 1);
+            components[i] = match i == separators {
+                true => truncate_file_stem(&components[i], unit, new_len, &rules),
+                false => truncate(&components[i], unit, new_len, &rules),
+            };
+        }
+
+        if let Some(stem) = components.last_mut() {
+            stem.push_str(&extension);
+        }
+        components
+    }
+
+    /// Appends a suffix like ` (2)` to the file stem of a sanitized path. The stem is shortened
+    /// if the file name or the whole path gets too long, so the suffix and the extension are
+    /// kept and the directories stay the same.
+    pub fn suffixed_path(self, path: &Path, suffix: &str) -> PathBuf {
+        let rules = self.rules();
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let tail = match path.extension() {
+            Some(e) => format!("{}.{}", suffix, e.to_string_lossy()),
+            None => suffix.to_owned(),
+        };
+
+        let (unit, max) = rules.component;
+        let (path_unit, path_max) = rules.path;
+        let max = max.min(path_max.saturating_sub(len(&parent.to_string_lossy(), path_unit) + 1));
+        let stem = truncate_file_stem(&stem, unit, max.saturating_sub(len(&tail, unit)), &rules);
+        parent.join(stem + &tail)
+    }
+
+    /// Trailing dots only matter at the end of a name, not before an extension.
+    fn clean(self, name: &str, end: bool) -> String {
+        let rules = self.rules();
+        let mut s: String = name
+            .chars()
+            .filter(|c| !c.is_control() && !rules.forbidden.contains(c))
+            .collect::<String>()
+            .trim()
+            .to_owned();
+
+        if s.starts_with('.') {
+            s.replace_range(..1, "_");
+        }
+        if rules.trailing_dots && end {
+            fix_trailing_dots(&mut s);
+        }
+        if rules.reserved_names && is_reserved(&s) {
+            let base_len = s.find('.').unwrap_or(s.len());
+            s.insert(base_len, '_');
+        }
+
+        s
+    }
+}
+
+/// Windows ignores spaces after the device name, so `CON .mp3` is reserved as well.
+fn is_reserved(name: &str) -> bool {
+    let base = name.split('.').next().unwrap_or("").trim_end();
+    RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(base))
+}
+
+fn fix_trailing_dots(s: &mut String) {
+    let trimmed = s.trim_end_matches(' ').len();
+    s.truncate(trimmed);
+    if s.ends_with('.') {
+        s.pop();
+        s.push('_');
+    }
+}
+
+fn len(s: &str, unit: Unit) -> usize {
+    match unit {
+        Unit::Bytes => s.len(),
+        Unit::Utf16 => s.encode_utf16().count(),
+    }
+}
+
+/// Cuts off the end of a name at a character boundary, so its length is at most `max`.
+fn truncate(name: &str, unit: Unit, max: usize, rules: &Rules) -> String {
+    if len(name, unit) <= max {
+        return name.to_owned();
+    }
+
+    let mut s = String::new();
+    let mut l = 0;
+    for c in name.chars() {
+        let c_len = match unit {
+            Unit::Bytes => c.len_utf8(),
+            Unit::Utf16 => c.len_utf16(),
+        };
+        if l + c_len > max {
+            break;
+        }
+        s.push(c);
+        l += c_len;
+    }
+
+    let mut s = s.trim_end().to_owned();
+    if rules.trailing_dots {
+        fix_trailing_dots(&mut s);
+    }
+    if s.is_empty() {
+        s.push('_');
+    }
+    s
+}
+
+/// Keeps a leading track number like `01 - ` or `1 01 - ` and cuts off the rest of the name.
+fn truncate_file_stem(stem: &str, unit: Unit, max: usize, rules: &Rules) -> String {
+    if len(stem, unit) <= max {
+        return stem.to_owned();
+    }
+
+    let prefix_len = stem
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, ' ' | '.' | '-' | '_')))
+        .unwrap_or(stem.len());
+    let (prefix, rest) = stem.split_at(prefix_len);
+    if prefix.is_empty() || len(prefix, unit) >= max {
+        return truncate(stem, unit, max, rules);
+    }
+
+    let rest = truncate(rest, unit, max - len(prefix, unit), rules);
+    let mut s = format!("{}{}", prefix, rest).trim_end_matches([' ', '-', ',']).to_owned();
+    if rules.trailing_dots {
+        fix_trailing_dots(&mut s);
+    }
+    s
+}
+
+impl Normalization {
+    pub fn from_name(name: &str) -> Option<Self> {
+        NORMALIZATIONS.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, f)| *f)
+    }
+
+    pub fn name(self) -> &'static str {
+        NORMALIZATIONS.iter().find(|(_, f)| *f == self).map(|(n, _)| *n).unwrap()
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        NORMALIZATIONS.iter().map(|(n, _)| *n)
+    }
+
+    pub fn apply(self, s: &str) -> String {
+        match self {
+            Self::Nfc => s.nfc().collect(),
+            Self::Nfd => s.nfd().collect(),
+            Self::Nfkc => s.nfkc().collect(),
+            Self::Nfkd => s.nfkd().collect(),
+        }
+    }
+}
+
+/// Romanizes a name to ASCII, like `Björk` to `Bjork` or `Кино` to `Kino`. Characters without
+/// a transliteration are replaced by an underscore.
+pub(crate) fn transliterate(s: &str) -> String {
+    deunicode::deunicode_with_tofu(s, "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ntfs_rules() -> Rules {
+        Filesystem::Ntfs.rules()
+    }
+
+    #[test]
+    fn reserved_names() {
+        let fs = Filesystem::Ntfs;
+        assert_eq!(fs.dir_name("CON"), "CON_");
+        assert_eq!(fs.dir_name("con.mp3"), "con_.mp3");
+        assert_eq!(fs.file_name("con", Some("mp3")), "con_.mp3");
+        assert_eq!(fs.file_name("CON .live", Some("mp3")), "CON _.live.mp3");
+        assert_eq!(fs.dir_name("COM0"), "COM0_");
+        assert_eq!(fs.dir_name("lpt²"), "lpt²_");
+        assert_eq!(fs.dir_name("CONCERT"), "CONCERT");
+        assert_eq!(fs.dir_name("COM10"), "COM10");
+        assert_eq!(Filesystem::Ext4.dir_name("CON"), "CON");
+    }
+
+    #[test]
+    fn trailing_dots_and_spaces() {
+        let fs = Filesystem::Ntfs;
+        assert_eq!(fs.dir_name("Vol. 2 ..."), "Vol. 2 .._");
+        assert_eq!(fs.dir_name("  Album  "), "Album");
+        assert_eq!(fs.dir_name("St."), "St_");
+        // Dots before the extension are fine
+        assert_eq!(fs.file_name("Song...", Some("mp3")), "Song....mp3");
+        assert_eq!(fs.dir_name(".hidden"), "_hidden");
+        assert_eq!(Filesystem::Ext4.dir_name("St."), "St.");
+    }
+
+    #[test]
+    fn forbidden_and_control_characters() {
+        assert_eq!(Filesystem::Ntfs.dir_name("AC/DC: Live?\u{0}\n\t"), "ACDC Live");
+        assert_eq!(Filesystem::Ext4.dir_name("AC/DC: Live?\u{7}"), "ACDC: Live?");
+        assert_eq!(Filesystem::Ntfs.file_name("???", None), "_");
+    }
+
+    #[test]
+    fn utf16_and_byte_limits() {
+        // Every character takes 3 bytes, but only one UTF-16 code unit
+        let name = "あ".repeat(200);
+        assert_eq!(Filesystem::Ntfs.dir_name(&name), name);
+        assert_eq!(Filesystem::Samba.dir_name(&name), "あ".repeat(85));
+        assert_eq!(Filesystem::Ext4.dir_name(&name), "あ".repeat(85));
+
+        // Characters outside the BMP take two UTF-16 code units
+        let name = "🎵".repeat(200);
+        assert_eq!(Filesystem::Ntfs.dir_name(&name), "🎵".repeat(127));
+        assert_eq!(Filesystem::Ext4.dir_name(&name), "🎵".repeat(63));
+    }
+
+    #[test]
+    fn file_stem_truncation() {
+        let rules = ntfs_rules();
+        let stem = format!("1 01 - {}", "a".repeat(300));
+        let truncated = truncate_file_stem(&stem, Unit::Utf16, 20, &rules);
+        assert_eq!(truncated, format!("1 01 - {}", "a".repeat(13)));
+
+        // Separators at the cut are removed
+        let truncated = truncate_file_stem("01 - Artist - Title", Unit::Utf16, 14, &rules);
+        assert_eq!(truncated, "01 - Artist");
+
+        let name = Filesystem::Ntfs.file_name(&stem, Some("flac"));
+        assert_eq!(name.encode_utf16().count(), 255);
+        assert!(name.starts_with("1 01 - aaa"));
+        assert!(name.ends_with("a.flac"));
+    }
+
+    #[test]
+    fn suffix_budget() {
+        let fs = Filesystem::Ntfs;
+        let path = Path::new("/music/Artist/Album/01 - Title.mp3");
+        assert_eq!(
+            fs.suffixed_path(path, " (2)"),
+            Path::new("/music/Artist/Album/01 - Title (2).mp3")
+        );
+
+        // The suffix and the extension are kept when the name gets too long
+        let path = format!("/music/Artist/Album/01 - {}.mp3", "a".repeat(246));
+        let suffixed = Filesystem::Ext4.suffixed_path(Path::new(&path), " (2)");
+        let name = suffixed.file_name().unwrap().to_string_lossy();
+        assert_eq!(name.len(), 255);
+        assert!(name.starts_with("01 - aaa"));
+        assert!(name.ends_with("a (2).mp3"));
+
+        // Only the file name is shortened when the whole path gets too long
+        let path = format!("/music/{}/{}/01 - Title.mp3", "a".repeat(200), "b".repeat(34));
+        let suffixed = fs.suffixed_path(Path::new(&path), " (2)");
+        assert_eq!(suffixed.to_string_lossy().encode_utf16().count(), 259);
+        assert_eq!(suffixed.parent(), Path::new(&path).parent());
+        assert!(suffixed.to_string_lossy().ends_with("01 - Tit (2).mp3"));
+    }
+
+    #[test]
+    fn path_budget() {
+        let fs = Filesystem::Ntfs;
+        let dirs = ["a".repeat(200), "b".repeat(200)];
+        let dirs: Vec<&str> = dirs.iter().map(|d| d.as_str()).collect();
+        let stem = format!("01 - {}", "c".repeat(200));
+        let output_dir = Path::new("/music/library");
+
+        let components = fs.path(output_dir, &dirs, &stem, Some("mp3"));
+        let path = format!("{}/{}", output_dir.display(), components.join("/"));
+        assert_eq!(path.encode_utf16().count(), 259);
+        assert!(components[2].starts_with("01 - c"));
+        assert!(components[2].ends_with(".mp3"));
+
+        // Short paths aren't changed
+        let components = fs.path(output_dir, &["Artist", "Album"], "01 - Title", Some("mp3"));
+        assert_eq!(components, ["Artist", "Album", "01 - Title.mp3"]);
+    }
+}
@@ -0,0 +1,75 @@
+/// Leading articles per language, which are moved to the end of a name to get a sort name like
+/// `Beatles, The`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Articles {
+    pub languages: Vec<(String, Vec<String>)>,
+}
+
+const BUILTIN: [(&str, &[&str]); 7] = [
+    ("en", &["The", "A", "An"]),
+    ("de", &["Der", "Die", "Das", "Ein", "Eine"]),
+    ("fr", &["Le", "La", "Les", "L'", "Un", "Une"]),
+    ("es", &["El", "La", "Los", "Las", "Un", "Una"]),
+    ("it", &["Il", "Lo", "La", "I", "Gli", "Le", "L'"]),
+    ("nl", &["De", "Het", "Een"]),
+    ("pt", &["O", "A", "Os", "As"]),
+];
+
+impl Default for Articles {
+    fn default() -> Self {
+        Self::builtin(&["en"])
+    }
+}
+
+impl Articles {
+    /// The built-in articles of the languages, unknown languages are ignored.
+    pub fn builtin(languages: &[&str]) -> Self {
+        let languages = BUILTIN
+            .iter()
+            .filter(|(l, _)| languages.contains(l))
+            .map(|(l, a)| (l.to_string(), a.iter().map(|a| a.to_string()).collect()))
+            .collect();
+
+        Self { languages }
+    }
+
+    pub fn builtin_languages() -> impl Iterator<Item = &'static str> {
+        BUILTIN.iter().map(|(l, _)| *l)
+    }
+
+    pub fn add(&mut self, language: &str, article: &str) {
+        match self.languages.iter_mut().find(|(l, _)| l == language) {
+            Some((_, a)) => a.push(article.to_owned()),
+            None => self.languages.push((language.to_owned(), vec![article.to_owned()])),
+        }
+    }
+
+    /// Moves a leading article to the end, `The Beatles` becomes `Beatles, The`. Articles are
+    /// matched case-insensitively and have to be followed by a space, unless they end with an
+    /// apostrophe like `L'`.
+    pub fn move_leading(&self, name: &str) -> Option<String> {
+        let longest = self
+            .languages
+            .iter()
+            .flat_map(|(_, a)| a.iter())
+            .filter_map(|a| {
+                let prefix = name.get(..a.len())?;
+                if !prefix.eq_ignore_ascii_case(a) {
+                    return None;
+                }
+
+                let rest = match a.ends_with('\'') {
+                    true => &name[a.len()..],
+                    false => name[a.len()..].strip_prefix(' ')?,
+                };
+                let rest = rest.trim_start();
+                match rest.is_empty() {
+                    true => None,
+                    false => Some((prefix, rest)),
+                }
+            })
+            .max_by_key(|(prefix, _)| prefix.len());
+
+        longest.map(|(article, rest)| format!("{}, {}", rest, article))
+    }
+}
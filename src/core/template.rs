@@ -0,0 +1,704 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Song;
+
+/// The widest numbers can be padded to.
+const MAX_WIDTH: usize = 16;
+
+/// A template for the path of a song relative to the output directory, like
+/// `{artistdir}/{year? "{year} - "}{album}/{disc>1? "{disc}-"}{track:02} {title}`. The extension
+/// is appended to the file name.
+///
+/// - `{field}` inserts the value of a field, numbers can be zero-padded to up to 16 digits with
+///   `{track:02}`.
+/// - `{field? "then"}` and `{field? "then" : "else"}` insert the quoted template if the field
+///   is present. `{!field? "..."}` negates the condition.
+/// - `{field>1? "..."}` compares a field with a value using `=`, `!=`, `<`, `<=`, `>` or `>=`,
+///   text fields are compared with quoted strings like `{format="MP3"? "..."}`.
+/// - `/` separates directories, `{{` and `}}` are literal braces. Inside quotes `\"` and `\\`
+///   are escaped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+/// Values that are derived from the whole library instead of a single song.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Context {
+    /// The name of the artist directory, which respects sort names and compilations.
+    pub artist_dir: String,
+    /// The earliest year of the release, so all of its songs end up in the same directory.
+    pub year: Option<i32>,
+    /// The earliest original year of the release, which falls back to the release year.
+    pub original_year: Option<i32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemplateError {
+    /// The position of the error in characters, starting at 1.
+    pub column: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Separator,
+    Field { field: Field, width: Option<usize> },
+    Conditional { negate: bool, condition: Condition, then: Vec<Part>, otherwise: Vec<Part> },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Condition {
+    Present(Field),
+    Compare(Field, Op, Literal),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Literal {
+    Number(i64),
+    Text(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Text,
+    Number,
+    Flag,
+}
+
+enum FieldValue {
+    Text(String),
+    Number(i64),
+    Flag(bool),
+}
+
+macro_rules! fields {
+    ($($name:literal => $field:ident: $kind:ident,)*) => {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        enum Field {
+            $($field,)*
+        }
+
+        const FIELDS: &[(&str, Field, Kind)] = &[$(($name, Field::$field, Kind::$kind),)*];
+    };
+}
+
+fields! {
+    "artistdir" => ArtistDir: Text,
+    "albumartist" => ReleaseArtists: Text,
+    "artist" => Artists: Text,
+    "album" => Release: Text,
+    "title" => Title: Text,
+    "albumartistsort" => ReleaseArtistsSort: Text,
+    "artistsort" => ArtistsSort: Text,
+    "albumsort" => ReleaseSort: Text,
+    "titlesort" => TitleSort: Text,
+    "track" => TrackNumber: Number,
+    "tracks" => TotalTracks: Number,
+    "disc" => DiscNumber: Number,
+    "discs" => TotalDiscs: Number,
+    "discsubtitle" => DiscSubtitle: Text,
+    "year" => Year: Number,
+    "originalyear" => OriginalYear: Number,
+    "date" => ReleaseDate: Text,
+    "originaldate" => OriginalDate: Text,
+    "trackgain" => TrackGain: Text,
+    "albumgain" => ReleaseGain: Text,
+    "mbrecordingid" => MbRecordingId: Text,
+    "mbreleaseid" => MbReleaseId: Text,
+    "mbreleasegroupid" => MbReleaseGroupId: Text,
+    "mbartistid" => MbArtistIds: Text,
+    "mbalbumartistid" => MbReleaseArtistIds: Text,
+    "genre" => Genre: Text,
+    "genres" => Genres: Text,
+    "composer" => Composer: Text,
+    "conductor" => Conductor: Text,
+    "comment" => Comment: Text,
+    "grouping" => Grouping: Text,
+    "compilation" => Compilation: Flag,
+    "artwork" => HasArtwork: Flag,
+    "apetag" => HasApeTag: Flag,
+    "narrator" => Narrator: Text,
+    "series" => Series: Text,
+    "seriespart" => SeriesPart: Number,
+    "chapters" => Chapters: Number,
+    "format" => Format: Text,
+    "filename" => FileName: Text,
+    "duration" => Duration: Text,
+    "seconds" => Seconds: Number,
+    "bitrate" => Bitrate: Number,
+    "samplerate" => SampleRate: Number,
+    "bitdepth" => BitDepth: Number,
+    "channels" => Channels: Number,
+    "codec" => Codec: Text,
+    "profile" => Profile: Text,
+    "lossless" => Lossless: Flag,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut parser = Parser { src: source, pos: 0 };
+        let parts = parser.parts(None)?;
+        Ok(Self { source: source.to_owned(), parts })
+    }
+
+    /// The names of all fields that can be used in a template.
+    pub fn field_names() -> impl Iterator<Item = &'static str> {
+        FIELDS.iter().map(|(n, _, _)| *n)
+    }
+
+    /// Renders the path components of the song, the last one is the file name without an
+    /// extension. The components aren't sanitized.
+    pub fn render(&self, song: &Song, context: &Context) -> Vec<String> {
+        let mut components = vec![String::new()];
+        render_parts(&self.parts, song, context, &mut components);
+        components
+    }
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at column {}", self.message, self.column)
+    }
+}
+
+impl error::Error for TemplateError {}
+
+fn render_parts(parts: &[Part], song: &Song, context: &Context, components: &mut Vec<String>) {
+    for p in parts {
+        match p {
+            Part::Literal(l) => components.last_mut().unwrap().push_str(l),
+            Part::Separator => components.push(String::new()),
+            Part::Field { field, width } => {
+                let value = match field.value(song, context) {
+                    Some(FieldValue::Text(t)) => t,
+                    Some(FieldValue::Number(n)) => format!("{:01$}", n, width.unwrap_or(0)),
+                    Some(FieldValue::Flag(_)) | None => continue,
+                };
+                components.last_mut().unwrap().push_str(&value);
+            }
+            Part::Conditional { negate, condition, then, otherwise } => {
+                match condition.matches(song, context) != *negate {
+                    true => render_parts(then, song, context, components),
+                    false => render_parts(otherwise, song, context, components),
+                }
+            }
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, song: &Song, context: &Context) -> bool {
+        match self {
+            Self::Present(field) => match field.value(song, context) {
+                Some(FieldValue::Text(t)) => !t.is_empty(),
+                Some(FieldValue::Number(_)) => true,
+                Some(FieldValue::Flag(f)) => f,
+                None => false,
+            },
+            Self::Compare(field, op, literal) => match (field.value(song, context), literal) {
+                (Some(FieldValue::Number(a)), Literal::Number(b)) => op.compare(&a, b),
+                (Some(FieldValue::Text(a)), Literal::Text(b)) => op.compare(a.as_str(), b.as_str()),
+                _ => false,
+            },
+        }
+    }
+}
+
+impl Op {
+    fn compare<T: PartialOrd + ?Sized>(self, a: &T, b: &T) -> bool {
+        match self {
+            Self::Eq => a == b,
+            Self::Ne => a != b,
+            Self::Lt => a < b,
+            Self::Le => a <= b,
+            Self::Gt => a > b,
+            Self::Ge => a >= b,
+        }
+    }
+}
+
+impl Field {
+    fn kind(self) -> Kind {
+        FIELDS.iter().find(|(_, f, _)| *f == self).map(|(_, _, k)| *k).unwrap()
+    }
+
+    fn value(self, song: &Song, context: &Context) -> Option<FieldValue> {
+        use FieldValue::{Flag, Number, Text};
+
+        let text = |s: &Option<String>| s.clone().map(Text);
+        let list = |v: &[String]| Some(Text(v.join(", "))).filter(|_| !v.is_empty());
+        let number = |n: Option<u16>| n.map(|n| Number(n as i64));
+        let properties = &song.properties;
+
+        match self {
+            Self::ArtistDir => Some(Text(context.artist_dir.clone())),
+            Self::ReleaseArtists => list(&song.release_artists),
+            Self::Artists => list(&song.artists),
+            Self::Release => Some(Text(song.release.clone())),
+            Self::Title => Some(Text(song.title.clone())),
+            Self::ReleaseArtistsSort => text(&song.release_artists_sort),
+            Self::ArtistsSort => text(&song.artists_sort),
+            Self::ReleaseSort => text(&song.release_sort),
+            Self::TitleSort => text(&song.title_sort),
+            Self::TrackNumber => number(song.track_number),
+            Self::TotalTracks => number(song.total_tracks),
+            Self::DiscNumber => number(song.disc_number),
+            Self::TotalDiscs => number(song.total_discs),
+            Self::DiscSubtitle => text(&song.disc_subtitle),
+            Self::Year => context.year.map(|y| Number(y as i64)),
+            Self::OriginalYear => context.original_year.map(|y| Number(y as i64)),
+            Self::ReleaseDate => song.release_date.map(|d| Text(d.to_string())),
+            Self::OriginalDate => song.original_date.map(|d| Text(d.to_string())),
+            Self::TrackGain => song.track_gain.map(|g| Text(g.gain_str())),
+            Self::ReleaseGain => song.release_gain.map(|g| Text(g.gain_str())),
+            Self::MbRecordingId => text(&song.mb_recording_id),
+            Self::MbReleaseId => text(&song.mb_release_id),
+            Self::MbReleaseGroupId => text(&song.mb_release_group_id),
+            Self::MbArtistIds => list(&song.mb_artist_ids),
+            Self::MbReleaseArtistIds => list(&song.mb_release_artist_ids),
+            Self::Genre => song.genres.first().map(|g| Text(g.clone())),
+            Self::Genres => list(&song.genres),
+            Self::Composer => text(&song.composer),
+            Self::Conductor => text(&song.conductor),
+            Self::Comment => text(&song.comment),
+            Self::Grouping => text(&song.grouping),
+            Self::Compilation => Some(Flag(song.compilation)),
+            Self::HasArtwork => Some(Flag(song.has_artwork)),
+            Self::HasApeTag => Some(Flag(song.has_ape_tag)),
+            Self::Narrator => text(&song.narrator),
+            Self::Series => text(&song.series),
+            Self::SeriesPart => number(song.series_part),
+            Self::Chapters => Some(Number(song.chapters.len() as i64)),
+            Self::Format => song.format.map(|f| Text(f.name().to_owned())),
+            Self::FileName => song.path.file_stem().map(|s| Text(s.to_string_lossy().into_owned())),
+            Self::Duration => properties.duration.map(|d| {
+                let secs = d.as_secs();
+                match secs / 3600 {
+                    0 => Text(format!("{}:{:02}", secs / 60, secs % 60)),
+                    h => Text(format!("{}:{:02}:{:02}", h, secs / 60 % 60, secs % 60)),
+                }
+            }),
+            Self::Seconds => properties.duration.map(|d| Number(d.as_secs() as i64)),
+            Self::Bitrate => properties.bitrate.map(|b| Number(b as i64)),
+            Self::SampleRate => properties.sample_rate.map(|r| Number(r as i64)),
+            Self::BitDepth => properties.bit_depth.map(|b| Number(b as i64)),
+            Self::Channels => properties.channels.map(|c| Number(c as i64)),
+            Self::Codec => text(&properties.codec),
+            Self::Profile => text(&properties.profile),
+            Self::Lossless => properties.lossless.map(Flag),
+        }
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        match self.peek() == Some(c) {
+            true => {
+                self.pos += c.len_utf8();
+                true
+            }
+            false => false,
+        }
+    }
+
+    fn eat_while(&mut self, f: impl Fn(char) -> bool) {
+        while matches!(self.peek(), Some(c) if f(c)) {
+            self.next();
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.eat_while(char::is_whitespace);
+    }
+
+    fn error(&self, pos: usize, message: impl Into<String>) -> TemplateError {
+        TemplateError { column: self.src[..pos].chars().count() + 1, message: message.into() }
+    }
+
+    /// Parses the parts until the end of the template, or the closing quote of a string that
+    /// was opened at `quote`.
+    fn parts(&mut self, quote: Option<usize>) -> Result<Vec<Part>, TemplateError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+
+        loop {
+            let start = self.pos;
+            let c = match (self.next(), quote) {
+                (None, Some(q)) => return Err(self.error(q, "unterminated string")),
+                (None, None) => break,
+                (Some('"'), Some(_)) => break,
+                (Some(c), _) => c,
+            };
+
+            match c {
+                '\\' if quote.is_some() => match self.next() {
+                    Some(c @ ('"' | '\\')) => literal.push(c),
+                    _ => return Err(self.error(start, "invalid escape, expected `\\\"` or `\\\\`")),
+                },
+                '{' if self.eat('{') => literal.push('{'),
+                '}' if self.eat('}') => literal.push('}'),
+                '}' => return Err(self.error(start, "unmatched `}`, use `}}` for a literal brace")),
+                '/' | '{' => {
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    match c {
+                        '/' => parts.push(Part::Separator),
+                        _ => parts.push(self.expression(start)?),
+                    }
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(parts)
+    }
+
+    /// Parses the expression after a `{` at `open`.
+    fn expression(&mut self, open: usize) -> Result<Part, TemplateError> {
+        self.skip_whitespace();
+        let negate = self.eat('!');
+        self.skip_whitespace();
+        let field_pos = self.pos;
+        let field = self.field()?;
+        self.skip_whitespace();
+
+        if !negate && self.eat(':') {
+            let width_pos = self.pos;
+            let width = self.width()?;
+            if field.kind() != Kind::Number {
+                return Err(self.error(width_pos, "only numbers can be padded"));
+            }
+            self.close(open)?;
+            return Ok(Part::Field { field, width: Some(width) });
+        }
+        if !negate && self.peek() == Some('}') {
+            if field.kind() == Kind::Flag {
+                return Err(self.error(field_pos, "flags can only be used in conditions"));
+            }
+            self.close(open)?;
+            return Ok(Part::Field { field, width: None });
+        }
+
+        let op_pos = self.pos;
+        let condition = match self.op() {
+            Some(op) => {
+                self.skip_whitespace();
+                let literal_pos = self.pos;
+                let literal = match self.eat('"') {
+                    true => Literal::Text(self.string(literal_pos)?),
+                    false => Literal::Number(self.number()?),
+                };
+                match (field.kind(), op, &literal) {
+                    (Kind::Flag, _, _) => {
+                        return Err(self.error(op_pos, "flags can't be compared"));
+                    }
+                    (Kind::Text, Op::Eq | Op::Ne, Literal::Text(_)) => (),
+                    (Kind::Text, Op::Eq | Op::Ne, Literal::Number(_)) => {
+                        return Err(self.error(literal_pos, "text has to be compared to a string"));
+                    }
+                    (Kind::Text, _, _) => {
+                        return Err(self.error(op_pos, "text can only be compared with = or !="));
+                    }
+                    (Kind::Number, _, Literal::Text(_)) => {
+                        return Err(self.error(literal_pos, "expected a number"));
+                    }
+                    (Kind::Number, _, Literal::Number(_)) => (),
+                }
+                Condition::Compare(field, op, literal)
+            }
+            None => Condition::Present(field),
+        };
+
+        self.skip_whitespace();
+        if self.peek().is_none() {
+            self.close(open)?;
+        }
+        if !self.eat('?') {
+            let message = match negate {
+                true => "expected `?` after the condition",
+                false => "expected `}`, `:`, `?` or a comparison after the field",
+            };
+            return Err(self.error(self.pos, message));
+        }
+
+        let then = self.quoted()?;
+        self.skip_whitespace();
+        let otherwise = match self.eat(':') {
+            true => self.quoted()?,
+            false => Vec::new(),
+        };
+        self.close(open)?;
+
+        Ok(Part::Conditional { negate, condition, then, otherwise })
+    }
+
+    fn field(&mut self) -> Result<Field, TemplateError> {
+        let start = self.pos;
+        self.eat_while(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        let name = &self.src[start..self.pos];
+        if name.is_empty() {
+            return Err(self.error(start, "expected a field name"));
+        }
+        let field = FIELDS.iter().find(|(n, _, _)| n.eq_ignore_ascii_case(name));
+        match field {
+            Some((_, f, _)) => Ok(*f),
+            None => Err(self.error(start, format!("unknown field `{}`", name))),
+        }
+    }
+
+    fn op(&mut self) -> Option<Op> {
+        let ops = [
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("=", Op::Eq),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+        let (s, op) = ops.iter().find(|(s, _)| self.src[self.pos..].starts_with(s))?;
+        self.pos += s.len();
+        Some(*op)
+    }
+
+    fn number(&mut self) -> Result<i64, TemplateError> {
+        let start = self.pos;
+        self.eat('-');
+        self.eat_while(|c| c.is_ascii_digit());
+
+        self.src[start..self.pos].parse().map_err(|_| self.error(start, "expected a number"))
+    }
+
+    /// The width numbers are padded to, which is at most `MAX_WIDTH`.
+    fn width(&mut self) -> Result<usize, TemplateError> {
+        let start = self.pos;
+        self.eat_while(|c| c.is_ascii_digit());
+
+        match self.src[start..self.pos].parse() {
+            Ok(w) if w <= MAX_WIDTH => Ok(w),
+            _ => Err(self.error(start, format!("expected a width from 0 to {}", MAX_WIDTH))),
+        }
+    }
+
+    /// A string without placeholders, the opening quote at `open` was already consumed.
+    fn string(&mut self, open: usize) -> Result<String, TemplateError> {
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                None => return Err(self.error(open, "unterminated string")),
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some(c @ ('"' | '\\')) => s.push(c),
+                    _ => {
+                        let pos = self.pos - 1;
+                        return Err(self.error(pos, "invalid escape, expected `\\\"` or `\\\\`"));
+                    }
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    /// A quoted template, which might contain placeholders and conditions itself.
+    fn quoted(&mut self) -> Result<Vec<Part>, TemplateError> {
+        self.skip_whitespace();
+        let open = self.pos;
+        if !self.eat('"') {
+            return Err(self.error(open, "expected a quoted template like \"{title}\""));
+        }
+        self.parts(Some(open))
+    }
+
+    fn close(&mut self, open: usize) -> Result<(), TemplateError> {
+        self.skip_whitespace();
+        if self.eat('}') {
+            return Ok(());
+        }
+
+        let column = self.error(open, "").column;
+        Err(self.error(self.pos, format!("expected `}}` to close the `{{` at column {}", column)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song() -> Song {
+        Song {
+            release_artists: vec!["Band".to_owned()],
+            artists: vec!["Singer".to_owned(), "Band".to_owned()],
+            release: "Album".to_owned(),
+            title: "Song".to_owned(),
+            track_number: Some(3),
+            disc_number: Some(2),
+            total_discs: Some(2),
+            ..Default::default()
+        }
+    }
+
+    fn render(template: &str, song: &Song) -> Vec<String> {
+        let context =
+            Context { artist_dir: "Band".to_owned(), year: Some(2001), original_year: None };
+        Template::parse(template).unwrap().render(song, &context)
+    }
+
+    fn error(template: &str) -> (usize, String) {
+        let e = Template::parse(template).unwrap_err();
+        (e.column, e.message)
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            render("{artistdir}/{year} - {album}/{track:02} {artist} - {title}", &song()),
+            ["Band", "2001 - Album", "03 Singer, Band - Song"]
+        );
+        assert_eq!(render("{track:0}{track:1}{track:16}", &song()), ["330000000000000003"]);
+        assert_eq!(render("{ TITLE }", &song()), ["Song"]);
+        assert_eq!(render("{{{title}}}", &song()), ["{Song}"]);
+    }
+
+    #[test]
+    fn missing_fields() {
+        assert_eq!(render("{composer}{originalyear:02}{title}", &song()), ["Song"]);
+    }
+
+    #[test]
+    fn conditionals() {
+        let s = song();
+        assert_eq!(render("{year? \"{year} - \"}{album}", &s), ["2001 - Album"]);
+        assert_eq!(render("{!composer? \"none\" : \"{composer}\"}", &s), ["none"]);
+        assert_eq!(render("{discs>1? \"CD{disc}/\"}{title}", &s), ["CD2", "Song"]);
+        assert_eq!(render("{track<=2? \"a\" : \"b\"}", &s), ["b"]);
+        assert_eq!(render("{album=\"Album\"? \"x\"}{album!=\"Album\"? \"y\"}", &s), ["x"]);
+        assert_eq!(render("{compilation? \"VA\" : \"{albumartist}\"}", &s), ["Band"]);
+        assert_eq!(render("{title? \"\\\"{title}\\\" \\\\\"}", &s), ["\"Song\" \\"]);
+    }
+
+    #[test]
+    fn nested_conditionals() {
+        // Quotes inside a placeholder start a new template, they don't end the outer one
+        let template = "{discs>1? \"{disc=1? \"first\" : \"{disc}\"}/\"}{title}";
+        assert_eq!(render(template, &song()), ["2", "Song"]);
+
+        let s = Song { disc_number: Some(1), ..song() };
+        assert_eq!(render(template, &s), ["first", "Song"]);
+
+        let s = Song { total_discs: Some(1), ..song() };
+        assert_eq!(render(template, &s), ["Song"]);
+    }
+
+    #[test]
+    fn width_errors() {
+        let message = "expected a width from 0 to 16".to_owned();
+        assert_eq!(error("{track:-1}"), (8, message.clone()));
+        assert_eq!(error("{track:17}"), (8, message.clone()));
+        assert_eq!(error("{track:99999999999999999999}"), (8, message.clone()));
+        assert_eq!(error("{track:}"), (8, message));
+        assert_eq!(error("{title:02}"), (8, "only numbers can be padded".to_owned()));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(error("a}"), (2, "unmatched `}`, use `}}` for a literal brace".to_owned()));
+        assert_eq!(error("{}"), (2, "expected a field name".to_owned()));
+        assert_eq!(error("{foo}"), (2, "unknown field `foo`".to_owned()));
+        assert_eq!(error("{title"), (7, "expected `}` to close the `{` at column 1".to_owned()));
+        assert_eq!(
+            error("{title \"x\"}"),
+            (8, "expected `}`, `:`, `?` or a comparison after the field".to_owned())
+        );
+        assert_eq!(error("{!title \"x\"}"), (9, "expected `?` after the condition".to_owned()));
+        assert_eq!(
+            error("{title? x}"),
+            (9, "expected a quoted template like \"{title}\"".to_owned())
+        );
+        assert_eq!(
+            error("{title? \"x\""),
+            (12, "expected `}` to close the `{` at column 1".to_owned())
+        );
+        assert_eq!(
+            error("{track:02 x}"),
+            (11, "expected `}` to close the `{` at column 1".to_owned())
+        );
+    }
+
+    #[test]
+    fn string_errors() {
+        assert_eq!(error("{title? \"x"), (9, "unterminated string".to_owned()));
+        assert_eq!(
+            error("{title? \"\\x\"}"),
+            (10, "invalid escape, expected `\\\"` or `\\\\`".to_owned())
+        );
+        assert_eq!(error("{format=\"MP3}"), (9, "unterminated string".to_owned()));
+        assert_eq!(
+            error("{format=\"\\x\"? \"\"}"),
+            (11, "invalid escape, expected `\\\"` or `\\\\`".to_owned())
+        );
+    }
+
+    #[test]
+    fn condition_errors() {
+        assert_eq!(error("{compilation}"), (2, "flags can only be used in conditions".to_owned()));
+        assert_eq!(error("{compilation=1? \"x\"}"), (13, "flags can't be compared".to_owned()));
+        assert_eq!(
+            error("{format=1? \"x\"}"),
+            (9, "text has to be compared to a string".to_owned())
+        );
+        assert_eq!(
+            error("{format<\"a\"? \"x\"}"),
+            (8, "text can only be compared with = or !=".to_owned())
+        );
+        assert_eq!(error("{track=\"1\"? \"x\"}"), (8, "expected a number".to_owned()));
+        assert_eq!(error("{track>? \"x\"}"), (8, "expected a number".to_owned()));
+    }
+}
@@ -1,15 +1,46 @@
 use std::{error, path::Path};
 
+use crate::meta::{
+    parse_pair, ARTISTS_SORT, CONDUCTOR, DISC_SUBTITLE, MB_ARTIST_ID, MB_RECORDING_ID,
+    MB_RELEASE_ARTIST_ID, MB_RELEASE_GROUP_ID, MB_RELEASE_ID, MB_UFID_OWNER, NARRATOR,
+    NARRATOR_FREEFORM, ORIGINAL_DATE, RELEASE_ARTISTS_SORT, RELEASE_SORT, TITLE_SORT,
+};
+use crate::vorbis::VorbisComment;
+use crate::{ape, flac, ogg, riff, Date, DateKind, Format, Metadata, ReplayGain, Song};
+
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct TagUpdate {
     pub track_number: Value<u16>,
     pub total_tracks: Value<u16>,
     pub disc_number: Value<u16>,
     pub total_discs: Value<u16>,
+    pub disc_subtitle: Value<String>,
     pub artists: Value<Vec<String>>,
     pub release_artists: Value<Vec<String>>,
     pub release: Value<String>,
     pub title: Value<String>,
+    pub release_artists_sort: Value<String>,
+    pub artists_sort: Value<String>,
+    pub release_sort: Value<String>,
+    pub title_sort: Value<String>,
+    pub release_date: Value<Date>,
+    pub original_date: Value<Date>,
+    pub track_gain: Value<ReplayGain>,
+    pub release_gain: Value<ReplayGain>,
+    pub mb_recording_id: Value<String>,
+    pub mb_release_id: Value<String>,
+    pub mb_release_group_id: Value<String>,
+    pub mb_artist_ids: Value<Vec<String>>,
+    pub mb_release_artist_ids: Value<Vec<String>>,
+    pub genres: Value<Vec<String>>,
+    pub composer: Value<String>,
+    pub conductor: Value<String>,
+    pub comment: Value<String>,
+    pub grouping: Value<String>,
+    pub compilation: Value<bool>,
+    /// Only stored in MPEG-4 files, which is where audiobooks carry it.
+    pub narrator: Value<String>,
+    pub ape_policy: ApePolicy,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -21,6 +52,16 @@ pub enum Value<T> {
 
 impl<T: Copy> Copy for Value<T> {}
 
+/// What to do with an APEv2 tag that is found next to the ID3 tag of an MP3 file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ApePolicy {
+    /// Apply the same changes to both tags.
+    #[default]
+    Sync,
+    /// Remove the APEv2 tag.
+    Strip,
+}
+
 impl<T> Default for Value<T> {
     fn default() -> Self {
         Self::Unchanged
@@ -34,22 +75,171 @@ impl<T> Value<T> {
             _ => None,
         }
     }
+
+    pub fn is_unchanged(&self) -> bool {
+        matches!(self, Self::Unchanged)
+    }
+
+    pub fn map_ref<U>(&self, f: impl FnOnce(&T) -> U) -> Value<U> {
+        match self {
+            Self::Update(v) => Value::Update(f(v)),
+            Self::Remove => Value::Remove,
+            Self::Unchanged => Value::Unchanged,
+        }
+    }
+
+    /// Returns the resulting value when this change is applied to the old one.
+    pub fn apply(self, old: Option<T>) -> Option<T> {
+        match self {
+            Self::Update(v) => Some(v),
+            Self::Remove => None,
+            Self::Unchanged => old,
+        }
+    }
 }
 
 impl TagUpdate {
+    /// Applies the changes to the metadata of a song, like it would be read after the tag is
+    /// written.
+    pub fn apply_to(&self, song: &mut Song) {
+        fn opt<T: Clone>(value: &Value<T>, field: &mut Option<T>) {
+            *field = value.clone().apply(field.take());
+        }
+        fn list(value: &Value<Vec<String>>, field: &mut Vec<String>) {
+            *field = value.clone().apply(Some(std::mem::take(field))).unwrap_or_default();
+        }
+
+        opt(&self.track_number, &mut song.track_number);
+        opt(&self.total_tracks, &mut song.total_tracks);
+        opt(&self.disc_number, &mut song.disc_number);
+        opt(&self.total_discs, &mut song.total_discs);
+        opt(&self.disc_subtitle, &mut song.disc_subtitle);
+        // The required fields can't be removed, release artists fall back to the artists
+        if let Value::Update(a) = &self.artists {
+            song.artists = a.clone();
+        }
+        match &self.release_artists {
+            Value::Update(a) => {
+                song.release_artists = a.clone();
+                song.has_release_artists = true;
+            }
+            Value::Remove => {
+                song.release_artists = song.artists.clone();
+                song.has_release_artists = false;
+            }
+            Value::Unchanged => (),
+        }
+        if let Value::Update(r) = &self.release {
+            song.release = r.clone();
+        }
+        if let Value::Update(t) = &self.title {
+            song.title = t.clone();
+        }
+        opt(&self.release_artists_sort, &mut song.release_artists_sort);
+        opt(&self.artists_sort, &mut song.artists_sort);
+        opt(&self.release_sort, &mut song.release_sort);
+        opt(&self.title_sort, &mut song.title_sort);
+        opt(&self.release_date, &mut song.release_date);
+        opt(&self.original_date, &mut song.original_date);
+        opt(&self.track_gain, &mut song.track_gain);
+        opt(&self.release_gain, &mut song.release_gain);
+        opt(&self.mb_recording_id, &mut song.mb_recording_id);
+        opt(&self.mb_release_id, &mut song.mb_release_id);
+        opt(&self.mb_release_group_id, &mut song.mb_release_group_id);
+        list(&self.mb_artist_ids, &mut song.mb_artist_ids);
+        list(&self.mb_release_artist_ids, &mut song.mb_release_artist_ids);
+        list(&self.genres, &mut song.genres);
+        opt(&self.composer, &mut song.composer);
+        opt(&self.conductor, &mut song.conductor);
+        opt(&self.comment, &mut song.comment);
+        opt(&self.grouping, &mut song.grouping);
+        song.compilation = self.compilation.apply(Some(song.compilation)) == Some(true);
+        opt(&self.narrator, &mut song.narrator);
+    }
+
+    /// Takes over the changes of `other`, fields that it doesn't change are kept.
+    pub fn merge(&mut self, other: TagUpdate) {
+        fn field<T>(value: &mut Value<T>, other: Value<T>) {
+            if !other.is_unchanged() {
+                *value = other;
+            }
+        }
+
+        field(&mut self.track_number, other.track_number);
+        field(&mut self.total_tracks, other.total_tracks);
+        field(&mut self.disc_number, other.disc_number);
+        field(&mut self.total_discs, other.total_discs);
+        field(&mut self.disc_subtitle, other.disc_subtitle);
+        field(&mut self.artists, other.artists);
+        field(&mut self.release_artists, other.release_artists);
+        field(&mut self.release, other.release);
+        field(&mut self.title, other.title);
+        field(&mut self.release_artists_sort, other.release_artists_sort);
+        field(&mut self.artists_sort, other.artists_sort);
+        field(&mut self.release_sort, other.release_sort);
+        field(&mut self.title_sort, other.title_sort);
+        field(&mut self.release_date, other.release_date);
+        field(&mut self.original_date, other.original_date);
+        field(&mut self.track_gain, other.track_gain);
+        field(&mut self.release_gain, other.release_gain);
+        field(&mut self.mb_recording_id, other.mb_recording_id);
+        field(&mut self.mb_release_id, other.mb_release_id);
+        field(&mut self.mb_release_group_id, other.mb_release_group_id);
+        field(&mut self.mb_artist_ids, other.mb_artist_ids);
+        field(&mut self.mb_release_artist_ids, other.mb_release_artist_ids);
+        field(&mut self.genres, other.genres);
+        field(&mut self.composer, other.composer);
+        field(&mut self.conductor, other.conductor);
+        field(&mut self.comment, other.comment);
+        field(&mut self.grouping, other.grouping);
+        field(&mut self.compilation, other.compilation);
+        field(&mut self.narrator, other.narrator);
+        if other.ape_policy != ApePolicy::default() {
+            self.ape_policy = other.ape_policy;
+        }
+    }
+
+    pub fn date_mut(&mut self, kind: DateKind) -> &mut Value<Date> {
+        match kind {
+            DateKind::Release => &mut self.release_date,
+            DateKind::Original => &mut self.original_date,
+        }
+    }
+
     pub fn execute(&self, path: &Path) -> Result<(), Box<dyn error::Error>> {
-        match path.extension().unwrap().to_str().unwrap() {
-            "mp3" => {
-                let tag = match id3::Tag::read_from_path(path) {
+        match Format::detect(path) {
+            Some(Format::Mp3) => {
+                let mut tag = id3::Tag::read_from_path(path).unwrap_or_default();
+                let ape_tag = ape::Tag::read_from_path(path).ok();
+
+                if let (Some(a), ApePolicy::Strip) = (&ape_tag, self.ape_policy) {
+                    // Values that are only present in the APEv2 tag would get lost otherwise
+                    fill_id3(&mut tag, Metadata::from_ape(a));
+                }
+                self.update_id3(&mut tag);
+                tag.write_to_path(path, id3::Version::Id3v24)?;
+
+                if let Some(mut a) = ape_tag {
+                    match self.ape_policy {
+                        ApePolicy::Sync => {
+                            self.update_ape(&mut a);
+                            a.write_to_path(path)?;
+                        }
+                        ApePolicy::Strip => ape::Tag::remove_from_path(path)?,
+                    }
+                }
+            }
+            Some(Format::Mp4) => {
+                let tag = match mp4ameta::Tag::read_from_path(path) {
                     Ok(mut tag) => {
                         match &self.release_artists {
-                            Value::Update(a) => tag.set_album_artist(a.join("\u{0}")),
-                            Value::Remove => tag.remove_album_artist(),
+                            Value::Update(a) => tag.set_album_artists(a.clone()),
+                            Value::Remove => tag.remove_album_artists(),
                             Value::Unchanged => (),
                         }
-                        match &self.release_artists {
-                            Value::Update(a) => tag.set_artist(a.join("\u{0}")),
-                            Value::Remove => tag.remove_artist(),
+                        match &self.artists {
+                            Value::Update(a) => tag.set_artists(a.clone()),
+                            Value::Remove => tag.remove_artists(),
                             Value::Unchanged => (),
                         }
                         match &self.release {
@@ -62,75 +252,130 @@ impl TagUpdate {
                             Value::Remove => tag.remove_title(),
                             Value::Unchanged => (),
                         }
+                        for (ident, value) in [
+                            (RELEASE_ARTISTS_SORT, &self.release_artists_sort),
+                            (ARTISTS_SORT, &self.artists_sort),
+                            (RELEASE_SORT, &self.release_sort),
+                            (TITLE_SORT, &self.title_sort),
+                        ] {
+                            match value {
+                                Value::Update(v) => {
+                                    tag.set_data(ident, mp4ameta::Data::Utf8(v.clone()))
+                                }
+                                Value::Remove => tag.remove_data_of(&ident),
+                                Value::Unchanged => (),
+                            }
+                        }
                         match &self.track_number {
-                            Value::Update(t) => tag.set_track(*t as u32),
-                            Value::Remove => tag.remove_track(),
+                            Value::Update(t) => tag.set_track_number(*t),
+                            Value::Remove => tag.remove_track_number(),
                             Value::Unchanged => (),
                         }
                         match &self.total_tracks {
-                            Value::Update(t) => tag.set_total_tracks(*t as u32),
+                            Value::Update(t) => tag.set_total_tracks(*t),
                             Value::Remove => tag.remove_total_tracks(),
                             Value::Unchanged => (),
                         }
                         match &self.disc_number {
-                            Value::Update(d) => tag.set_disc(*d as u32),
-                            Value::Remove => tag.remove_disc(),
+                            Value::Update(d) => tag.set_disc_number(*d),
+                            Value::Remove => tag.remove_disc_number(),
                             Value::Unchanged => (),
                         }
                         match &self.total_discs {
-                            Value::Update(d) => tag.set_total_discs(*d as u32),
+                            Value::Update(d) => tag.set_total_discs(*d),
                             Value::Remove => tag.remove_total_discs(),
                             Value::Unchanged => (),
                         }
-
-                        tag
-                    }
-                    Err(_) => id3::Tag::default(),
-                };
-
-                tag.write_to_path(path, id3::Version::Id3v24)?;
-            }
-            "m4a" | "m4b" | "m4p" | "m4v" => {
-                let tag = match mp4ameta::Tag::read_from_path(path) {
-                    Ok(mut tag) => {
-                        match &self.release_artists {
-                            Value::Update(a) => tag.set_album_artists(a.clone()),
-                            Value::Remove => tag.remove_album_artists(),
+                        match &self.disc_subtitle {
+                            Value::Update(s) => {
+                                tag.set_data(DISC_SUBTITLE, mp4ameta::Data::Utf8(s.clone()))
+                            }
+                            Value::Remove => tag.remove_data_of(&DISC_SUBTITLE),
                             Value::Unchanged => (),
                         }
-                        match &self.artists {
-                            Value::Update(a) => tag.set_artists(a.clone()),
-                            Value::Remove => tag.remove_artists(),
+                        match &self.release_date {
+                            Value::Update(d) => tag.set_year(d.to_string()),
+                            Value::Remove => tag.remove_year(),
                             Value::Unchanged => (),
                         }
-                        match &self.release {
-                            Value::Update(a) => tag.set_album(a),
-                            Value::Remove => tag.remove_album(),
+                        match &self.original_date {
+                            Value::Update(d) => {
+                                tag.set_data(ORIGINAL_DATE, mp4ameta::Data::Utf8(d.to_string()))
+                            }
+                            Value::Remove => tag.remove_data_of(&ORIGINAL_DATE),
                             Value::Unchanged => (),
                         }
-                        match &self.title {
-                            Value::Update(t) => tag.set_title(t),
-                            Value::Remove => tag.remove_title(),
+                        for (scope, value) in
+                            [("TRACK", self.track_gain), ("ALBUM", self.release_gain)]
+                        {
+                            update_mp4_replay_gain(&mut tag, scope, value);
+                        }
+                        for (ident, value) in [
+                            (MB_RECORDING_ID, &self.mb_recording_id),
+                            (MB_RELEASE_ID, &self.mb_release_id),
+                            (MB_RELEASE_GROUP_ID, &self.mb_release_group_id),
+                        ] {
+                            match value {
+                                Value::Update(v) => {
+                                    tag.set_data(ident, mp4ameta::Data::Utf8(v.clone()))
+                                }
+                                Value::Remove => tag.remove_data_of(&ident),
+                                Value::Unchanged => (),
+                            }
+                        }
+                        for (ident, value) in [
+                            (MB_ARTIST_ID, &self.mb_artist_ids),
+                            (MB_RELEASE_ARTIST_ID, &self.mb_release_artist_ids),
+                        ] {
+                            match value {
+                                Value::Update(v) => tag.set_all_data(
+                                    ident,
+                                    v.iter().map(|v| mp4ameta::Data::Utf8(v.clone())),
+                                ),
+                                Value::Remove => tag.remove_data_of(&ident),
+                                Value::Unchanged => (),
+                            }
+                        }
+                        match &self.genres {
+                            Value::Update(g) => tag.set_genres(g.clone()),
+                            Value::Remove => tag.remove_genres(),
                             Value::Unchanged => (),
                         }
-                        match &self.track_number {
-                            Value::Update(t) => tag.set_track_number(*t),
-                            Value::Remove => tag.remove_track_number(),
+                        match &self.composer {
+                            Value::Update(c) => tag.set_composer(c),
+                            Value::Remove => tag.remove_composers(),
                             Value::Unchanged => (),
                         }
-                        match &self.total_tracks {
-                            Value::Update(t) => tag.set_total_tracks(*t),
-                            Value::Remove => tag.remove_total_tracks(),
+                        match &self.conductor {
+                            Value::Update(c) => {
+                                tag.set_data(CONDUCTOR, mp4ameta::Data::Utf8(c.clone()))
+                            }
+                            Value::Remove => tag.remove_data_of(&CONDUCTOR),
                             Value::Unchanged => (),
                         }
-                        match &self.disc_number {
-                            Value::Update(d) => tag.set_disc_number(*d),
-                            Value::Remove => tag.remove_disc_number(),
+                        match &self.comment {
+                            Value::Update(c) => tag.set_comment(c),
+                            Value::Remove => tag.remove_comments(),
                             Value::Unchanged => (),
                         }
-                        match &self.total_discs {
-                            Value::Update(d) => tag.set_total_discs(*d),
-                            Value::Remove => tag.remove_total_discs(),
+                        match &self.grouping {
+                            Value::Update(g) => tag.set_grouping(g),
+                            Value::Remove => tag.remove_groupings(),
+                            Value::Unchanged => (),
+                        }
+                        match &self.compilation {
+                            Value::Update(true) => tag.set_compilation(),
+                            Value::Update(false) | Value::Remove => tag.remove_compilation(),
+                            Value::Unchanged => (),
+                        }
+                        if !self.narrator.is_unchanged() {
+                            tag.remove_data_of(&NARRATOR_FREEFORM);
+                        }
+                        match &self.narrator {
+                            Value::Update(n) => {
+                                tag.set_data(NARRATOR, mp4ameta::Data::Utf8(n.clone()))
+                            }
+                            Value::Remove => tag.remove_data_of(&NARRATOR),
                             Value::Unchanged => (),
                         }
 
@@ -141,9 +386,648 @@ impl TagUpdate {
 
                 tag.write_to_path(path)?;
             }
-            _ => (),
+            Some(Format::Flac) => {
+                let mut tag = flac::Tag::read_from_path(path)?;
+                self.update_vorbis(&mut tag.comment, false);
+                tag.write_to_path(path)?;
+            }
+            Some(f @ (Format::Ogg | Format::Opus)) => {
+                let mut tag = ogg::Tag::read_from_path(path)?;
+                self.update_vorbis(&mut tag.comment, f == Format::Opus);
+                tag.write_to_path(path)?;
+            }
+            Some(Format::Wav) => {
+                let mut tag = match id3::Tag::read_from_wav(path) {
+                    Ok(tag) => tag,
+                    Err(_) => {
+                        // Keep the values of the LIST/INFO chunk in the new ID3 chunk
+                        let mut tag = id3::Tag::default();
+                        if let Ok(info) = riff::Info::read_from_path(path) {
+                            fill_id3(&mut tag, Metadata::from_riff_info(&info));
+                        }
+                        tag
+                    }
+                };
+                self.update_id3(&mut tag);
+                tag.write_to_wav(path, id3::Version::Id3v24)?;
+            }
+            Some(Format::Aiff) => {
+                let mut tag = id3::Tag::read_from_aiff(path).unwrap_or_default();
+                self.update_id3(&mut tag);
+                tag.write_to_aiff(path, id3::Version::Id3v24)?;
+            }
+            Some(Format::Ape | Format::WavPack | Format::Musepack) => {
+                let mut tag = ape::Tag::read_from_path(path).unwrap_or_default();
+                self.update_ape(&mut tag);
+                tag.write_to_path(path)?;
+            }
+            None => (),
         }
 
         Ok(())
     }
+
+    fn update_id3(&self, tag: &mut id3::Tag) {
+        match &self.release_artists {
+            Value::Update(a) => tag.set_album_artist(a.join("\u{0}")),
+            Value::Remove => tag.remove_album_artist(),
+            Value::Unchanged => (),
+        }
+        match &self.artists {
+            Value::Update(a) => tag.set_artist(a.join("\u{0}")),
+            Value::Remove => tag.remove_artist(),
+            Value::Unchanged => (),
+        }
+        match &self.release {
+            Value::Update(a) => tag.set_album(a),
+            Value::Remove => tag.remove_album(),
+            Value::Unchanged => (),
+        }
+        match &self.title {
+            Value::Update(t) => tag.set_title(t),
+            Value::Remove => tag.remove_title(),
+            Value::Unchanged => (),
+        }
+        for (id, value) in [
+            ("TSO2", &self.release_artists_sort),
+            ("TSOP", &self.artists_sort),
+            ("TSOA", &self.release_sort),
+            ("TSOT", &self.title_sort),
+        ] {
+            match value {
+                Value::Update(v) => tag.set_text(id, v),
+                Value::Remove => tag.remove(id),
+                Value::Unchanged => (),
+            }
+        }
+        match &self.track_number {
+            Value::Update(t) => tag.set_track(*t as u32),
+            Value::Remove => tag.remove_track(),
+            Value::Unchanged => (),
+        }
+        match &self.total_tracks {
+            Value::Update(t) => tag.set_total_tracks(*t as u32),
+            Value::Remove => tag.remove_total_tracks(),
+            Value::Unchanged => (),
+        }
+        match &self.disc_number {
+            Value::Update(d) => tag.set_disc(*d as u32),
+            Value::Remove => tag.remove_disc(),
+            Value::Unchanged => (),
+        }
+        match &self.total_discs {
+            Value::Update(d) => tag.set_total_discs(*d as u32),
+            Value::Remove => tag.remove_total_discs(),
+            Value::Unchanged => (),
+        }
+        match &self.disc_subtitle {
+            Value::Update(s) => tag.set_text("TSST", s),
+            Value::Remove => tag.remove("TSST"),
+            Value::Unchanged => (),
+        }
+        // The ID3v2.3 frames are replaced by the ones of ID3v2.4
+        match &self.release_date {
+            Value::Update(d) => tag.set_text("TDRC", d.to_string()),
+            Value::Remove => tag.remove("TDRC"),
+            Value::Unchanged => (),
+        }
+        if !self.release_date.is_unchanged() {
+            tag.remove("TYER");
+            tag.remove("TDAT");
+        }
+        match &self.original_date {
+            Value::Update(d) => tag.set_text("TDOR", d.to_string()),
+            Value::Remove => tag.remove("TDOR"),
+            Value::Unchanged => (),
+        }
+        if !self.original_date.is_unchanged() {
+            tag.remove("TORY");
+        }
+        for (scope, value) in [("TRACK", self.track_gain), ("ALBUM", self.release_gain)] {
+            update_id3_replay_gain(tag, scope, value);
+        }
+        update_id3_ufid(tag, MB_UFID_OWNER, &self.mb_recording_id);
+        for (description, value) in [
+            ("MusicBrainz Album Id", self.mb_release_id.clone()),
+            ("MusicBrainz Release Group Id", self.mb_release_group_id.clone()),
+            ("MusicBrainz Artist Id", self.mb_artist_ids.map_ref(|v| v.join("\u{0}"))),
+            (
+                "MusicBrainz Album Artist Id",
+                self.mb_release_artist_ids.map_ref(|v| v.join("\u{0}")),
+            ),
+        ] {
+            if !value.is_unchanged() {
+                tag.remove_extended_text(Some(description), None);
+            }
+            if let Value::Update(v) = value {
+                tag.add_extended_text(description, v);
+            }
+        }
+        match &self.genres {
+            Value::Update(g) => tag.set_genre(g.join("\u{0}")),
+            Value::Remove => tag.remove_genre(),
+            Value::Unchanged => (),
+        }
+        match &self.composer {
+            Value::Update(c) => tag.set_text("TCOM", c),
+            Value::Remove => tag.remove("TCOM"),
+            Value::Unchanged => (),
+        }
+        match &self.conductor {
+            Value::Update(c) => tag.set_text("TPE3", c),
+            Value::Remove => tag.remove("TPE3"),
+            Value::Unchanged => (),
+        }
+        if !self.comment.is_unchanged() {
+            tag.remove_comment(Some(""), None);
+        }
+        if let Value::Update(c) = &self.comment {
+            tag.add_comment(id3::frame::Comment {
+                lang: "eng".to_string(),
+                description: String::new(),
+                text: c.clone(),
+            });
+        }
+        match &self.grouping {
+            Value::Update(g) => tag.set_text("TIT1", g),
+            Value::Remove => tag.remove("TIT1"),
+            Value::Unchanged => (),
+        }
+        match &self.compilation {
+            Value::Update(true) => tag.set_text("TCMP", "1"),
+            Value::Update(false) | Value::Remove => tag.remove("TCMP"),
+            Value::Unchanged => (),
+        }
+    }
+
+    fn update_ape(&self, tag: &mut ape::Tag) {
+        match &self.release_artists {
+            Value::Update(a) => tag.set_values("Album Artist", a),
+            Value::Remove => tag.remove("Album Artist"),
+            Value::Unchanged => (),
+        }
+        if !self.release_artists.is_unchanged() {
+            tag.remove("AlbumArtist");
+        }
+        match &self.artists {
+            Value::Update(a) => tag.set_values("Artist", a),
+            Value::Remove => tag.remove("Artist"),
+            Value::Unchanged => (),
+        }
+        match &self.release {
+            Value::Update(a) => tag.set_value("Album", a),
+            Value::Remove => tag.remove("Album"),
+            Value::Unchanged => (),
+        }
+        match &self.title {
+            Value::Update(t) => tag.set_value("Title", t),
+            Value::Remove => tag.remove("Title"),
+            Value::Unchanged => (),
+        }
+        for (key, value) in [
+            ("AlbumArtistSort", &self.release_artists_sort),
+            ("ArtistSort", &self.artists_sort),
+            ("AlbumSort", &self.release_sort),
+            ("TitleSort", &self.title_sort),
+        ] {
+            match value {
+                Value::Update(v) => tag.set_value(key, v),
+                Value::Remove => tag.remove(key),
+                Value::Unchanged => (),
+            }
+        }
+        update_ape_pair(tag, "Track", self.track_number, self.total_tracks);
+        update_ape_pair(tag, "Disc", self.disc_number, self.total_discs);
+        match &self.release_date {
+            Value::Update(d) => tag.set_value("Year", d.to_string()),
+            Value::Remove => tag.remove("Year"),
+            Value::Unchanged => (),
+        }
+        for (scope, value) in [("TRACK", self.track_gain), ("ALBUM", self.release_gain)] {
+            let (gain_key, peak_key) = replay_gain_keys(scope);
+            match value {
+                Value::Update(g) => {
+                    tag.set_value(&gain_key, g.gain_str());
+                    match g.peak_str() {
+                        Some(p) => tag.set_value(&peak_key, p),
+                        None => tag.remove(&peak_key),
+                    }
+                }
+                Value::Remove => {
+                    tag.remove(&gain_key);
+                    tag.remove(&peak_key);
+                }
+                Value::Unchanged => (),
+            }
+        }
+        for (key, value) in [
+            ("MUSICBRAINZ_TRACKID", &self.mb_recording_id),
+            ("MUSICBRAINZ_ALBUMID", &self.mb_release_id),
+            ("MUSICBRAINZ_RELEASEGROUPID", &self.mb_release_group_id),
+        ] {
+            match value {
+                Value::Update(v) => tag.set_value(key, v),
+                Value::Remove => tag.remove(key),
+                Value::Unchanged => (),
+            }
+        }
+        for (key, value) in [
+            ("MUSICBRAINZ_ARTISTID", &self.mb_artist_ids),
+            ("MUSICBRAINZ_ALBUMARTISTID", &self.mb_release_artist_ids),
+        ] {
+            match value {
+                Value::Update(v) => tag.set_values(key, v),
+                Value::Remove => tag.remove(key),
+                Value::Unchanged => (),
+            }
+        }
+        match &self.genres {
+            Value::Update(g) => tag.set_values("Genre", g),
+            Value::Remove => tag.remove("Genre"),
+            Value::Unchanged => (),
+        }
+        for (key, value) in [
+            ("DiscSubtitle", &self.disc_subtitle),
+            ("Composer", &self.composer),
+            ("Conductor", &self.conductor),
+            ("Comment", &self.comment),
+            ("Grouping", &self.grouping),
+        ] {
+            match value {
+                Value::Update(v) => tag.set_value(key, v),
+                Value::Remove => tag.remove(key),
+                Value::Unchanged => (),
+            }
+        }
+        match &self.compilation {
+            Value::Update(true) => tag.set_value("Compilation", "1"),
+            Value::Update(false) | Value::Remove => tag.remove("Compilation"),
+            Value::Unchanged => (),
+        }
+    }
+
+    /// Opus files store the gain in `R128_*` fields instead of `REPLAYGAIN_*` ones.
+    fn update_vorbis(&self, comment: &mut VorbisComment, opus: bool) {
+        match &self.release_artists {
+            Value::Update(a) => comment.set_values("ALBUMARTIST", a.iter().cloned()),
+            Value::Remove => comment.remove("ALBUMARTIST"),
+            Value::Unchanged => (),
+        }
+        if !self.release_artists.is_unchanged() {
+            comment.remove("ALBUM ARTIST");
+        }
+        match &self.artists {
+            Value::Update(a) => comment.set_values("ARTIST", a.iter().cloned()),
+            Value::Remove => comment.remove("ARTIST"),
+            Value::Unchanged => (),
+        }
+        match &self.release {
+            Value::Update(a) => comment.set_value("ALBUM", a),
+            Value::Remove => comment.remove("ALBUM"),
+            Value::Unchanged => (),
+        }
+        match &self.title {
+            Value::Update(t) => comment.set_value("TITLE", t),
+            Value::Remove => comment.remove("TITLE"),
+            Value::Unchanged => (),
+        }
+        for (key, value) in [
+            ("ALBUMARTISTSORT", &self.release_artists_sort),
+            ("ARTISTSORT", &self.artists_sort),
+            ("ALBUMSORT", &self.release_sort),
+            ("TITLESORT", &self.title_sort),
+        ] {
+            match value {
+                Value::Update(v) => comment.set_value(key, v),
+                Value::Remove => comment.remove(key),
+                Value::Unchanged => (),
+            }
+        }
+        update_vorbis_pair(
+            comment,
+            ("TRACKNUMBER", "TRACKTOTAL", "TOTALTRACKS"),
+            self.track_number,
+            self.total_tracks,
+        );
+        update_vorbis_pair(
+            comment,
+            ("DISCNUMBER", "DISCTOTAL", "TOTALDISCS"),
+            self.disc_number,
+            self.total_discs,
+        );
+        match &self.release_date {
+            Value::Update(d) => comment.set_value("DATE", d.to_string()),
+            Value::Remove => comment.remove("DATE"),
+            Value::Unchanged => (),
+        }
+        match &self.original_date {
+            Value::Update(d) => comment.set_value("ORIGINALDATE", d.to_string()),
+            Value::Remove => comment.remove("ORIGINALDATE"),
+            Value::Unchanged => (),
+        }
+        if !self.original_date.is_unchanged() {
+            comment.remove("ORIGINALYEAR");
+        }
+        for (scope, value) in [("TRACK", self.track_gain), ("ALBUM", self.release_gain)] {
+            if value.is_unchanged() {
+                continue;
+            }
+
+            let (gain_key, peak_key) = replay_gain_keys(scope);
+            let r128_key = format!("R128_{}_GAIN", scope);
+            for key in [&gain_key, &peak_key, &r128_key] {
+                comment.remove(key);
+            }
+            match (value, opus) {
+                (Value::Update(g), true) => comment.set_value(&r128_key, g.r128_str()),
+                (Value::Update(g), false) => {
+                    comment.set_value(&gain_key, g.gain_str());
+                    if let Some(p) = g.peak_str() {
+                        comment.set_value(&peak_key, p);
+                    }
+                }
+                _ => (),
+            }
+        }
+        for (key, value) in [
+            ("MUSICBRAINZ_TRACKID", &self.mb_recording_id),
+            ("MUSICBRAINZ_ALBUMID", &self.mb_release_id),
+            ("MUSICBRAINZ_RELEASEGROUPID", &self.mb_release_group_id),
+        ] {
+            match value {
+                Value::Update(v) => comment.set_value(key, v),
+                Value::Remove => comment.remove(key),
+                Value::Unchanged => (),
+            }
+        }
+        for (key, value) in [
+            ("MUSICBRAINZ_ARTISTID", &self.mb_artist_ids),
+            ("MUSICBRAINZ_ALBUMARTISTID", &self.mb_release_artist_ids),
+        ] {
+            match value {
+                Value::Update(v) => comment.set_values(key, v.iter().cloned()),
+                Value::Remove => comment.remove(key),
+                Value::Unchanged => (),
+            }
+        }
+        match &self.genres {
+            Value::Update(g) => comment.set_values("GENRE", g.iter().cloned()),
+            Value::Remove => comment.remove("GENRE"),
+            Value::Unchanged => (),
+        }
+        for (key, value) in [
+            ("DISCSUBTITLE", &self.disc_subtitle),
+            ("COMPOSER", &self.composer),
+            ("CONDUCTOR", &self.conductor),
+            ("COMMENT", &self.comment),
+            ("GROUPING", &self.grouping),
+        ] {
+            match value {
+                Value::Update(v) => comment.set_value(key, v),
+                Value::Remove => comment.remove(key),
+                Value::Unchanged => (),
+            }
+        }
+        if !self.comment.is_unchanged() {
+            comment.remove("DESCRIPTION");
+        }
+        match &self.compilation {
+            Value::Update(true) => comment.set_value("COMPILATION", "1"),
+            Value::Update(false) | Value::Remove => comment.remove("COMPILATION"),
+            Value::Unchanged => (),
+        }
+    }
+}
+
+/// Sets the fields that are missing in the ID3 tag.
+fn fill_id3(tag: &mut id3::Tag, m: Metadata) {
+    if tag.album_artist().is_none() && !m.release_artists.is_empty() {
+        tag.set_album_artist(m.release_artists.join("\u{0}"));
+    }
+    if tag.artist().is_none() && !m.artists.is_empty() {
+        tag.set_artist(m.artists.join("\u{0}"));
+    }
+    if let (None, Some(r)) = (tag.album(), m.release) {
+        tag.set_album(r);
+    }
+    if let (None, Some(t)) = (tag.title(), m.title) {
+        tag.set_title(t);
+    }
+    if let (None, Some(n)) = (tag.track(), m.track_number) {
+        tag.set_track(n as u32);
+    }
+    if let (None, Some(n)) = (tag.total_tracks(), m.total_tracks) {
+        tag.set_total_tracks(n as u32);
+    }
+    if let (None, Some(n)) = (tag.disc(), m.disc_number) {
+        tag.set_disc(n as u32);
+    }
+    if let (None, Some(n)) = (tag.total_discs(), m.total_discs) {
+        tag.set_total_discs(n as u32);
+    }
+    if let (None, None, Some(d)) = (tag.date_recorded(), tag.year(), m.release_date) {
+        tag.set_text("TDRC", d.to_string());
+    }
+    if let (None, Some(d)) = (tag.get("TDOR"), m.original_date) {
+        tag.set_text("TDOR", d.to_string());
+    }
+    for (scope, value) in [("TRACK", m.track_gain), ("ALBUM", m.release_gain)] {
+        let (gain_key, _) = replay_gain_keys(scope);
+        let present = tag.extended_texts().any(|t| t.description.eq_ignore_ascii_case(&gain_key));
+        if let (false, Some(g)) = (present, value) {
+            update_id3_replay_gain(tag, scope, Value::Update(g));
+        }
+    }
+    if let Some(id) = m.mb_recording_id {
+        if !has_ufid(tag, MB_UFID_OWNER) {
+            update_id3_ufid(tag, MB_UFID_OWNER, &Value::Update(id));
+        }
+    }
+    for (description, value) in [
+        ("MusicBrainz Album Id", m.mb_release_id),
+        ("MusicBrainz Release Group Id", m.mb_release_group_id),
+        ("MusicBrainz Artist Id", Some(m.mb_artist_ids.join("\u{0}")).filter(|v| !v.is_empty())),
+        (
+            "MusicBrainz Album Artist Id",
+            Some(m.mb_release_artist_ids.join("\u{0}")).filter(|v| !v.is_empty()),
+        ),
+    ] {
+        let present = tag.extended_texts().any(|t| t.description == description);
+        if let (false, Some(v)) = (present, value) {
+            tag.add_extended_text(description, v);
+        }
+    }
+    if tag.genre().is_none() && !m.genres.is_empty() {
+        tag.set_genre(m.genres.join("\u{0}"));
+    }
+    for (id, value) in [
+        ("TSST", m.disc_subtitle),
+        ("TSO2", m.release_artists_sort),
+        ("TSOP", m.artists_sort),
+        ("TSOA", m.release_sort),
+        ("TSOT", m.title_sort),
+        ("TCOM", m.composer),
+        ("TPE3", m.conductor),
+        ("TIT1", m.grouping),
+    ] {
+        if let (None, Some(v)) = (tag.get(id), value) {
+            tag.set_text(id, v);
+        }
+    }
+    if tag.get("TCMP").is_none() && m.compilation {
+        tag.set_text("TCMP", "1");
+    }
+    let has_comment = tag.comments().any(|c| c.description.is_empty());
+    if let (false, Some(c)) = (has_comment, m.comment) {
+        tag.add_comment(id3::frame::Comment {
+            lang: "eng".to_string(),
+            description: String::new(),
+            text: c,
+        });
+    }
+}
+
+fn replay_gain_keys(scope: &str) -> (String, String) {
+    (format!("REPLAYGAIN_{}_GAIN", scope), format!("REPLAYGAIN_{}_PEAK", scope))
+}
+
+/// Updates the ReplayGain `TXXX` frames, which might have lowercase descriptions.
+fn update_id3_replay_gain(tag: &mut id3::Tag, scope: &str, value: Value<ReplayGain>) {
+    if value.is_unchanged() {
+        return;
+    }
+
+    let (gain_key, peak_key) = replay_gain_keys(scope);
+    let old: Vec<String> = tag
+        .extended_texts()
+        .map(|t| t.description.clone())
+        .filter(|d| d.eq_ignore_ascii_case(&gain_key) || d.eq_ignore_ascii_case(&peak_key))
+        .collect();
+    for d in old {
+        tag.remove_extended_text(Some(&d), None);
+    }
+
+    if let Value::Update(g) = value {
+        tag.add_extended_text(gain_key, g.gain_str());
+        if let Some(p) = g.peak_str() {
+            tag.add_extended_text(peak_key, p);
+        }
+    }
+}
+
+fn update_mp4_replay_gain(tag: &mut mp4ameta::Tag, scope: &str, value: Value<ReplayGain>) {
+    if value.is_unchanged() {
+        return;
+    }
+
+    let (gain_key, peak_key) = replay_gain_keys(scope);
+    let old: Vec<mp4ameta::DataIdent> = tag
+        .data()
+        .map(|(ident, _)| ident)
+        .filter(|ident| match ident {
+            mp4ameta::DataIdent::Freeform { name, .. } => {
+                name.eq_ignore_ascii_case(&gain_key) || name.eq_ignore_ascii_case(&peak_key)
+            }
+            _ => false,
+        })
+        .cloned()
+        .collect();
+    for ident in old {
+        tag.remove_data_of(&ident);
+    }
+
+    if let Value::Update(g) = value {
+        let ident = mp4ameta::FreeformIdent::new("com.apple.iTunes", &gain_key);
+        tag.set_data(ident, mp4ameta::Data::Utf8(g.gain_str()));
+        if let Some(p) = g.peak_str() {
+            let ident = mp4ameta::FreeformIdent::new("com.apple.iTunes", &peak_key);
+            tag.set_data(ident, mp4ameta::Data::Utf8(p));
+        }
+    }
+}
+
+fn has_ufid(tag: &id3::Tag, owner: &str) -> bool {
+    tag.frames()
+        .filter(|f| f.id() == "UFID")
+        .filter_map(|f| f.content().unknown())
+        .any(|d| d.splitn(2, |b| *b == 0).next() == Some(owner.as_bytes()))
+}
+
+/// Updates the `UFID` frame of an owner, the frames of other owners are kept.
+fn update_id3_ufid(tag: &mut id3::Tag, owner: &str, value: &Value<String>) {
+    if value.is_unchanged() {
+        return;
+    }
+
+    let others: Vec<id3::Frame> = tag
+        .frames()
+        .filter(|f| f.id() == "UFID")
+        .filter(|f| match f.content().unknown() {
+            Some(d) => d.splitn(2, |b| *b == 0).next() != Some(owner.as_bytes()),
+            None => true,
+        })
+        .cloned()
+        .collect();
+    tag.remove("UFID");
+    for f in others {
+        tag.add_frame(f);
+    }
+
+    if let Value::Update(id) = value {
+        let mut data = owner.as_bytes().to_vec();
+        data.push(0);
+        data.extend_from_slice(id.as_bytes());
+        tag.add_frame(id3::Frame::with_content("UFID", id3::Content::Unknown(data)));
+    }
+}
+
+/// Updates a number and total field pair. The number field might contain both values as
+/// `number/total`, in that case the values are split up into their own fields.
+fn update_vorbis_pair(
+    comment: &mut VorbisComment,
+    (number_key, total_key, alt_total_key): (&str, &str, &str),
+    number: Value<u16>,
+    total: Value<u16>,
+) {
+    if number.is_unchanged() && total.is_unchanged() {
+        return;
+    }
+
+    let (old_number, old_total) = comment.value(number_key).map_or((None, None), parse_pair);
+    let old_total = comment
+        .value(total_key)
+        .or_else(|| comment.value(alt_total_key))
+        .and_then(|v| v.trim().parse().ok())
+        .or(old_total);
+
+    let new_number = number.apply(old_number);
+    let new_total = total.apply(old_total);
+
+    match new_number {
+        Some(n) => comment.set_value(number_key, n.to_string()),
+        None => comment.remove(number_key),
+    }
+    comment.remove(alt_total_key);
+    match new_total {
+        Some(n) => comment.set_value(total_key, n.to_string()),
+        None => comment.remove(total_key),
+    }
+}
+
+/// Updates a field that is written as `number/total`.
+fn update_ape_pair(tag: &mut ape::Tag, key: &str, number: Value<u16>, total: Value<u16>) {
+    if number.is_unchanged() && total.is_unchanged() {
+        return;
+    }
+
+    let (old_number, old_total) = tag.value(key).map_or((None, None), parse_pair);
+    let new_number = number.apply(old_number);
+    let new_total = total.apply(old_total);
+
+    match (new_number, new_total) {
+        (Some(n), Some(t)) => tag.set_value(key, format!("{}/{}", n, t)),
+        (Some(n), None) => tag.set_value(key, n.to_string()),
+        (None, Some(t)) => tag.set_value(key, format!("0/{}", t)),
+        (None, None) => tag.remove(key),
+    }
 }
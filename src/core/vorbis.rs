@@ -0,0 +1,133 @@
+use std::convert::TryInto;
+use std::io;
+
+const DEFAULT_VENDOR: &str = "music-organizer";
+
+/// A vorbis comment block as it is used by FLAC and Ogg files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VorbisComment {
+    /// The vendor string, it isn't decoded so it's written back unchanged.
+    pub vendor: Vec<u8>,
+    pub fields: Vec<(String, String)>,
+    /// Entries that aren't valid UTF-8 or have no `=`, they are written back unchanged.
+    pub unparsed: Vec<Vec<u8>>,
+}
+
+impl Default for VorbisComment {
+    fn default() -> Self {
+        Self { vendor: DEFAULT_VENDOR.into(), fields: Vec::new(), unparsed: Vec::new() }
+    }
+}
+
+impl VorbisComment {
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        Self::parse_prefix(data).map(|(c, _)| c)
+    }
+
+    /// Parses a comment block at the start of `data`, which may be followed by other data.
+    /// Returns the comment and its length in bytes.
+    pub fn parse_prefix(data: &[u8]) -> io::Result<(Self, usize)> {
+        let mut reader = Reader { data, pos: 0 };
+
+        let vendor_len = reader.u32()? as usize;
+        let vendor = reader.bytes(vendor_len)?.to_vec();
+
+        let count = reader.u32()?;
+        let mut fields = Vec::new();
+        let mut unparsed = Vec::new();
+        for _ in 0..count {
+            let len = reader.u32()? as usize;
+            let field = reader.bytes(len)?;
+
+            match std::str::from_utf8(field).ok().and_then(|f| f.split_once('=')) {
+                Some((k, v)) => fields.push((k.to_owned(), v.to_owned())),
+                None => unparsed.push(field.to_vec()),
+            }
+        }
+
+        Ok((Self { vendor, fields, unparsed }, reader.pos))
+    }
+
+    /// The length of the encoded comment block in bytes.
+    pub fn byte_len(&self) -> usize {
+        let fields: usize = self.fields.iter().map(|(k, v)| 4 + k.len() + 1 + v.len()).sum();
+        let unparsed: usize = self.unparsed.iter().map(|f| 4 + f.len()).sum();
+        8 + self.vendor.len() + fields + unparsed
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.byte_len());
+
+        data.extend_from_slice(&(self.vendor.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.vendor);
+        let count = self.fields.len() + self.unparsed.len();
+        data.extend_from_slice(&(count as u32).to_le_bytes());
+        for (k, v) in self.fields.iter() {
+            data.extend_from_slice(&((k.len() + 1 + v.len()) as u32).to_le_bytes());
+            data.extend_from_slice(k.as_bytes());
+            data.push(b'=');
+            data.extend_from_slice(v.as_bytes());
+        }
+        for f in self.unparsed.iter() {
+            data.extend_from_slice(&(f.len() as u32).to_le_bytes());
+            data.extend_from_slice(f);
+        }
+
+        data
+    }
+
+    pub fn values<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.fields
+            .iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.value(key).is_some()
+    }
+
+    /// Replaces all values of the key, the new values are inserted where the first old one was.
+    pub fn set_values(&mut self, key: &str, values: impl IntoIterator<Item = String>) {
+        let pos = self.fields.iter().position(|(k, _)| k.eq_ignore_ascii_case(key));
+        self.remove(key);
+
+        let pos = pos.unwrap_or(self.fields.len());
+        let new = values.into_iter().map(|v| (key.to_owned(), v));
+        self.fields.splice(pos..pos, new);
+    }
+
+    pub fn set_value(&mut self, key: &str, value: impl Into<String>) {
+        self.set_values(key, std::iter::once(value.into()));
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.fields.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> io::Result<&[u8]> {
+        match self.data.get(self.pos..self.pos + len) {
+            Some(b) => {
+                self.pos += len;
+                Ok(b)
+            }
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "vorbis comment too short")),
+        }
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes(b.try_into().unwrap()))
+    }
+}